@@ -45,11 +45,22 @@ impl<'a, A> IO<'a, A> {
         }))
     }
 
+    pub fn read_bytes(filename: String) -> IO<'a, Vec<u8>> {
+        IO::Suspend(Box::new(move || match fs::read(filename) {
+            Ok(content) => IO::Return(content),
+            Err(e) => IO::Error(e.to_string()),
+        }))
+    }
+
     pub fn write_file(filename: String, content: String) -> IO<'a, ()> {
+        Self::write_bytes(filename, content.into_bytes())
+    }
+
+    pub fn write_bytes(filename: String, content: Vec<u8>) -> IO<'a, ()> {
         IO::Suspend(Box::new(move || match fs::File::create(filename) {
             Ok(file) => {
                 let mut writer = BufWriter::new(file);
-                match writer.write_all(content.as_bytes()) {
+                match writer.write_all(&content) {
                     Ok(_) => IO::Return(()),
                     Err(e) => IO::Error(e.to_string()),
                 }
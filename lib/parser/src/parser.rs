@@ -284,6 +284,24 @@ pub fn line_comment<'a>() -> impl Parser<'a, ()> {
     )
 }
 
+/// Skips any whitespace, `// ...` and `/* ... */` comments.
+pub fn blank<'a>() -> impl Parser<'a, ()> {
+    zero_or_more(either(
+        whitespace_char().map(|_| ()),
+        either(line_comment(), block_comment()),
+    ))
+    .map(|_| ())
+}
+
+/// Applies `parser` after skipping whitespace and comments.
+pub fn token<'a, P, A>(parser: P) -> impl Parser<'a, A>
+where
+    P: Parser<'a, A> + 'a,
+    A: 'a,
+{
+    right(blank(), parser)
+}
+
 fn comment(input: &str) -> ParseResult<&str> {
     match input.find("//") {
         Some(index) => Ok((&input[0..index], &input[index + 2..])),
@@ -516,6 +534,15 @@ mod tests {
         assert!(line_comment().parse("not a comment").is_err());
     }
 
+    #[test]
+    fn token_parser() {
+        assert_eq!(
+            Ok((" rest", "word".to_string())),
+            token(identifier).parse("  // comment\n /* block */ word rest")
+        );
+        assert_eq!(Ok(("", ())), blank().parse(" \n// comment"));
+    }
+
     #[test]
     fn simple_comment_parser() {
         assert_eq!(
@@ -0,0 +1,271 @@
+use crate::script::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The machine a script drives, e.g. the CPU emulator or the hardware simulator
+pub trait Simulator {
+    // Loads a program or chip. `path` is relative to the script directory, or None for `load` alone.
+    fn load(&mut self, dir: &Path, path: Option<&str>) -> Result<(), String>;
+    fn set(&mut self, name: &str, value: i64) -> Result<(), String>;
+    fn get(&self, name: &str) -> Result<Value, String>;
+    // Runs a simulator specific command, e.g. "ticktock" or "ROM32K load Max.hack"
+    fn action(&mut self, name: &str, args: &[String]) -> Result<(), String>;
+    // Called as a `while` loop starts and ends, e.g. to act as a user who presses a key
+    // the loop waits for
+    fn waiting(&mut self, _waiting: bool) {}
+}
+
+// First line of the output that differs from the compare file. `line` is 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Outcome {
+    pub output_file: Option<PathBuf>,
+    pub compare_file: Option<PathBuf>,
+    pub output: Vec<String>,
+    pub echo: Vec<String>,
+    pub mismatch: Option<Mismatch>,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.mismatch.is_none()
+    }
+
+    // Returns the text of the output file
+    pub fn output_text(&self) -> String {
+        self.output
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+}
+
+// Bound on the iterations of a `while` loop, which may wait for input no batch run provides,
+// e.g. a key press in projects/05/Memory.tst
+pub const MAX_ITERATIONS: u64 = 1_000_000;
+
+struct State {
+    dir: PathBuf,
+    columns: Vec<Column>,
+    expected: Option<Vec<String>>,
+    outcome: Outcome,
+}
+
+// Returns true if a line of output matches a line of the compare file, where '*' matches any character
+pub fn matches(expected: &str, actual: &str) -> bool {
+    let expected = expected.trim_end();
+    let actual = actual.trim_end();
+    expected.chars().count() == actual.chars().count()
+        && expected
+            .chars()
+            .zip(actual.chars())
+            .all(|(e, a)| e == '*' || e == a)
+}
+
+fn write(state: &mut State, line: String) {
+    let index = state.outcome.output.len();
+    if let Some(expected) = &state.expected {
+        let expected_line = expected.get(index).cloned().unwrap_or_default();
+        if state.outcome.mismatch.is_none() && !matches(&expected_line, &line) {
+            state.outcome.mismatch = Some(Mismatch {
+                line: index + 1,
+                expected: expected_line,
+                actual: line.clone(),
+            });
+        }
+    }
+    state.outcome.output.push(line);
+}
+
+fn holds(simulator: &impl Simulator, condition: &Condition) -> Result<bool, String> {
+    let value = match simulator.get(&condition.name)? {
+        Value::Number(number) => number,
+        Value::Text(text) => parse_value(&text)?,
+    };
+    let expected = parse_value(&condition.value)?;
+    match condition.operator.as_str() {
+        "=" => Ok(value == expected),
+        "<>" => Ok(value != expected),
+        "<" => Ok(value < expected),
+        ">" => Ok(value > expected),
+        "<=" => Ok(value <= expected),
+        ">=" => Ok(value >= expected),
+        operator => Err(format!("unknown operator `{}`", operator)),
+    }
+}
+
+fn execute(
+    commands: &[Command],
+    state: &mut State,
+    simulator: &mut impl Simulator,
+) -> Result<(), String> {
+    for command in commands {
+        // Like the Java tools, a script stops at the first comparison failure
+        if state.outcome.mismatch.is_some() {
+            return Ok(());
+        }
+        match command {
+            Command::Load(path) => simulator.load(&state.dir, path.as_deref())?,
+            Command::OutputFile(file) => state.outcome.output_file = Some(state.dir.join(file)),
+            Command::CompareTo(file) => {
+                let path = state.dir.join(file);
+                let content = fs::read_to_string(&path)
+                    .map_err(|error| format!("{}: {}", path.display(), error))?;
+                state.expected = Some(content.lines().map(|line| line.to_string()).collect());
+                state.outcome.compare_file = Some(path);
+            }
+            Command::OutputList(columns) => {
+                state.columns = columns.clone();
+                let header = columns
+                    .iter()
+                    .map(|column| format!("|{}", column.header()))
+                    .collect::<String>();
+                write(state, format!("{}|", header));
+            }
+            Command::Set(name, value) => simulator.set(name, parse_value(value)?)?,
+            Command::Output => {
+                let row = state
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        simulator
+                            .get(&column.name)
+                            .map(|value| format!("|{}", column.cell(&value)))
+                    })
+                    .collect::<Result<String, String>>()?;
+                write(state, format!("{}|", row));
+            }
+            Command::Echo(text) => state.outcome.echo.push(text.clone()),
+            Command::ClearEcho => state.outcome.echo.clear(),
+            Command::Repeat(Some(count), body) => {
+                for _ in 0..*count {
+                    execute(body, state, simulator)?;
+                }
+            }
+            Command::Repeat(None, _) => {
+                return Err("`repeat` without a count never terminates".to_string())
+            }
+            Command::While(condition, body) => {
+                let mut iterations = 0;
+                simulator.waiting(true);
+                while state.outcome.mismatch.is_none() && holds(simulator, condition)? {
+                    if iterations == MAX_ITERATIONS {
+                        return Err(format!(
+                            "`while {} {} {}` did not end after {} iterations",
+                            condition.name, condition.operator, condition.value, MAX_ITERATIONS
+                        ));
+                    }
+                    execute(body, state, simulator)?;
+                    iterations += 1;
+                }
+                simulator.waiting(false);
+            }
+            Command::Action(name, args) => simulator.action(name, args)?,
+        }
+    }
+    Ok(())
+}
+
+// Runs a script whose files are relative to `dir`
+pub fn run(
+    commands: &[Command],
+    dir: &Path,
+    simulator: &mut impl Simulator,
+) -> Result<Outcome, String> {
+    let mut state = State {
+        dir: dir.to_path_buf(),
+        columns: Vec::new(),
+        expected: None,
+        outcome: Outcome::default(),
+    };
+    execute(commands, &mut state, simulator)?;
+
+    // Output that stops short of the compare file is a failure as well
+    if let (None, Some(expected)) = (&state.outcome.mismatch, &state.expected) {
+        let lines = state.outcome.output.len();
+        if lines < expected.len() {
+            state.outcome.mismatch = Some(Mismatch {
+                line: lines + 1,
+                expected: expected[lines].clone(),
+                actual: String::new(),
+            });
+        }
+    }
+    Ok(state.outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A counter that "tick" increments
+    struct Counter {
+        value: i64,
+    }
+
+    impl Simulator for Counter {
+        fn load(&mut self, _: &Path, _: Option<&str>) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn set(&mut self, _: &str, value: i64) -> Result<(), String> {
+            self.value = value;
+            Ok(())
+        }
+
+        fn get(&self, _: &str) -> Result<Value, String> {
+            Ok(Value::Number(self.value))
+        }
+
+        fn action(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+            match (name, args) {
+                ("tick", []) => {
+                    self.value += 1;
+                    Ok(())
+                }
+                _ => Err(format!("unknown command `{}`", name)),
+            }
+        }
+    }
+
+    #[test]
+    fn run_script() {
+        let script =
+            parse_script("output-list n%D1.3.1; set n 5, repeat 2 { tick; } output; while n < 9 { tick; } output;")
+                .unwrap();
+        let outcome = run(&script, Path::new("."), &mut Counter { value: 0 }).unwrap();
+        assert_eq!(vec!["|  n  |", "|   7 |", "|   9 |"], outcome.output);
+        assert!(outcome.passed());
+    }
+
+    #[test]
+    fn bound_while_loops() {
+        let script = parse_script("while n <> 0 { set n 1; }").unwrap();
+        assert_eq!(
+            Err("`while n <> 0` did not end after 1000000 iterations".to_string()),
+            run(&script, Path::new("."), &mut Counter { value: 1 })
+        );
+    }
+
+    #[test]
+    fn compare_with_wildcards() {
+        assert!(matches("|  ***** |", "|  12345 |"));
+        assert!(matches("|   1 |\r", "|   1 |"));
+        assert!(!matches("|   1 |", "|   2 |"));
+    }
+
+    #[test]
+    fn reject_unknown_actions() {
+        let script = parse_script("tock;").unwrap();
+        assert_eq!(
+            Err("unknown command `tock`".to_string()),
+            run(&script, Path::new("."), &mut Counter { value: 0 })
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use parser::parser::*;
+
+// Output format of a column in an output list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Binary,
+    Decimal,
+    Hex,
+    String,
+}
+
+// A column of an output list, e.g. "RAM[0]%D2.6.2": the name, the format,
+// and the number of padding spaces on the left, characters of the value and padding spaces on the right
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub format: Format,
+    pub left: usize,
+    pub width: usize,
+    pub right: usize,
+}
+
+// Value of a column as read from a simulator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Number(i64),
+    Text(String),
+}
+
+// Condition of a while loop, e.g. "out <> 75"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub name: String,
+    pub operator: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Load(Option<String>),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(String, String),
+    Output,
+    Echo(String),
+    ClearEcho,
+    Repeat(Option<u64>, Vec<Command>),
+    While(Condition, Vec<Command>),
+    // A simulator specific command, e.g. "eval", "tick", "tock", "ticktock" or "ROM32K load Max.hack"
+    Action(String, Vec<String>),
+}
+
+// A command as written in a script, before its arguments are checked
+struct Statement {
+    name: String,
+    args: Vec<String>,
+    body: Option<Vec<Statement>>,
+}
+
+fn word(input: &str) -> ParseResult<'_, String> {
+    one_or_more(pred(any_char, |c| {
+        !c.is_whitespace() && !",;{}\"".contains(*c)
+    }))
+    .map(|chars| chars.into_iter().collect())
+    .parse(input)
+}
+
+fn terminator<'a>() -> impl Parser<'a, ()> {
+    token(either(match_literal(","), match_literal(";")))
+}
+
+fn block(input: &str) -> ParseResult<'_, Vec<Statement>> {
+    right(
+        token(match_literal("{")),
+        left(statements, token(match_literal("}"))),
+    )
+    .parse(input)
+}
+
+fn statement(input: &str) -> ParseResult<'_, Statement> {
+    pair(
+        token(word),
+        pair(
+            zero_or_more(token(either(quoted_string(), word))),
+            either(block.map(Some), terminator().map(|_| None)),
+        ),
+    )
+    .map(|(name, (args, body))| Statement { name, args, body })
+    .parse(input)
+}
+
+fn statements(input: &str) -> ParseResult<'_, Vec<Statement>> {
+    zero_or_more(statement).parse(input)
+}
+
+// Parses a value such as "-1", "%D-1", "%B0101" or "%XFF"
+pub fn parse_value(text: &str) -> Result<i64, String> {
+    let (radix, digits) = match text.get(0..2) {
+        Some("%B") => (2, &text[2..]),
+        Some("%X") => (16, &text[2..]),
+        Some("%D") => (10, &text[2..]),
+        _ => (10, text),
+    };
+    i64::from_str_radix(digits, radix).map_err(|_| format!("invalid value `{}`", text))
+}
+
+// Parses an output list column such as "RAM[0]%D2.6.2"
+pub fn parse_column(text: &str) -> Result<Column, String> {
+    let invalid = || format!("invalid output column `{}`", text);
+    let (name, format) = text.split_once('%').unwrap_or((text, "D1.6.1"));
+    let mut chars = format.chars();
+    let format_type = match chars.next() {
+        Some('B') => Format::Binary,
+        Some('D') => Format::Decimal,
+        Some('X') => Format::Hex,
+        Some('S') => Format::String,
+        _ => return Err(invalid()),
+    };
+    let sizes = chars
+        .as_str()
+        .split('.')
+        .map(|size| size.parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| invalid())?;
+    match sizes.as_slice() {
+        &[left, width, right] => Ok(Column {
+            name: name.to_string(),
+            format: format_type,
+            left,
+            width,
+            right,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+// Commands of the script language itself; any other name is simulator specific
+const COMMANDS: [&str; 10] = [
+    "load",
+    "output-file",
+    "compare-to",
+    "output-list",
+    "set",
+    "output",
+    "echo",
+    "clear-echo",
+    "repeat",
+    "while",
+];
+
+fn command(statement: Statement) -> Result<Command, String> {
+    let Statement { name, args, body } = statement;
+    let invalid = || format!("invalid arguments to `{}`: {}", name, args.join(" "));
+    match (name.as_str(), args.as_slice(), body) {
+        ("load", [], None) => Ok(Command::Load(None)),
+        ("load", [file], None) => Ok(Command::Load(Some(file.clone()))),
+        ("output-file", [file], None) => Ok(Command::OutputFile(file.clone())),
+        ("compare-to", [file], None) => Ok(Command::CompareTo(file.clone())),
+        ("output-list", columns, None) => columns
+            .iter()
+            .map(|column| parse_column(column))
+            .collect::<Result<Vec<Column>, String>>()
+            .map(Command::OutputList),
+        ("set", [target, value], None) => Ok(Command::Set(target.clone(), value.clone())),
+        ("output", [], None) => Ok(Command::Output),
+        ("echo", [text], None) => Ok(Command::Echo(text.clone())),
+        ("clear-echo", [], None) => Ok(Command::ClearEcho),
+        ("repeat", [], Some(body)) => commands(body).map(|body| Command::Repeat(None, body)),
+        ("repeat", [count], Some(body)) => {
+            let count = count.parse::<u64>().map_err(|_| invalid())?;
+            commands(body).map(|body| Command::Repeat(Some(count), body))
+        }
+        ("while", [name, operator, value], Some(body)) => commands(body).map(|body| {
+            Command::While(
+                Condition {
+                    name: name.clone(),
+                    operator: operator.clone(),
+                    value: value.clone(),
+                },
+                body,
+            )
+        }),
+        (name, args, None) if !COMMANDS.contains(&name) => {
+            Ok(Command::Action(name.to_string(), args.to_vec()))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn commands(statements: Vec<Statement>) -> Result<Vec<Command>, String> {
+    statements.into_iter().map(command).collect()
+}
+
+// Parses a test script into its commands
+pub fn parse_script(input: &str) -> Result<Vec<Command>, String> {
+    match left(statements, blank()).parse(input) {
+        Ok(("", statements)) => commands(statements),
+        Ok((rest, _)) | Err(rest) => {
+            let line = input[..input.len() - rest.len()].matches('\n').count() + 1;
+            Err(format!("line {}: syntax error", line))
+        }
+    }
+}
+
+impl Column {
+    pub fn size(&self) -> usize {
+        self.left + self.width + self.right
+    }
+
+    // Returns the name centered in the column, truncated if it is too long
+    pub fn header(&self) -> String {
+        let name = self.name.chars().take(self.size()).collect::<String>();
+        let left = (self.size() - name.len()) / 2;
+        format!(
+            "{}{}{}",
+            " ".repeat(left),
+            name,
+            " ".repeat(self.size() - left - name.len())
+        )
+    }
+
+    // Returns the value formatted and padded as specified by the column
+    pub fn cell(&self, value: &Value) -> String {
+        let width = self.width;
+        let mask = |bits: usize| {
+            if bits < 64 {
+                (1i64 << bits) - 1
+            } else {
+                -1
+            }
+        };
+        let text = match (self.format, value) {
+            (Format::Binary, Value::Number(number)) => {
+                format!("{:0width$b}", number & mask(width))
+            }
+            (Format::Hex, Value::Number(number)) => {
+                format!("{:0width$X}", number & mask(4 * width))
+            }
+            (Format::Decimal, Value::Number(number)) => format!("{:>width$}", number),
+            (Format::String, Value::Number(number)) => format!("{:<width$}", number),
+            (_, Value::Text(text)) => format!("{:<width$}", text),
+        };
+        format!(
+            "{}{}{}",
+            " ".repeat(self.left),
+            text,
+            " ".repeat(self.right)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        let script = r#"// Comment
+load Mult.asm,
+output-file Mult.out,
+output-list RAM[0]%D2.6.2 /* block */ time%S1.4.1;
+set RAM[0] -1,
+repeat 20 {
+  ticktock;
+}
+while out <> 75 { tick, tock; }
+ROM32K load Max.hack,
+echo "Hello, world";
+output;"#;
+        assert_eq!(
+            Ok(vec![
+                Command::Load(Some("Mult.asm".to_string())),
+                Command::OutputFile("Mult.out".to_string()),
+                Command::OutputList(vec![
+                    Column {
+                        name: "RAM[0]".to_string(),
+                        format: Format::Decimal,
+                        left: 2,
+                        width: 6,
+                        right: 2
+                    },
+                    Column {
+                        name: "time".to_string(),
+                        format: Format::String,
+                        left: 1,
+                        width: 4,
+                        right: 1
+                    }
+                ]),
+                Command::Set("RAM[0]".to_string(), "-1".to_string()),
+                Command::Repeat(
+                    Some(20),
+                    vec![Command::Action("ticktock".to_string(), vec![])]
+                ),
+                Command::While(
+                    Condition {
+                        name: "out".to_string(),
+                        operator: "<>".to_string(),
+                        value: "75".to_string()
+                    },
+                    vec![
+                        Command::Action("tick".to_string(), vec![]),
+                        Command::Action("tock".to_string(), vec![])
+                    ]
+                ),
+                Command::Action(
+                    "ROM32K".to_string(),
+                    vec!["load".to_string(), "Max.hack".to_string()]
+                ),
+                Command::Echo("Hello, world".to_string()),
+                Command::Output,
+            ]),
+            parse_script(script)
+        );
+    }
+
+    #[test]
+    fn reject_invalid_scripts() {
+        assert_eq!(
+            Err("line 2: syntax error".to_string()),
+            parse_script("load Mult.asm,\nrepeat 10 { ticktock; ")
+        );
+        assert_eq!(
+            Err("invalid arguments to `set`: RAM[0]".to_string()),
+            parse_script("set RAM[0];")
+        );
+    }
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(Ok(-1), parse_value("-1"));
+        assert_eq!(Ok(-1), parse_value("%D-1"));
+        assert_eq!(Ok(5), parse_value("%B0101"));
+        assert_eq!(Ok(255), parse_value("%XFF"));
+        assert!(parse_value("x").is_err());
+    }
+
+    #[test]
+    fn format_columns() {
+        let column = |text| parse_column(text).unwrap();
+        assert_eq!("  RAM[0]  ", column("RAM[0]%D2.6.2").header());
+        assert_eq!(
+            "     -1  ",
+            column("RAM[0]%D1.6.2").cell(&Value::Number(-1))
+        );
+        assert_eq!(" in  ", column("in%B2.1.2").header());
+        assert_eq!("  1  ", column("in%B2.1.2").cell(&Value::Number(1)));
+        assert_eq!(
+            " 1111111111111111 ",
+            column("out%B1.16.1").cell(&Value::Number(-1))
+        );
+        assert_eq!("addre", column("addressM%D0.5.0").header());
+        assert_eq!(
+            " 0+   ",
+            column("time%S1.4.1").cell(&Value::Text("0+".to_string()))
+        );
+        assert_eq!("00FF", column("x%X0.4.0").cell(&Value::Number(255)));
+    }
+}
@@ -0,0 +1,74 @@
+use asm::assembler::translate_into;
+use asm::macros::expand;
+use std::time::Instant;
+
+// Lines of the generated program
+const LINES: usize = 100_000;
+// Stack of the thread assembling it, far below the default of the main thread
+const STACK_SIZE: usize = 256 * 1024;
+
+// Generates a program of about `lines` lines, with a third of them instructions so that
+// it fits in ROM: blocks of a label, comments, a variable update and a conditional jump
+fn program(lines: usize) -> Vec<String> {
+    let mut program = Vec::with_capacity(lines);
+    let mut block = 0;
+    while program.len() < lines {
+        let variable = format!("var{}", block % 1000);
+        program.extend([
+            format!("// Block {}: counts in {} up to {}", block, variable, block),
+            "// and loops until it reaches the bound.".to_string(),
+            String::new(),
+            format!("(BLOCK{})", block),
+            "    // Increments the counter".to_string(),
+            format!("    @{}", variable),
+            "    MD=M+1".to_string(),
+            String::new(),
+            "    // Compares it with the bound".to_string(),
+            format!("    @{}", block),
+            "    D=D-A".to_string(),
+            String::new(),
+            "    // Loops while it is below".to_string(),
+            format!("    @BLOCK{}", block),
+            "    D;JLT".to_string(),
+            String::new(),
+            String::new(),
+            String::new(),
+            "// ----------------------------------------".to_string(),
+            String::new(),
+        ]);
+        block += 1;
+    }
+    program
+}
+
+fn main() {
+    let source = program(LINES);
+    let (words, elapsed) = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let lines = source.iter().map(String::as_str).collect::<Vec<&str>>();
+            let start = Instant::now();
+            let read = |path: &str| Err(format!("cannot read `{}`", path));
+            let expansion = expand("Big.asm", &lines, &read).unwrap();
+            let mut words = 0;
+            translate_into("Big.asm", &expansion.lines(), false, &mut |_| words += 1).unwrap();
+            (words, start.elapsed())
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    let seconds = elapsed.as_secs_f64();
+    println!(
+        "assembled {} lines into {} words in {:.3} s with a {} KiB stack",
+        LINES,
+        words,
+        seconds,
+        STACK_SIZE / 1024
+    );
+    println!(
+        "throughput: {:.0} lines/s, {:.0} words/s",
+        LINES as f64 / seconds,
+        words as f64 / seconds
+    );
+}
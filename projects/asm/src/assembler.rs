@@ -0,0 +1,534 @@
+use crate::error::*;
+use crate::expression::{self, evaluate, expression};
+use crate::instruction::*;
+use crate::translation::*;
+use collections::deque::*;
+use collections::hashmap::*;
+use parser::parser::*;
+
+// The largest address an A-instruction can hold
+pub const MAX_ADDRESS: u32 = 32767;
+
+// Returns the part of a line before its comment, if any
+fn strip_comment(line: &str) -> &str {
+    line.split("//").next().unwrap_or("")
+}
+
+// A constant defined by ".equ", resolved once all labels are known
+struct Constant<'a> {
+    name: String,
+    value: String,
+    line: usize,
+    source: &'a str,
+}
+
+// The constants of a program, indexed by name
+struct Constants<'a> {
+    list: Vec<Constant<'a>>,
+    index: HashMap<String, usize>,
+}
+
+impl<'a> Constants<'a> {
+    fn get(&self, name: &str) -> Option<&Constant<'a>> {
+        self.index
+            .get(&name.to_string())
+            .map(|&index| &self.list[index])
+    }
+}
+
+// Returns the value of a symbol, resolving the constants it depends on first.
+// Constants are resolved in order of definition, so only forward references recurse.
+fn resolve(
+    name: &str,
+    constants: &Constants,
+    symbol_table: &mut HashMap<String, u32>,
+    resolving: &mut Vec<String>,
+) -> Result<i64, ErrorKind> {
+    if let Some(&value) = symbol_table.get(&name.to_string()) {
+        return Ok(value as i64);
+    }
+    let constant = constants
+        .get(name)
+        .ok_or_else(|| ErrorKind::UndefinedSymbol(name.to_string()))?;
+    if resolving.iter().any(|symbol| symbol == name) {
+        return Err(ErrorKind::CircularDefinition(name.to_string()));
+    }
+
+    resolving.push(name.to_string());
+    let value = match expression().parse(&constant.value) {
+        Ok((_, expression)) => evaluate(&expression, &mut |symbol| {
+            resolve(symbol, constants, symbol_table, resolving)
+        }),
+        Err(_) => Err(ErrorKind::InvalidInstruction),
+    };
+    resolving.pop();
+
+    match value? {
+        value if (0..=MAX_ADDRESS as i64).contains(&value) => {
+            *symbol_table = symbol_table.insert(name.to_string(), value as u32);
+            Ok(value)
+        }
+        value => Err(ErrorKind::AddressOutOfRange(value.to_string())),
+    }
+}
+
+// The tables of the C-instruction fields
+pub struct Fields {
+    dest: HashMap<&'static str, &'static str>,
+    comp: HashMap<&'static str, &'static str>,
+    jump: HashMap<&'static str, &'static str>,
+}
+
+impl Fields {
+    // In extended mode, the mnemonics of the undocumented comp bit patterns are accepted
+    pub fn new(extended: bool) -> Self {
+        Fields {
+            dest: dest_table(),
+            comp: comp_table(extended),
+            jump: jump_table(),
+        }
+    }
+
+    // Returns the binary code of a C-instruction, or the text and error of each unknown field.
+    // Other orders of commutative operands and dest registers are accepted.
+    pub fn encode(
+        &self,
+        dest: &Option<String>,
+        comp: &str,
+        jump: &Option<String>,
+    ) -> Result<u16, Vec<(String, ErrorKind)>> {
+        let binary = (
+            dest.as_deref().map_or(Some("000"), |d| {
+                ordered_dest(d).and_then(|d| self.dest.get(&d.as_str()).copied())
+            }),
+            self.comp
+                .get(&comp)
+                .copied()
+                .or_else(|| commuted(comp).and_then(|c| self.comp.get(&c.as_str()).copied())),
+            jump.as_deref()
+                .map_or(Some("000"), |j| self.jump.get(&j).copied()),
+        );
+
+        match binary {
+            (Some(dest_bin), Some(comp_bin), Some(jump_bin)) => {
+                let binary = format!("111{}{}{}", comp_bin, dest_bin, jump_bin);
+                Ok(u16::from_str_radix(&binary, 2).unwrap())
+            }
+            (dest_bin, comp_bin, jump_bin) => {
+                let mut errors = Vec::new();
+                if let (None, Some(dest)) = (dest_bin, dest) {
+                    errors.push((dest.clone(), ErrorKind::UnknownDest(dest.clone())));
+                }
+                if comp_bin.is_none() {
+                    errors.push((comp.to_string(), ErrorKind::UnknownComp(comp.to_string())));
+                }
+                if let (None, Some(jump)) = (jump_bin, jump) {
+                    errors.push((jump.clone(), ErrorKind::UnknownJump(jump.clone())));
+                }
+                Err(errors)
+            }
+        }
+    }
+}
+
+// Collects the labels and constants. Constants may refer to labels defined after them
+// and to each other, in any order.
+pub fn preprocess(file: &str, lines: &[&str]) -> (HashMap<String, u32>, Vec<AssembleError>) {
+    preprocess_at(file, lines, 0)
+}
+
+// Collects the labels and constants of code placed at the ROM address `origin`
+pub fn preprocess_at(
+    file: &str,
+    lines: &[&str],
+    origin: u32,
+) -> (HashMap<String, u32>, Vec<AssembleError>) {
+    let instruction = instruction();
+    let mut symbol_table = symbol_table();
+    let mut constants = Constants {
+        list: Vec::new(),
+        index: HashMap::new(),
+    };
+    let mut errors = Vec::new();
+
+    let mut address = 0;
+    for (index, &line) in lines.iter().enumerate() {
+        let error = |text: &str, kind| AssembleError::new(file, index + 1, line, text, kind);
+        match instruction.parse(line) {
+            Ok(("", Instruction::L(symbol))) => {
+                if constants.get(&symbol).is_some() {
+                    errors.push(error(&symbol, ErrorKind::DuplicateSymbol(symbol.clone())));
+                } else if symbol_table.get(&symbol).is_some() {
+                    errors.push(error(&symbol, ErrorKind::DuplicateLabel(symbol.clone())));
+                } else {
+                    symbol_table = symbol_table.insert(symbol, origin + address);
+                }
+            }
+            Ok(("", Instruction::Equ(name, value))) => {
+                if symbol_table.get(&name).is_some() || constants.get(&name).is_some() {
+                    errors.push(error(&name, ErrorKind::DuplicateSymbol(name.clone())));
+                } else {
+                    constants.index = constants.index.insert(name.clone(), constants.list.len());
+                    constants.list.push(Constant {
+                        name,
+                        value,
+                        line: index + 1,
+                        source: line,
+                    });
+                }
+            }
+            // Exports and imports only matter to object files
+            Ok(("", Instruction::Export(_))) | Ok(("", Instruction::Import(_))) => (),
+            Ok(("", Instruction::A(_))) | Ok(("", Instruction::C(_, _, _))) => {
+                // Reported once, at the first instruction beyond the ROM
+                if address == MAX_ADDRESS + 1 {
+                    errors.push(error("", ErrorKind::AddressOutOfRange(address.to_string())));
+                }
+                address += 1;
+            }
+            _ if strip_comment(line).trim().is_empty() => (),
+            _ => errors.push(error("", ErrorKind::InvalidInstruction)),
+        }
+    }
+
+    for constant in &constants.list {
+        if let Err(kind) = resolve(
+            &constant.name,
+            &constants,
+            &mut symbol_table,
+            &mut Vec::new(),
+        ) {
+            let text = match &kind {
+                ErrorKind::UndefinedSymbol(symbol) | ErrorKind::CircularDefinition(symbol) => {
+                    symbol.clone()
+                }
+                _ => constant.value.clone(),
+            };
+            errors.push(AssembleError::new(
+                file,
+                constant.line,
+                constant.source,
+                &text,
+                kind,
+            ));
+        }
+    }
+
+    (symbol_table, errors)
+}
+
+// Returns the symbols assemble allocates as variables, in order of allocation, with the
+// index of the line where each is first used: those of A-instructions that are neither
+// predefined nor labels nor constants
+pub fn variables(lines: &[&str]) -> Vec<(String, usize)> {
+    let instruction = instruction();
+    let instructions = lines
+        .iter()
+        .map(|line| {
+            instruction
+                .parse(line)
+                .ok()
+                .map(|(_, instruction)| instruction)
+        })
+        .collect::<Vec<Option<Instruction>>>();
+    let defined =
+        instructions
+            .iter()
+            .fold(symbol_table(), |defined, instruction| match instruction {
+                Some(Instruction::L(symbol)) | Some(Instruction::Equ(symbol, _)) => {
+                    defined.insert(symbol.clone(), 0)
+                }
+                _ => defined,
+            });
+
+    let mut variables: Vec<(String, usize)> = Vec::new();
+    let mut allocated: HashSet<String> = HashSet::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        let Some(Instruction::A(text)) = instruction else {
+            continue;
+        };
+        let Ok((_, expression)) = expression().parse(text) else {
+            continue;
+        };
+        for symbol in expression.symbols() {
+            let symbol = symbol.to_string();
+            if defined.get(&symbol).is_none() && allocated.get(&symbol).is_none() {
+                allocated = allocated.insert(symbol.clone(), ());
+                variables.push((symbol, index));
+            }
+        }
+    }
+    variables
+}
+
+// Assembles the lines collected by preprocess, passing each word of machine code to `emit`
+// as soon as it is known, so that memory use does not grow with the code. Variables are
+// allocated from address 16 in order of first use. Returns the symbol table with them.
+pub fn assemble_into(
+    file: &str,
+    lines: &[&str],
+    symbol_table: HashMap<String, u32>,
+    extended: bool,
+    emit: &mut dyn FnMut(u16),
+) -> Result<HashMap<String, u32>, Vec<AssembleError>> {
+    let instruction = instruction();
+    let fields = Fields::new(extended);
+    let mut symbol_table = symbol_table;
+    let mut available_address = 16;
+    let mut errors = Vec::new();
+
+    for (index, &line) in lines.iter().enumerate() {
+        let error = |text: &str, kind| AssembleError::new(file, index + 1, line, text, kind);
+        match instruction.parse(line) {
+            Ok(("", Instruction::A(symbol))) => {
+                // Symbols that are neither labels nor constants are variables,
+                // in an expression as well
+                let mut value_of = |name: &str| {
+                    let name = name.to_string();
+                    match symbol_table.get(&name) {
+                        Some(&value) => value as i64,
+                        None => {
+                            symbol_table = symbol_table.insert(name, available_address);
+                            available_address += 1;
+                            available_address as i64 - 1
+                        }
+                    }
+                };
+                let value = if symbol.chars().all(|c| c.is_ascii_digit()) {
+                    symbol
+                        .parse::<i64>()
+                        .map_err(|_| ErrorKind::AddressOutOfRange(symbol.clone()))
+                } else if matches!(expression::symbol(&symbol), Ok(("", _))) {
+                    Ok(value_of(&symbol))
+                } else {
+                    match expression().parse(&symbol) {
+                        Ok((_, expression)) => {
+                            evaluate(&expression, &mut |name| Ok(value_of(name)))
+                        }
+                        Err(_) => Err(ErrorKind::InvalidInstruction),
+                    }
+                };
+                match value {
+                    Ok(value) if (0..=MAX_ADDRESS as i64).contains(&value) => emit(value as u16),
+                    Ok(value) => errors.push(error(
+                        &symbol,
+                        ErrorKind::AddressOutOfRange(value.to_string()),
+                    )),
+                    Err(kind) => errors.push(error(&symbol, kind)),
+                }
+            }
+            Ok(("", Instruction::C(dest, comp, jump))) => {
+                match fields.encode(&dest, &comp, &jump) {
+                    Ok(word) => emit(word),
+                    Err(field_errors) => errors.extend(
+                        field_errors
+                            .into_iter()
+                            .map(|(text, kind)| error(&text, kind)),
+                    ),
+                }
+            }
+            Ok(("", Instruction::Import(name))) => {
+                errors.push(error(&name, ErrorKind::ImportedSymbol(name.clone())))
+            }
+            // Blank lines and comments are skipped, and invalid lines have been reported by preprocess
+            _ => (),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(symbol_table)
+    } else {
+        Err(errors)
+    }
+}
+
+// Assembles into a deque of binary words, see assemble_into
+pub fn assemble<D: Deque<String>>(
+    file: &str,
+    lines: &[&str],
+    symbol_table: HashMap<String, u32>,
+    extended: bool,
+    code: D,
+) -> Result<(HashMap<String, u32>, D), Vec<AssembleError>> {
+    let mut code = code;
+    let mut emit = |word: u16| code = code.push_back(format!("{:016b}", word));
+    let symbol_table = assemble_into(file, lines, symbol_table, extended, &mut emit)?;
+    Ok((symbol_table, code))
+}
+
+// Runs both passes and collects the errors of both, ordered by line.
+// In extended mode, the mnemonics of the undocumented comp bit patterns are accepted.
+// Returns the symbol table, with the variables allocated by assemble added to the labels
+// and constants computed by preprocess. The code is passed to `emit` word by word.
+pub fn translate_into(
+    file: &str,
+    lines: &[&str],
+    extended: bool,
+    emit: &mut dyn FnMut(u16),
+) -> Result<HashMap<String, u32>, Vec<AssembleError>> {
+    let (symbol_table, errors) = preprocess(file, lines);
+    match assemble_into(file, lines, symbol_table, extended, emit) {
+        Ok(symbol_table) if errors.is_empty() => Ok(symbol_table),
+        Ok(_) => Err(errors),
+        Err(assemble_errors) => {
+            let mut errors = errors
+                .into_iter()
+                .chain(assemble_errors)
+                .collect::<Vec<_>>();
+            errors.sort_by_key(|error| (error.line, error.column));
+            Err(errors)
+        }
+    }
+}
+
+// Translates into a deque of binary words, see translate_into
+pub fn translate<D: Deque<String>>(
+    file: &str,
+    lines: &[&str],
+    extended: bool,
+    code: D,
+) -> Result<(HashMap<String, u32>, D), Vec<AssembleError>> {
+    let mut code = code;
+    let mut emit = |word: u16| code = code.push_back(format!("{:016b}", word));
+    let symbol_table = translate_into(file, lines, extended, &mut emit)?;
+    Ok((symbol_table, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collections::Empty;
+
+    #[test]
+    fn assemble_program() {
+        let lines = ["@2", "D=A", "(END)", "@END", "0;JMP"];
+        let (_, code) = translate("Test.asm", &lines, false, BankersDeque::empty()).unwrap();
+        assert_eq!(
+            vec![
+                "0000000000000010",
+                "1110110000010000",
+                "0000000000000010",
+                "1110101010000111"
+            ],
+            code.iter()
+                .map(|s| s.as_ref().clone())
+                .collect::<Vec<String>>()
+        );
+    }
+
+    #[test]
+    fn assemble_alternate_spellings() {
+        let lines = ["M=-M", "MA=A+D", "AMD=1+M", "D=M|D", "D=D-A-1"];
+        let errors = translate("Test.asm", &lines, false, BankersDeque::<String>::empty())
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![(5, ErrorKind::UnknownComp("D-A-1".to_string()))],
+            errors
+                .into_iter()
+                .map(|error| (error.line, error.kind))
+                .collect::<Vec<_>>()
+        );
+        let (_, code) = translate("Test.asm", &lines, true, BankersDeque::empty()).unwrap();
+        assert_eq!(
+            vec![
+                "1111110011001000",
+                "1110000010101000",
+                "1111110111111000",
+                "1111010101010000",
+                "1110000110010000"
+            ],
+            code.iter()
+                .map(|s| s.as_ref().clone())
+                .collect::<Vec<String>>()
+        );
+    }
+
+    #[test]
+    fn assemble_expressions() {
+        let lines = [
+            ".equ ROW 32",
+            ".equ LAST SCREEN + ROW*(HEIGHT - 1)",
+            ".equ HEIGHT 256",
+            "(START)",
+            "@LAST",
+            "@END - START",
+            "@buffer + 2",
+            "@(1 << 4) | 3 & 1",
+            "(END)",
+        ];
+        let (_, code) = translate("Test.asm", &lines, false, BankersDeque::empty()).unwrap();
+        assert_eq!(
+            vec![16384 + 32 * 255, 4, 18, 17],
+            code.iter()
+                .map(|s| u16::from_str_radix(s.as_ref(), 2).unwrap())
+                .collect::<Vec<u16>>()
+        );
+    }
+
+    #[test]
+    fn find_variables() {
+        let lines = [
+            "@i", "(LOOP)", "@j + i", "@LOOP", "@SCREEN", "@END", "(END)",
+        ];
+        assert_eq!(
+            vec![("i".to_string(), 0), ("j".to_string(), 2)],
+            variables(&lines)
+        );
+    }
+
+    #[test]
+    fn report_expression_errors() {
+        let lines = [
+            ".equ A B",
+            ".equ B A + 1",
+            ".equ C x",
+            ".equ SCREEN 1",
+            "(C)",
+            "@SCREEN * 2",
+            "@1 - 2",
+            "@4 / (2 - 2)",
+        ];
+        let errors = translate("Test.asm", &lines, false, BankersDeque::<String>::empty())
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![
+                (1, ErrorKind::CircularDefinition("A".to_string())),
+                (2, ErrorKind::CircularDefinition("B".to_string())),
+                (3, ErrorKind::UndefinedSymbol("x".to_string())),
+                (4, ErrorKind::DuplicateSymbol("SCREEN".to_string())),
+                (5, ErrorKind::DuplicateSymbol("C".to_string())),
+                (6, ErrorKind::AddressOutOfRange("32768".to_string())),
+                (7, ErrorKind::AddressOutOfRange("-1".to_string())),
+                (8, ErrorKind::InvalidExpression("4 / 0".to_string())),
+            ],
+            errors
+                .into_iter()
+                .map(|error| (error.line, error.kind))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn collect_all_errors() {
+        let lines = ["(LOOP)", "D=D+X", "(LOOP)", "XY=D;JXX", "@40000", "%"];
+        let errors = translate("Test.asm", &lines, false, BankersDeque::<String>::empty())
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![
+                (2, ErrorKind::UnknownComp("D+X".to_string())),
+                (3, ErrorKind::DuplicateLabel("LOOP".to_string())),
+                (4, ErrorKind::UnknownDest("XY".to_string())),
+                (4, ErrorKind::UnknownJump("JXX".to_string())),
+                (5, ErrorKind::AddressOutOfRange("40000".to_string())),
+                (6, ErrorKind::InvalidInstruction),
+            ],
+            errors
+                .into_iter()
+                .map(|error| (error.line, error.kind))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use crate::error::*;
+use crate::instruction::*;
+use crate::translation::*;
+use collections::hashmap::{HashMap, HashSet};
+use collections::Empty;
+
+// Decodes a line of 16 binary digits into an A- or C-instruction
+fn decode(
+    bits: &str,
+    comp_table: &HashMap<&'static str, &'static str>,
+    dest_table: &HashMap<&'static str, &'static str>,
+    jump_table: &HashMap<&'static str, &'static str>,
+) -> Result<Instruction, ErrorKind> {
+    if bits.len() != 16 || !bits.chars().all(|c| c == '0' || c == '1') {
+        return Err(ErrorKind::InvalidMachineCode(bits.to_string()));
+    }
+
+    match &bits[0..3] {
+        "111" => {
+            let (comp, dest, jump) = (&bits[3..10], &bits[10..13], &bits[13..16]);
+            let mnemonic = |table: &HashMap<&str, &'static str>, bits: &str| {
+                table.get(&bits).map(|mnemonic| mnemonic.to_string())
+            };
+            // Only exact encodings, so that the mnemonic assembles back to the same bits
+            match mnemonic(comp_table, comp) {
+                Some(comp) => Ok(Instruction::C(
+                    mnemonic(dest_table, dest),
+                    comp,
+                    mnemonic(jump_table, jump),
+                )),
+                None => Err(ErrorKind::UnknownComp(comp.to_string())),
+            }
+        }
+        prefix if prefix.starts_with('0') => Ok(Instruction::A(
+            u16::from_str_radix(bits, 2).unwrap().to_string(),
+        )),
+        _ => Err(ErrorKind::InvalidMachineCode(bits.to_string())),
+    }
+}
+
+// Returns the ROM addresses that are loaded into A right before a jump
+fn jump_targets(instructions: &[Instruction]) -> HashSet<u32> {
+    instructions
+        .windows(2)
+        .fold(HashSet::empty(), |targets, window| match window {
+            [Instruction::A(value), Instruction::C(_, _, Some(_))] => match value.parse::<u32>() {
+                Ok(address) if address as usize <= instructions.len() => {
+                    targets.insert(address, ())
+                }
+                _ => targets,
+            },
+            _ => targets,
+        })
+}
+
+fn label(address: u32) -> String {
+    format!("L_{address}")
+}
+
+// Turns the lines of a .hack file back into assembly.
+// If `labels` is set, addresses used as jump targets are replaced with synthesized (L_nnn) labels.
+// In extended mode, the comp bit patterns of EXTENDED_COMP are decoded too.
+pub fn disassemble(
+    file: &str,
+    lines: &[&str],
+    labels: bool,
+    extended: bool,
+) -> Result<Vec<String>, Vec<AssembleError>> {
+    let comp_table = comp_mnemonic_table(extended);
+    let dest_table = dest_mnemonic_table();
+    let jump_table = jump_mnemonic_table();
+
+    let (instructions, errors) = lines.iter().enumerate().fold(
+        (Vec::new(), Vec::new()),
+        |(mut instructions, mut errors), (index, &line)| {
+            let bits = line.trim();
+            if !bits.is_empty() {
+                match decode(bits, &comp_table, &dest_table, &jump_table) {
+                    Ok(instruction) => instructions.push(instruction),
+                    Err(kind) => errors.push(AssembleError::new(file, index + 1, line, bits, kind)),
+                }
+            }
+            (instructions, errors)
+        },
+    );
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let targets = if labels {
+        jump_targets(&instructions)
+    } else {
+        HashSet::empty()
+    };
+    let is_target = |address: u32| targets.get(&address).is_some();
+
+    let assembly = instructions
+        .iter()
+        .zip(
+            instructions
+                .iter()
+                .skip(1)
+                .map(Some)
+                .chain(std::iter::once(None)),
+        )
+        .enumerate()
+        .fold(
+            Vec::new(),
+            |mut assembly, (address, (instruction, next))| {
+                let address = address as u32;
+                if is_target(address) {
+                    assembly.push(Instruction::L(label(address)).to_string());
+                }
+                match (instruction, next) {
+                    (Instruction::A(value), Some(Instruction::C(_, _, Some(_))))
+                        if value.parse().is_ok_and(is_target) =>
+                    {
+                        assembly.push(Instruction::A(label(value.parse().unwrap())).to_string())
+                    }
+                    _ => assembly.push(instruction.to_string()),
+                }
+                assembly
+            },
+        );
+
+    let end = instructions.len() as u32;
+    Ok(if is_target(end) {
+        assembly
+            .into_iter()
+            .chain(std::iter::once(Instruction::L(label(end)).to_string()))
+            .collect()
+    } else {
+        assembly
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::translate;
+    use collections::deque::*;
+
+    #[test]
+    fn disassemble_instructions() {
+        let lines = [
+            "0000000000000010",
+            "1110110000010000",
+            "1111110111001000",
+            "1110001100000001",
+            "0000000000000000",
+            "1110101010000111",
+        ];
+        assert_eq!(
+            Ok(vec![
+                "@2".to_string(),
+                "D=A".to_string(),
+                "M=M+1".to_string(),
+                "D;JGT".to_string(),
+                "@0".to_string(),
+                "0;JMP".to_string(),
+            ]),
+            disassemble("Test.hack", &lines, false, false)
+        );
+    }
+
+    #[test]
+    fn synthesize_labels() {
+        let lines = [
+            "0000000000000001",
+            "1110110000010000",
+            "0000000000000001",
+            "1110101010000111",
+        ];
+        assert_eq!(
+            Ok(vec![
+                "@1".to_string(),
+                "(L_1)".to_string(),
+                "D=A".to_string(),
+                "@L_1".to_string(),
+                "0;JMP".to_string(),
+            ]),
+            disassemble("Test.hack", &lines, true, false)
+        );
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        let lines = [
+            "0000000000000010",
+            "1110000001000000",
+            "101",
+            "1010101010000111",
+        ];
+        assert_eq!(
+            vec![
+                ErrorKind::UnknownComp("0000001".to_string()),
+                ErrorKind::InvalidMachineCode("101".to_string()),
+                ErrorKind::InvalidMachineCode("1010101010000111".to_string()),
+            ],
+            disassemble("Test.hack", &lines, false, false)
+                .unwrap_err()
+                .into_iter()
+                .map(|error| error.kind)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn disassemble_extended_comps() {
+        let lines = ["1110000110010000", "1110001010001000", "1111101010001000"];
+        assert_eq!(
+            vec![
+                ErrorKind::UnknownComp("0001010".to_string()),
+                ErrorKind::UnknownComp("1101010".to_string()),
+            ],
+            disassemble("Test.hack", &lines, false, true)
+                .unwrap_err()
+                .into_iter()
+                .map(|error| error.kind)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Ok(vec!["D=D-A-1".to_string()]),
+            disassemble("Test.hack", &lines[..1], false, true)
+        );
+    }
+
+    #[test]
+    fn reassemble_extended_comps() {
+        // Every comp bit pattern, with the M dest and no jump
+        let lines = (0..128)
+            .map(|comp| format!("111{:07b}001000", comp))
+            .collect::<Vec<String>>();
+        let lines = lines.iter().map(String::as_str).collect::<Vec<&str>>();
+        let decoded = lines
+            .iter()
+            .filter_map(|&line| {
+                disassemble("Test.hack", &[line], false, true)
+                    .ok()
+                    .map(|assembly| (line, assembly))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(COMP.len() + EXTENDED_COMP.len(), decoded.len());
+        for (line, assembly) in decoded {
+            let assembly = assembly.iter().map(String::as_str).collect::<Vec<&str>>();
+            let (_, code) = translate("Test.asm", &assembly, true, BankersDeque::empty()).unwrap();
+            assert_eq!(
+                vec![line.to_string()],
+                code.iter()
+                    .map(|word| word.to_string())
+                    .collect::<Vec<String>>(),
+                "{:?}",
+                assembly
+            );
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::assembler::MAX_ADDRESS;
+use std::fmt;
+
+// Reason why a line could not be assembled
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    InvalidInstruction,
+    UnknownComp(String),
+    UnknownDest(String),
+    UnknownJump(String),
+    DuplicateLabel(String),
+    DuplicateSymbol(String),
+    UndefinedSymbol(String),
+    CircularDefinition(String),
+    InvalidExpression(String),
+    ImportedSymbol(String),
+    NotRelocatable(String),
+    AddressOutOfRange(String),
+    InvalidMachineCode(String),
+    UnknownMacro(String),
+    DuplicateMacro(String),
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    NestedMacro(String),
+    UnterminatedMacro(String),
+    UnexpectedEndMacro,
+    RecursiveMacro(String),
+    RecursiveInclude(String),
+    Include {
+        path: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::InvalidInstruction => write!(f, "invalid instruction"),
+            ErrorKind::UnknownComp(comp) => write!(f, "unknown comp mnemonic `{comp}`"),
+            ErrorKind::UnknownDest(dest) => write!(f, "unknown dest `{dest}`"),
+            ErrorKind::UnknownJump(jump) => write!(f, "unknown jump `{jump}`"),
+            ErrorKind::DuplicateLabel(label) => write!(f, "duplicate label `{label}`"),
+            ErrorKind::DuplicateSymbol(symbol) => write!(f, "symbol `{symbol}` is already defined"),
+            ErrorKind::UndefinedSymbol(symbol) => write!(f, "undefined symbol `{symbol}`"),
+            ErrorKind::CircularDefinition(symbol) => {
+                write!(f, "constant `{symbol}` is defined in terms of itself")
+            }
+            ErrorKind::InvalidExpression(operation) => {
+                write!(f, "cannot evaluate `{operation}`")
+            }
+            ErrorKind::ImportedSymbol(symbol) => write!(
+                f,
+                "`{symbol}` is imported; assemble the module with -c and link it"
+            ),
+            ErrorKind::NotRelocatable(text) => write!(
+                f,
+                "`{text}` cannot be relocated: it must be a symbol plus or minus a constant"
+            ),
+            ErrorKind::AddressOutOfRange(address) => {
+                write!(f, "address `{address}` out of range (0..={MAX_ADDRESS})")
+            }
+            ErrorKind::InvalidMachineCode(bits) => {
+                write!(f, "invalid machine instruction `{bits}`")
+            }
+            ErrorKind::UnknownMacro(name) => write!(f, "unknown macro `{name}`"),
+            ErrorKind::DuplicateMacro(name) => write!(f, "duplicate macro `{name}`"),
+            ErrorKind::ArgumentCount {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "macro `{name}` takes {expected} argument{}, found {actual}",
+                if *expected == 1 { "" } else { "s" }
+            ),
+            ErrorKind::NestedMacro(name) => {
+                write!(f, "macro `{name}` is defined inside another macro")
+            }
+            ErrorKind::UnterminatedMacro(name) => write!(f, "macro `{name}` has no `.endm`"),
+            ErrorKind::UnexpectedEndMacro => write!(f, "`.endm` outside a macro"),
+            ErrorKind::RecursiveMacro(name) => write!(f, "macro `{name}` expands itself"),
+            ErrorKind::RecursiveInclude(path) => write!(f, "`{path}` includes itself"),
+            ErrorKind::Include { path, message } => {
+                write!(f, "cannot include `{path}`: {message}")
+            }
+        }
+    }
+}
+
+// An error located in a source file. `line` and `column` are 1-based.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub source: String,
+    pub kind: ErrorKind,
+}
+
+// Returns the 1-based column of the first occurrence of `text` in `source`,
+// or of the first non-blank character if `text` is not found
+pub fn column(source: &str, text: &str) -> usize {
+    let indent = source.len() - source.trim_start().len();
+    let column = if text.is_empty() {
+        indent
+    } else {
+        source.find(text).unwrap_or(indent)
+    };
+    column + 1
+}
+
+// Writes a diagnostic such as an error or a warning, with the source line and a caret
+// under the column
+pub fn write_diagnostic(
+    f: &mut fmt::Formatter<'_>,
+    file: &str,
+    line: usize,
+    column: usize,
+    severity: &str,
+    message: &dyn fmt::Display,
+    source: &str,
+) -> fmt::Result {
+    let gutter = line.to_string().len();
+    writeln!(f, "{}:{}:{}: {}: {}", file, line, column, severity, message)?;
+    writeln!(f, "{:>gutter$} |", "")?;
+    writeln!(f, "{} | {}", line, source)?;
+    write!(f, "{:>gutter$} | {:>column$}", "", "^", column = column)
+}
+
+impl AssembleError {
+    // Creates an error pointing at the first occurrence of `text` in `source`,
+    // or at the first non-blank character if `text` is not found
+    pub fn new(file: &str, line: usize, source: &str, text: &str, kind: ErrorKind) -> Self {
+        AssembleError {
+            file: file.to_string(),
+            line,
+            column: column(source, text),
+            source: source.to_string(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(
+            f,
+            &self.file,
+            self.line,
+            self.column,
+            "error",
+            &self.kind,
+            &self.source,
+        )
+    }
+}
+
+// Formats all errors followed by a summary line. `action` is what failed, e.g. "assemble".
+pub fn report(action: &str, file: &str, errors: &[AssembleError]) -> String {
+    let summary = format!(
+        "error: could not {} `{}` due to {} previous error{}",
+        action,
+        file,
+        errors.len(),
+        if errors.len() == 1 { "" } else { "s" }
+    );
+    errors
+        .iter()
+        .map(|error| format!("{error}\n"))
+        .chain(std::iter::once(summary))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_error() {
+        let error = AssembleError::new(
+            "Add.asm",
+            3,
+            "  D=D+X",
+            "D+X",
+            ErrorKind::UnknownComp("D+X".to_string()),
+        );
+        assert_eq!(5, error.column);
+        assert_eq!(
+            "Add.asm:3:5: error: unknown comp mnemonic `D+X`\n  |\n3 |   D=D+X\n  |     ^",
+            error.to_string()
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use crate::error::ErrorKind;
+use parser::parser::*;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    And,
+    Or,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::And => "&",
+            Operator::Or => "|",
+            Operator::ShiftLeft => "<<",
+            Operator::ShiftRight => ">>",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+// Compile-time expression of an A-instruction or a `.equ` constant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    Number(i64),
+    Symbol(String),
+    Negate(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    // Returns the symbols the expression refers to, from left to right
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expression::Number(_) => Vec::new(),
+            Expression::Symbol(symbol) => vec![symbol],
+            Expression::Negate(operand) => operand.symbols(),
+            Expression::Binary(_, left, right) => {
+                let mut symbols = left.symbols();
+                symbols.extend(right.symbols());
+                symbols
+            }
+        }
+    }
+}
+
+// A Hack symbol: letters, digits, "_", ".", "$" and ":", not starting with a digit.
+// Unlike `identifier`, "-" is not part of it, so that "SCREEN-1" is a subtraction.
+pub fn symbol(input: &str) -> ParseResult<'_, String> {
+    let is_symbol_char = |c: char| c.is_alphanumeric() || ['_', '.', '$', ':'].contains(&c);
+    match input.chars().next() {
+        Some(first) if is_symbol_char(first) && !first.is_ascii_digit() => {
+            let end = input
+                .find(|c: char| !is_symbol_char(c))
+                .unwrap_or(input.len());
+            Ok((&input[end..], input[..end].to_string()))
+        }
+        _ => Err(input),
+    }
+}
+
+fn decimal(input: &str) -> ParseResult<'_, i64> {
+    let end = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    // Too large numbers saturate, to be reported as out of range
+    match input[..end].parse::<u64>() {
+        Ok(value) => Ok((&input[end..], value.min(i64::MAX as u64) as i64)),
+        Err(_) if end > 0 => Ok((&input[end..], i64::MAX)),
+        Err(_) => Err(input),
+    }
+}
+
+fn atom(input: &str) -> ParseResult<'_, Expression> {
+    either(
+        either(
+            decimal.map(Expression::Number),
+            symbol.map(Expression::Symbol),
+        ),
+        right(
+            match_literal("("),
+            left(whitespace_wrap(or), match_literal(")")),
+        ),
+    )
+    .parse(input)
+}
+
+fn unary(input: &str) -> ParseResult<'_, Expression> {
+    either(
+        right(left(match_literal("-"), space0()), unary)
+            .map(|operand| Expression::Negate(Box::new(operand))),
+        atom,
+    )
+    .parse(input)
+}
+
+// Parses operands separated by operators of the same precedence, associating to the left
+fn binary<'a>(
+    input: &'a str,
+    operators: &'a [(&'static str, Operator)],
+    operand: fn(&str) -> ParseResult<'_, Expression>,
+) -> ParseResult<'a, Expression> {
+    let operator = |input: &'a str| {
+        operators
+            .iter()
+            .find_map(|&(text, operator)| input.strip_prefix(text).map(|rest| (rest, operator)))
+            .ok_or(input)
+    };
+    pair(
+        operand,
+        zero_or_more(pair(whitespace_wrap(operator), operand)),
+    )
+    .map(|(first, rest)| {
+        rest.into_iter().fold(first, |left, (operator, right)| {
+            Expression::Binary(operator, Box::new(left), Box::new(right))
+        })
+    })
+    .parse(input)
+}
+
+fn product(input: &str) -> ParseResult<'_, Expression> {
+    binary(
+        input,
+        &[("*", Operator::Multiply), ("/", Operator::Divide)],
+        unary,
+    )
+}
+
+fn sum(input: &str) -> ParseResult<'_, Expression> {
+    binary(
+        input,
+        &[("+", Operator::Add), ("-", Operator::Subtract)],
+        product,
+    )
+}
+
+fn shift(input: &str) -> ParseResult<'_, Expression> {
+    binary(
+        input,
+        &[("<<", Operator::ShiftLeft), (">>", Operator::ShiftRight)],
+        sum,
+    )
+}
+
+fn and(input: &str) -> ParseResult<'_, Expression> {
+    binary(input, &[("&", Operator::And)], shift)
+}
+
+fn or(input: &str) -> ParseResult<'_, Expression> {
+    binary(input, &[("|", Operator::Or)], and)
+}
+
+// Parses an expression with the precedence of C: "* /", then "+ -", then "<< >>",
+// then "&", then "|"
+pub fn expression<'a>() -> impl Parser<'a, Expression> {
+    or
+}
+
+// Replaces the symbols of an expression text for which `rename` returns a new name
+pub fn rename_symbols(text: &str, rename: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut renamed = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (next, token) = match (decimal(rest), symbol(rest)) {
+            (Ok((next, _)), _) => (next, rest[..rest.len() - next.len()].to_string()),
+            (_, Ok((next, symbol))) => (next, rename(&symbol).unwrap_or(symbol)),
+            _ => (&rest[c.len_utf8()..], c.to_string()),
+        };
+        renamed.push_str(&token);
+        rest = next;
+    }
+    renamed
+}
+
+// Evaluates an expression. `lookup` returns the value of a symbol.
+pub fn evaluate(
+    expression: &Expression,
+    lookup: &mut dyn FnMut(&str) -> Result<i64, ErrorKind>,
+) -> Result<i64, ErrorKind> {
+    match expression {
+        Expression::Number(value) => Ok(*value),
+        Expression::Symbol(symbol) => lookup(symbol),
+        Expression::Negate(operand) => {
+            let operand = evaluate(operand, lookup)?;
+            operand
+                .checked_neg()
+                .ok_or_else(|| ErrorKind::InvalidExpression(format!("-{operand}")))
+        }
+        Expression::Binary(operator, left, right) => {
+            let (left, right) = (evaluate(left, lookup)?, evaluate(right, lookup)?);
+            let shift = |right: i64| u32::try_from(right).ok().filter(|&right| right < 63);
+            match operator {
+                Operator::Add => left.checked_add(right),
+                Operator::Subtract => left.checked_sub(right),
+                Operator::Multiply => left.checked_mul(right),
+                Operator::Divide => left.checked_div(right),
+                Operator::And => Some(left & right),
+                Operator::Or => Some(left | right),
+                Operator::ShiftLeft => shift(right).and_then(|right| left.checked_shl(right)),
+                Operator::ShiftRight => shift(right).and_then(|right| left.checked_shr(right)),
+            }
+            .ok_or_else(|| ErrorKind::InvalidExpression(format!("{left} {operator} {right}")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str) -> Result<i64, ErrorKind> {
+        let (rest, expression) = expression()
+            .parse(text)
+            .map_err(|_| ErrorKind::InvalidInstruction)?;
+        assert_eq!("", rest);
+        evaluate(&expression, &mut |symbol| match symbol {
+            "SCREEN" => Ok(16384),
+            "LOOP" => Ok(10),
+            _ => Err(ErrorKind::UndefinedSymbol(symbol.to_string())),
+        })
+    }
+
+    #[test]
+    fn parse_expressions() {
+        assert_eq!(
+            Ok((
+                "",
+                Expression::Binary(
+                    Operator::Add,
+                    Box::new(Expression::Symbol("SCREEN".to_string())),
+                    Box::new(Expression::Binary(
+                        Operator::Multiply,
+                        Box::new(Expression::Number(32)),
+                        Box::new(Expression::Number(2))
+                    ))
+                )
+            )),
+            expression().parse("SCREEN + 32*2")
+        );
+        assert_eq!(
+            Ok(("", Expression::Symbol("a.b$c:d_1".to_string()))),
+            expression().parse("a.b$c:d_1")
+        );
+    }
+
+    #[test]
+    fn rename_expression_symbols() {
+        let rename = |symbol: &str| Some(format!("{symbol}$1")).filter(|_| symbol == "LOOP");
+        assert_eq!(
+            "LOOP$1 + 2*LOOP2",
+            rename_symbols("LOOP + 2*LOOP2", &rename)
+        );
+    }
+
+    #[test]
+    fn evaluate_expressions() {
+        assert_eq!(Ok(16384 + 64), value("SCREEN+32*2"));
+        assert_eq!(Ok(16384 + 8191), value("SCREEN + (256 * 32 - 1)"));
+        assert_eq!(Ok(12), value("LOOP+2"));
+        assert_eq!(Ok(3), value("-1 + 4"));
+        assert_eq!(Ok(0x0F0F | 0x10), value("(65535 >> 4 & 3855) | 1 << 4"));
+        assert_eq!(Ok(7), value("15/2"));
+        assert_eq!(
+            Err(ErrorKind::InvalidExpression("1 / 0".to_string())),
+            value("1/0")
+        );
+        assert_eq!(
+            Err(ErrorKind::UndefinedSymbol("x".to_string())),
+            value("x+1")
+        );
+    }
+}
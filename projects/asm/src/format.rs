@@ -0,0 +1,392 @@
+use crate::assembler::MAX_ADDRESS;
+use std::fmt;
+
+// A file format of machine code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // One 16-character binary word per line
+    Hack,
+    // Two bytes per word, least significant first
+    LittleEndian,
+    // Two bytes per word, most significant first
+    BigEndian,
+    // Intel HEX records of up to 16 bytes, with byte addresses and big-endian words
+    IntelHex,
+    // Memory files of Verilog's $readmemb and $readmemh
+    ReadMemB,
+    ReadMemH,
+    // Logisim "v2.0 raw" memory image
+    Logisim,
+}
+
+pub const FORMATS: [Format; 7] = [
+    Format::Hack,
+    Format::LittleEndian,
+    Format::BigEndian,
+    Format::IntelHex,
+    Format::ReadMemB,
+    Format::ReadMemH,
+    Format::Logisim,
+];
+
+const LOGISIM_HEADER: &str = "v2.0 raw";
+
+// Words a loaded program may have, up to the last ROM address
+const ROM_SIZE: usize = MAX_ADDRESS as usize + 1;
+
+fn beyond_rom(line: usize, token: &str) -> String {
+    format!("line {line}: `{token}` goes beyond the ROM ({ROM_SIZE} words)")
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Hack => "hack",
+            Format::LittleEndian => "bin-le",
+            Format::BigEndian => "bin-be",
+            Format::IntelHex => "ihex",
+            Format::ReadMemB => "readmemb",
+            Format::ReadMemH => "readmemh",
+            Format::Logisim => "logisim",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Format {
+    // Returns the format of the given name, e.g. "ihex"
+    pub fn from_name(name: &str) -> Option<Format> {
+        FORMATS
+            .into_iter()
+            .find(|format| format.to_string() == name)
+    }
+
+    // Returns the format of a file extension. "bin" files are little-endian.
+    pub fn from_extension(extension: &str) -> Option<Format> {
+        FORMATS
+            .into_iter()
+            .find(|format| format.extension() == extension)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Hack => "hack",
+            Format::LittleEndian => "bin",
+            Format::BigEndian => "be.bin",
+            Format::IntelHex => "hex",
+            Format::ReadMemB => "memb",
+            Format::ReadMemH => "memh",
+            Format::Logisim => "img",
+        }
+    }
+
+    pub fn encode(&self, words: &[u16]) -> Vec<u8> {
+        let lines = |format: &dyn Fn(u16) -> String| {
+            words
+                .iter()
+                .map(|&word| format(word) + "\n")
+                .collect::<String>()
+        };
+        match self {
+            // Without a final newline, as written by the assembler so far
+            Format::Hack => words
+                .iter()
+                .map(|word| format!("{:016b}", word))
+                .collect::<Vec<String>>()
+                .join("\n")
+                .into_bytes(),
+            Format::LittleEndian => words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+            Format::BigEndian => words.iter().flat_map(|word| word.to_be_bytes()).collect(),
+            Format::IntelHex => intel_hex(words).into_bytes(),
+            Format::ReadMemB => lines(&|word| format!("{:016b}", word)).into_bytes(),
+            Format::ReadMemH => lines(&|word| format!("{:04x}", word)).into_bytes(),
+            Format::Logisim => logisim(words).into_bytes(),
+        }
+    }
+
+    // Decodes the content of a file into words. Errors of text formats give the line.
+    pub fn decode(&self, content: &[u8]) -> Result<Vec<u16>, String> {
+        let text = || std::str::from_utf8(content).map_err(|error| error.to_string());
+        match self {
+            Format::Hack => text().and_then(load_hack),
+            Format::LittleEndian => bytes(content, u16::from_le_bytes),
+            Format::BigEndian => bytes(content, u16::from_be_bytes),
+            Format::IntelHex => text().and_then(load_intel_hex),
+            Format::ReadMemB => text().and_then(|text| load_memory(text, 2)),
+            Format::ReadMemH => text().and_then(|text| load_memory(text, 16)),
+            Format::Logisim => text().and_then(load_logisim),
+        }
+    }
+}
+
+fn bytes(content: &[u8], word: fn([u8; 2]) -> u16) -> Result<Vec<u16>, String> {
+    if !content.len().is_multiple_of(2) {
+        return Err(format!("odd number of bytes ({})", content.len()));
+    }
+    Ok(content
+        .chunks(2)
+        .map(|pair| word([pair[0], pair[1]]))
+        .collect())
+}
+
+fn load_hack(content: &str) -> Result<Vec<u16>, String> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            if line.len() == 16 {
+                u16::from_str_radix(line, 2).ok()
+            } else {
+                None
+            }
+            .ok_or_else(|| format!("line {}: invalid machine instruction `{}`", index + 1, line))
+        })
+        .collect()
+}
+
+// Stores a word at an address, extending the memory with zeros as needed
+fn store(memory: &mut Vec<u16>, address: usize, word: u16) {
+    if memory.len() <= address {
+        memory.resize(address + 1, 0);
+    }
+    memory[address] = word;
+}
+
+// A record ":LLAAAATT<data>CC", whose checksum makes the sum of all its bytes zero
+fn record(address: u16, kind: u8, data: &[u8]) -> String {
+    let bytes = [data.len() as u8, (address >> 8) as u8, address as u8, kind]
+        .into_iter()
+        .chain(data.iter().copied())
+        .collect::<Vec<u8>>();
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+        .wrapping_neg();
+    let hex = bytes
+        .iter()
+        .chain(std::iter::once(&checksum))
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<String>();
+    format!(":{}\n", hex)
+}
+
+fn intel_hex(words: &[u16]) -> String {
+    let bytes = words
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect::<Vec<u8>>();
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(index, data)| record((index * 16) as u16, 0, data))
+        .chain(std::iter::once(record(0, 1, &[])))
+        .collect()
+}
+
+fn load_intel_hex(content: &str) -> Result<Vec<u16>, String> {
+    let mut memory = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+        let record = line
+            .strip_prefix(':')
+            .filter(|hex| hex.len() >= 10 && hex.len().is_multiple_of(2))
+            .and_then(|hex| {
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|start| u8::from_str_radix(&hex[start..start + 2], 16).ok())
+                    .collect::<Option<Vec<u8>>>()
+            })
+            .ok_or_else(|| error(&format!("invalid record `{}`", line)))?;
+        let length = record[0] as usize;
+        if record.len() != length + 5 {
+            return Err(error("record length does not match its byte count"));
+        }
+        if record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(error("checksum mismatch"));
+        }
+        let address = (record[1] as usize) << 8 | record[2] as usize;
+        match record[3] {
+            0 => {
+                for (offset, &byte) in record[4..4 + length].iter().enumerate() {
+                    if memory.len() <= address + offset {
+                        memory.resize(address + offset + 1, 0);
+                    }
+                    memory[address + offset] = byte;
+                }
+            }
+            1 => break,
+            kind => return Err(error(&format!("unsupported record type {:02X}", kind))),
+        }
+    }
+    if !memory.len().is_multiple_of(2) {
+        memory.push(0);
+    }
+    bytes(&memory, u16::from_be_bytes)
+}
+
+// Loads a $readmemb or $readmemh file: words in the given radix separated by whitespace,
+// "@address" in hexadecimal to move on, and "//" comments
+fn load_memory(content: &str, radix: u32) -> Result<Vec<u16>, String> {
+    let mut memory = Vec::new();
+    let mut address = 0;
+    for (index, line) in content.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or("");
+        for token in line.split_whitespace() {
+            let error = || format!("line {}: invalid word `{}`", index + 1, token);
+            match token.strip_prefix('@') {
+                Some(target) => {
+                    address = usize::from_str_radix(target, 16).map_err(|_| error())?;
+                    if address >= ROM_SIZE {
+                        return Err(beyond_rom(index + 1, token));
+                    }
+                }
+                None => {
+                    let word =
+                        u16::from_str_radix(&token.replace('_', ""), radix).map_err(|_| error())?;
+                    if address >= ROM_SIZE {
+                        return Err(beyond_rom(index + 1, token));
+                    }
+                    store(&mut memory, address, word);
+                    address += 1;
+                }
+            }
+        }
+    }
+    Ok(memory)
+}
+
+// Eight hexadecimal words per line, with a run of four or more equal words written "N*word"
+fn logisim(words: &[u16]) -> String {
+    let mut runs: Vec<(usize, u16)> = Vec::new();
+    for &word in words {
+        match runs.last_mut() {
+            Some((count, last)) if *last == word => *count += 1,
+            _ => runs.push((1, word)),
+        }
+    }
+    let tokens = runs
+        .into_iter()
+        .flat_map(|(count, word)| {
+            if count >= 4 {
+                vec![format!("{}*{:x}", count, word)]
+            } else {
+                vec![format!("{:x}", word); count]
+            }
+        })
+        .collect::<Vec<String>>();
+    std::iter::once(LOGISIM_HEADER.to_string())
+        .chain(tokens.chunks(8).map(|line| line.join(" ")))
+        .map(|line| line + "\n")
+        .collect()
+}
+
+fn load_logisim(content: &str) -> Result<Vec<u16>, String> {
+    let mut lines = content.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == LOGISIM_HEADER => (),
+        _ => return Err(format!("line 1: expected `{}`", LOGISIM_HEADER)),
+    }
+    let mut memory = Vec::new();
+    for (index, line) in lines {
+        let line = line.split('#').next().unwrap_or("");
+        for token in line.split_whitespace() {
+            let error = || format!("line {}: invalid word `{}`", index + 1, token);
+            let (count, word) = match token.split_once('*') {
+                Some((count, word)) => (count.parse::<usize>().map_err(|_| error())?, word),
+                None => (1, token),
+            };
+            let word = u16::from_str_radix(word, 16).map_err(|_| error())?;
+            if count > ROM_SIZE - memory.len() {
+                return Err(beyond_rom(index + 1, token));
+            }
+            memory.extend(std::iter::repeat_n(word, count));
+        }
+    }
+    Ok(memory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_formats() {
+        let words = [0x0002, 0xEC10, 0, 0, 0, 0];
+        assert_eq!(
+            "0000000000000010\n1110110000010000\n0000000000000000\n0000000000000000\n0000000000000000\n0000000000000000",
+            String::from_utf8(Format::Hack.encode(&words)).unwrap()
+        );
+        assert_eq!(
+            vec![0x02, 0x00, 0x10, 0xEC],
+            Format::LittleEndian.encode(&words[..2])
+        );
+        assert_eq!(
+            vec![0x00, 0x02, 0xEC, 0x10],
+            Format::BigEndian.encode(&words[..2])
+        );
+        assert_eq!(
+            ":040000000002EC10FE\n:00000001FF\n",
+            String::from_utf8(Format::IntelHex.encode(&words[..2])).unwrap()
+        );
+        assert_eq!(
+            "0002\nec10\n",
+            String::from_utf8(Format::ReadMemH.encode(&words[..2])).unwrap()
+        );
+        assert_eq!(
+            "v2.0 raw\n2 ec10 4*0\n",
+            String::from_utf8(Format::Logisim.encode(&words)).unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_formats() {
+        let words = (0..100)
+            .map(|word| if word < 50 { word * 997 } else { 7 })
+            .collect::<Vec<u16>>();
+        for format in FORMATS {
+            assert_eq!(
+                Ok(words.clone()),
+                format.decode(&format.encode(&words)),
+                "{}",
+                format
+            );
+            assert_eq!(Some(format), Format::from_name(&format.to_string()));
+        }
+        assert_eq!(
+            Ok(vec![1, 0, 0, 0xA]),
+            Format::ReadMemH.decode(b"// memory\n0001 @3 a")
+        );
+        assert_eq!(
+            Err("line 1: checksum mismatch".to_string()),
+            Format::IntelHex.decode(b":040000000002EC100F\n")
+        );
+        assert_eq!(
+            Err("line 2: `@FFFFFFFFFF` goes beyond the ROM (32768 words)".to_string()),
+            Format::ReadMemH.decode(b"0001\n@FFFFFFFFFF")
+        );
+        assert_eq!(
+            Err("line 1: `1` goes beyond the ROM (32768 words)".to_string()),
+            Format::ReadMemH.decode(b"@7fff 0 1")
+        );
+        assert_eq!(
+            Err("line 3: `32768*0` goes beyond the ROM (32768 words)".to_string()),
+            Format::Logisim.decode(b"v2.0 raw\n1\n32768*0")
+        );
+        assert_eq!(
+            Ok(32768),
+            Format::Logisim
+                .decode(b"v2.0 raw\n32768*0")
+                .map(|words| words.len())
+        );
+        assert_eq!(
+            Err("line 2: invalid machine instruction `012`".to_string()),
+            Format::Hack.decode(b"0000000000000000\n012")
+        );
+    }
+}
@@ -1,21 +1,148 @@
+use crate::expression::*;
 use parser::parser::*;
+use std::fmt;
 
 #[derive(PartialEq, Debug)]
 pub enum Instruction {
     A(String),
     C(Option<String>, String, Option<String>),
     L(String),
+    // A constant and its expression
+    Equ(String, String),
+    // A symbol defined for other modules, and one defined by another module, see object
+    Export(String),
+    Import(String),
+    // Directives, replaced by macros::expand before preprocess
+    Macro(String, Vec<String>),
+    EndMacro,
+    Include(String),
+    Invoke(String, Vec<String>),
+}
+
+// Formats an instruction in the canonical assembly syntax
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::A(symbol) => write!(f, "@{symbol}"),
+            Instruction::C(dest, comp, jump) => {
+                if let Some(dest) = dest {
+                    write!(f, "{dest}=")?;
+                }
+                write!(f, "{comp}")?;
+                if let Some(jump) = jump {
+                    write!(f, ";{jump}")?;
+                }
+                Ok(())
+            }
+            Instruction::L(label) => write!(f, "({label})"),
+            Instruction::Equ(name, value) => write!(f, ".equ {name} {value}"),
+            Instruction::Export(name) => write!(f, ".export {name}"),
+            Instruction::Import(name) => write!(f, ".import {name}"),
+            Instruction::Macro(name, parameters) if parameters.is_empty() => {
+                write!(f, ".macro {name}")
+            }
+            Instruction::Macro(name, parameters) => {
+                write!(f, ".macro {name} {}", parameters.join(", "))
+            }
+            Instruction::EndMacro => write!(f, ".endm"),
+            Instruction::Include(path) => write!(f, ".include \"{path}\""),
+            Instruction::Invoke(name, arguments) if arguments.is_empty() => write!(f, "{name}"),
+            Instruction::Invoke(name, arguments) => write!(f, "{name} {}", arguments.join(", ")),
+        }
+    }
 }
 
 pub fn instruction<'a>() -> impl Parser<'a, Instruction> {
     whitespace_wrap(right(
         simple_comment(),
-        either(l_instruction(), either(a_instruction(), c_instruction())),
+        either(
+            directive(),
+            either(
+                l_instruction(),
+                either(
+                    a_instruction(),
+                    either(left(c_instruction(), end), invocation()),
+                ),
+            ),
+        ),
     ))
 }
 
+// Succeeds if only whitespace is left, so that a macro invocation such as
+// "PUSH_D" is not taken for a C-instruction with the comp "PUSH"
+fn end(input: &str) -> ParseResult<'_, ()> {
+    if input.trim().is_empty() {
+        Ok((input, ()))
+    } else {
+        Err(input)
+    }
+}
+
+// A macro argument or parameter, e.g. "LCL", "2" or "D+1"
+fn argument<'a>() -> impl Parser<'a, String> {
+    one_or_more(pred(any_char, |c| *c != ',' && !c.is_whitespace()))
+        .map(|chars| chars.into_iter().collect())
+}
+
+// Comma separated arguments after the name of a macro, if any
+fn arguments<'a>() -> impl Parser<'a, Vec<String>> {
+    either(
+        right(
+            space1(),
+            pair(
+                argument(),
+                zero_or_more(right(whitespace_wrap(match_literal(",")), argument())),
+            ),
+        )
+        .map(|(first, rest)| std::iter::once(first).chain(rest).collect()),
+        |input| Ok((input, Vec::new())),
+    )
+}
+
+fn directive<'a>() -> impl Parser<'a, Instruction> {
+    either(
+        right(
+            match_literal(".macro"),
+            pair(right(space1(), identifier), arguments()),
+        )
+        .map(|(name, parameters)| Instruction::Macro(name, parameters)),
+        either(
+            match_literal(".endm").map(|_| Instruction::EndMacro),
+            either(
+                right(pair(match_literal(".include"), space1()), quoted_string())
+                    .map(Instruction::Include),
+                either(
+                    right(
+                        pair(match_literal(".equ"), space1()),
+                        pair(left(symbol, space1()), expression_text()),
+                    )
+                    .map(|(name, value)| Instruction::Equ(name, value)),
+                    either(
+                        right(pair(match_literal(".export"), space1()), symbol)
+                            .map(Instruction::Export),
+                        right(pair(match_literal(".import"), space1()), symbol)
+                            .map(Instruction::Import),
+                    ),
+                ),
+            ),
+        ),
+    )
+}
+
+// The text of an expression, e.g. "SCREEN + 32"
+fn expression_text<'a>() -> impl Parser<'a, String> {
+    move |input: &'a str| {
+        let (rest, _) = expression().parse(input)?;
+        Ok((rest, input[..input.len() - rest.len()].to_string()))
+    }
+}
+
+fn invocation<'a>() -> impl Parser<'a, Instruction> {
+    pair(identifier, arguments()).map(|(name, arguments)| Instruction::Invoke(name, arguments))
+}
+
 fn a_instruction<'a>() -> impl Parser<'a, Instruction> {
-    right(match_literal("@"), either(number, identifier)).map(Instruction::A)
+    right(match_literal("@"), expression_text()).map(Instruction::A)
 }
 
 fn c_instruction<'a>() -> impl Parser<'a, Instruction> {
@@ -64,6 +191,10 @@ mod tests {
             Ok(("", Instruction::A("123".to_string()))),
             a_instruction().parse("@123")
         );
+        assert_eq!(
+            Ok((" // end", Instruction::A("SCREEN + (32*2)".to_string()))),
+            a_instruction().parse("@SCREEN + (32*2) // end")
+        );
     }
 
     #[test]
@@ -135,6 +266,80 @@ mod tests {
         );
     }
 
+    #[test]
+    fn format_instruction() {
+        assert_eq!("@xxx", Instruction::A("xxx".to_string()).to_string());
+        assert_eq!("(xxx)", Instruction::L("xxx".to_string()).to_string());
+        assert_eq!(
+            "D=M;JMP",
+            Instruction::C(
+                Some("D".to_string()),
+                "M".to_string(),
+                Some("JMP".to_string())
+            )
+            .to_string()
+        );
+        assert_eq!(
+            "0;JMP",
+            Instruction::C(None, "0".to_string(), Some("JMP".to_string())).to_string()
+        );
+    }
+
+    #[test]
+    fn parse_directives() {
+        assert_eq!(
+            Ok((
+                "",
+                Instruction::Macro(
+                    "PUSH_SEGMENT".to_string(),
+                    vec!["segment".to_string(), "index".to_string()]
+                )
+            )),
+            instruction().parse(".macro PUSH_SEGMENT segment, index // comment")
+        );
+        assert_eq!(
+            Ok(("", Instruction::EndMacro)),
+            instruction().parse("  .endm")
+        );
+        assert_eq!(
+            Ok(("", Instruction::Include("lib/stack.asm".to_string()))),
+            instruction().parse(".include \"lib/stack.asm\"")
+        );
+        assert_eq!(
+            Ok((
+                "",
+                Instruction::Equ("ROW".to_string(), "32 * 2".to_string())
+            )),
+            instruction().parse(".equ ROW 32 * 2")
+        );
+        assert_eq!(
+            Ok((
+                "",
+                Instruction::Invoke(
+                    "PUSH_SEGMENT".to_string(),
+                    vec!["LCL".to_string(), "2".to_string()]
+                )
+            )),
+            instruction().parse("  PUSH_SEGMENT LCL,2")
+        );
+        assert_eq!(
+            Ok(("", Instruction::Invoke("PUSH_D".to_string(), Vec::new()))),
+            instruction().parse("PUSH_D")
+        );
+        assert_eq!(
+            Ok(("", Instruction::C(None, "D".to_string(), None))),
+            instruction().parse("D")
+        );
+        assert_eq!(
+            ".macro PUSH_SEGMENT segment, index",
+            Instruction::Macro(
+                "PUSH_SEGMENT".to_string(),
+                vec!["segment".to_string(), "index".to_string()]
+            )
+            .to_string()
+        );
+    }
+
     #[test]
     fn parse_instruction() {
         assert_eq!(Err(""), instruction().parse("   // Comment"));
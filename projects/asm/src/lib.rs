@@ -0,0 +1,14 @@
+pub mod assembler;
+pub mod disassembler;
+pub mod error;
+pub mod expression;
+pub mod format;
+pub mod instruction;
+pub mod linker;
+pub mod listing;
+pub mod macros;
+pub mod object;
+pub mod optimizer;
+pub mod source_map;
+pub mod translation;
+pub mod warning;
@@ -0,0 +1,278 @@
+use crate::assembler::MAX_ADDRESS;
+use crate::object::*;
+use collections::hashmap::HashMap;
+use collections::Empty;
+use std::fmt;
+
+// The first RAM address of data symbols, as for the variables of a program
+pub const DATA_START: u32 = 16;
+
+// Reason why objects could not be linked
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    UndefinedSymbol {
+        symbol: String,
+        module: String,
+    },
+    DuplicateSymbol {
+        symbol: String,
+        first: String,
+        second: String,
+    },
+    AddressOutOfRange {
+        module: String,
+        address: u32,
+        value: i64,
+    },
+    ProgramTooLarge(usize),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::UndefinedSymbol { symbol, module } => {
+                write!(f, "undefined symbol `{symbol}` imported by `{module}`")
+            }
+            LinkError::DuplicateSymbol {
+                symbol,
+                first,
+                second,
+            } => write!(
+                f,
+                "symbol `{symbol}` is exported by both `{first}` and `{second}`"
+            ),
+            LinkError::AddressOutOfRange {
+                module,
+                address,
+                value,
+            } => write!(
+                f,
+                "address `{value}` at {module}+{address} out of range (0..={MAX_ADDRESS})"
+            ),
+            LinkError::ProgramTooLarge(size) => write!(
+                f,
+                "program of {size} instructions does not fit in ROM ({} words)",
+                MAX_ADDRESS + 1
+            ),
+        }
+    }
+}
+
+// Formats the errors of linking `file`, followed by a summary line
+pub fn report(file: &str, errors: &[LinkError]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("error: {error}"))
+        .chain(std::iter::once(format!(
+            "error: could not link `{}` due to {} previous error{}",
+            file,
+            errors.len(),
+            if errors.len() == 1 { "" } else { "s" }
+        )))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Links objects into a program: the code of the modules is placed one after the other
+// from ROM address 0, so the first module is the entry point. The data symbols of each
+// module are allocated in turn from DATA_START, and every relocated word gets the address
+// of its target added.
+pub fn link(objects: &[Object]) -> Result<Vec<u16>, Vec<LinkError>> {
+    let mut errors = Vec::new();
+    let mut bases = Vec::new();
+    let mut size = 0;
+    for object in objects {
+        bases.push(size as u32);
+        size += object.code.len();
+    }
+    if size > MAX_ADDRESS as usize + 1 {
+        return Err(vec![LinkError::ProgramTooLarge(size)]);
+    }
+
+    // Exported symbols with their value and the index of their module
+    let mut exports: HashMap<String, (u32, usize)> = HashMap::empty();
+    let mut data = Vec::new();
+    let mut available_address = DATA_START;
+    for (index, object) in objects.iter().enumerate() {
+        let mut module_data: HashMap<String, u32> = HashMap::empty();
+        for name in &object.data {
+            module_data = module_data.insert(name.clone(), available_address);
+            available_address += 1;
+        }
+        for (name, export) in &object.exports {
+            let value = match export {
+                Export::Code(offset) => bases[index] + offset,
+                Export::Constant(value) => *value,
+                Export::Data => match module_data.get(name) {
+                    Some(&address) => address,
+                    None => {
+                        module_data = module_data.insert(name.clone(), available_address);
+                        available_address += 1;
+                        available_address - 1
+                    }
+                },
+            };
+            match exports.get(name) {
+                Some(&(_, first)) => errors.push(LinkError::DuplicateSymbol {
+                    symbol: name.clone(),
+                    first: objects[first].module.clone(),
+                    second: object.module.clone(),
+                }),
+                None => exports = exports.insert(name.clone(), (value, index)),
+            }
+        }
+        data.push(module_data);
+    }
+
+    let mut code = Vec::with_capacity(size);
+    for (index, object) in objects.iter().enumerate() {
+        for name in &object.imports {
+            if exports.get(name).is_none() {
+                errors.push(LinkError::UndefinedSymbol {
+                    symbol: name.clone(),
+                    module: object.module.clone(),
+                });
+            }
+        }
+        let mut words = object.code.clone();
+        for relocation in &object.relocations {
+            let target = match &relocation.target {
+                Target::Code => Some(bases[index]),
+                Target::Data(name) => data[index].get(name).copied(),
+                Target::Import(name) => exports.get(name).map(|&(value, _)| value),
+            };
+            let Some(target) = target else {
+                match &relocation.target {
+                    // Reported once above
+                    Target::Import(name) if object.imports.contains(name) => (),
+                    Target::Data(name) | Target::Import(name) => {
+                        errors.push(LinkError::UndefinedSymbol {
+                            symbol: name.clone(),
+                            module: object.module.clone(),
+                        })
+                    }
+                    Target::Code => (),
+                }
+                continue;
+            };
+            let word = &mut words[relocation.address as usize];
+            match target as i64 + *word as i16 as i64 {
+                value if (0..=MAX_ADDRESS as i64).contains(&value) => *word = value as u16,
+                value => errors.push(LinkError::AddressOutOfRange {
+                    module: object.module.clone(),
+                    address: relocation.address,
+                    value,
+                }),
+            }
+        }
+        code.extend(words);
+    }
+
+    if errors.is_empty() {
+        Ok(code)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::translate;
+    use crate::error::ErrorKind;
+    use collections::deque::*;
+
+    fn compile_lines(file: &str, lines: &[&str]) -> Object {
+        compile(file, lines, false).unwrap()
+    }
+
+    #[test]
+    fn link_modules() {
+        let main = compile_lines(
+            "Main.asm",
+            &[
+                ".import mult",
+                ".import product",
+                "@i",
+                "@RETURN",
+                "D=A",
+                "@mult",
+                "0;JMP",
+                "(RETURN)",
+                "@product",
+                "D=M",
+            ],
+        );
+        let math = compile_lines(
+            "Math.asm",
+            &[
+                ".export mult",
+                ".export product",
+                "(mult)",
+                "@i",
+                "@product",
+                "@mult + 1",
+            ],
+        );
+        assert_eq!(
+            Ok(vec![16, 5, 0xEC10, 7, 0xEA87, 18, 0xFC10, 17, 18, 8]),
+            link(&[main, math])
+        );
+    }
+
+    #[test]
+    fn link_like_translate() {
+        let lines = [
+            "@i", "M=1", "(LOOP)", "@i", "D=M", "@sum", "M=D+M", "@LOOP", "0;JMP",
+        ];
+        let (_, code) = translate("Test.asm", &lines, false, BankersDeque::empty()).unwrap();
+        assert_eq!(
+            Ok(code
+                .iter()
+                .map(|word| u16::from_str_radix(word.as_ref(), 2).unwrap())
+                .collect::<Vec<u16>>()),
+            link(&[compile_lines("Test.asm", &lines)])
+        );
+    }
+
+    #[test]
+    fn reject_non_linear_relocations() {
+        let first = compile_lines("A.asm", &["@1", "@2", "@3", "@4"]);
+        // At address 0, "LOOP & 3" moves by 1 and 2 with LOOP, but at 4 it is 0
+        let lines = [".equ MASK LOOP & 3", "(LOOP)", "@LOOP & 3", "@MASK"];
+        let errors = compile("B.asm", &lines, false)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| (error.line, error.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (1, ErrorKind::NotRelocatable("LOOP & 3".to_string())),
+                (3, ErrorKind::NotRelocatable("LOOP & 3".to_string())),
+            ],
+            errors
+        );
+        let second = compile_lines("B.asm", &["(LOOP)", "@LOOP + 3", "@3 + LOOP", "@LOOP - 3"]);
+        assert_eq!(Ok(vec![1, 2, 3, 4, 7, 7, 1]), link(&[first, second]));
+    }
+
+    #[test]
+    fn report_link_errors() {
+        let main = compile_lines("Main.asm", &[".import mult", ".export x", "@mult", "@x"]);
+        let math = compile_lines("Math.asm", &[".export x", "(x)", "@x"]);
+        assert_eq!(
+            Err(vec![
+                LinkError::DuplicateSymbol {
+                    symbol: "x".to_string(),
+                    first: "Main".to_string(),
+                    second: "Math".to_string(),
+                },
+                LinkError::UndefinedSymbol {
+                    symbol: "mult".to_string(),
+                    module: "Main".to_string(),
+                },
+            ]),
+            link(&[main, math])
+        );
+    }
+}
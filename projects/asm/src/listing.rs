@@ -0,0 +1,194 @@
+use crate::assembler::variables;
+use crate::expression::expression;
+use crate::instruction::*;
+use crate::macros::Expansion;
+use collections::hashmap::HashMap;
+use parser::parser::*;
+use std::path::Path;
+
+// Returns the location of an expanded line: its line number, prefixed with the file name
+// for a line of an included file
+fn location(file: &str, expansion: &Expansion, index: usize) -> String {
+    let origin = &expansion.origins[index];
+    if origin.file == file {
+        origin.line.to_string()
+    } else {
+        let name = Path::new(&origin.file).file_name().unwrap_or_default();
+        format!("{}:{}", name.to_string_lossy(), origin.line)
+    }
+}
+
+// Appends a section of the symbol appendix
+fn section(listing: &mut String, title: &str, symbols: &[(String, u32)]) {
+    listing.push_str(&format!("\n{}:\n", title));
+    if symbols.is_empty() {
+        listing.push_str("  (none)\n");
+    }
+    for (symbol, value) in symbols {
+        listing.push_str(&format!("  {:<32} {:>5}\n", symbol, value));
+    }
+}
+
+// Formats an assembler listing: the ROM address, binary and hexadecimal code of every
+// instruction beside its source line and the values of the symbols it refers to,
+// followed by the labels, constants and variables of `symbol_table` as computed by
+// `translate`. `code` holds one word per A- or C-instruction.
+pub fn listing(
+    file: &str,
+    expansion: &Expansion,
+    symbol_table: &HashMap<String, u32>,
+    code: &[u16],
+) -> String {
+    let instruction = instruction();
+    let value = |symbol: &String| symbol_table.get(symbol).copied().unwrap_or_default();
+
+    let mut rows = Vec::new();
+    let mut labels = Vec::new();
+    let mut constants = Vec::new();
+    let mut words = code.iter();
+    for (index, line) in expansion.lines.iter().enumerate() {
+        let source = line.trim_end().to_string();
+        let location = location(file, expansion, index);
+        match instruction.parse(line) {
+            Ok(("", Instruction::A(text))) => {
+                let symbols = expression()
+                    .parse(&text)
+                    .map(|(_, expression)| {
+                        expression
+                            .symbols()
+                            .into_iter()
+                            .map(|symbol| symbol.to_string())
+                            .collect::<Vec<String>>()
+                    })
+                    .unwrap_or_default();
+                let symbols = symbols
+                    .iter()
+                    .map(|symbol| format!("{}={}", symbol, value(symbol)))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                rows.push((words.next(), location, source, symbols));
+            }
+            Ok(("", Instruction::C(_, _, _))) => {
+                rows.push((words.next(), location, source, String::new()))
+            }
+            Ok(("", Instruction::L(label))) => {
+                labels.push(label);
+                rows.push((None, location, source, String::new()));
+            }
+            Ok(("", Instruction::Equ(name, _))) => {
+                constants.push(name);
+                rows.push((None, location, source, String::new()));
+            }
+            _ => rows.push((None, location, source, String::new())),
+        }
+    }
+    let variables = variables(&expansion.lines())
+        .into_iter()
+        .map(|(variable, _)| variable)
+        .collect::<Vec<String>>();
+
+    let width = rows
+        .iter()
+        .filter(|(_, _, _, symbols)| !symbols.is_empty())
+        .map(|(_, _, source, _)| source.len())
+        .max()
+        .unwrap_or(0)
+        .min(40);
+    let mut listing = format!(
+        "Listing of {}\n\n{:>7}  {:<16}  {:<4}  {:>5}  {}\n",
+        file, "Address", "Binary", "Hex", "Line", "Source"
+    );
+    let mut address = 0;
+    for (word, location, source, symbols) in rows {
+        let code = match word {
+            Some(&word) => {
+                address += 1;
+                format!("{:>7}  {:016b}  {:04X}", address - 1, word, word)
+            }
+            None => " ".repeat(31),
+        };
+        let line = if symbols.is_empty() {
+            format!("{}  {:>5}  {}", code, location, source)
+        } else {
+            format!(
+                "{}  {:>5}  {:<width$}  ; {}",
+                code, location, source, symbols
+            )
+        };
+        listing.push_str(line.trim_end());
+        listing.push('\n');
+    }
+
+    let symbols = |names: Vec<String>| {
+        let mut symbols = names
+            .into_iter()
+            .map(|name| {
+                let value = value(&name);
+                (name, value)
+            })
+            .collect::<Vec<(String, u32)>>();
+        symbols.sort_by_key(|&(_, value)| value);
+        symbols
+    };
+    section(&mut listing, "Labels", &symbols(labels));
+    section(&mut listing, "Constants", &symbols(constants));
+    section(&mut listing, "Variables", &symbols(variables));
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::translate_into;
+    use crate::macros::expand;
+
+    #[test]
+    fn list_program() {
+        let lines = [
+            "// Counts down",
+            ".equ START 3",
+            "@START",
+            "D=A",
+            "@i",
+            "M=D",
+            "(LOOP)",
+            "@i",
+            "MD=M-1",
+            "@LOOP",
+            "D;JGT",
+        ];
+        let expansion = expand("Test.asm", &lines, &|_| Err(String::new())).unwrap();
+        let mut code = Vec::new();
+        let symbol_table = translate_into("Test.asm", &expansion.lines(), false, &mut |word| {
+            code.push(word)
+        })
+        .unwrap();
+        assert_eq!(
+            "Listing of Test.asm
+
+Address  Binary            Hex    Line  Source
+                                     1  // Counts down
+                                     2  .equ START 3
+      0  0000000000000011  0003      3  @START  ; START=3
+      1  1110110000010000  EC10      4  D=A
+      2  0000000000010000  0010      5  @i      ; i=16
+      3  1110001100001000  E308      6  M=D
+                                     7  (LOOP)
+      4  0000000000010000  0010      8  @i      ; i=16
+      5  1111110010011000  FC98      9  MD=M-1
+      6  0000000000000100  0004     10  @LOOP   ; LOOP=4
+      7  1110001100000001  E301     11  D;JGT
+
+Labels:
+  LOOP                                 4
+
+Constants:
+  START                                3
+
+Variables:
+  i                                   16
+",
+            listing("Test.asm", &expansion, &symbol_table, &code)
+        );
+    }
+}
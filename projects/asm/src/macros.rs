@@ -0,0 +1,505 @@
+use crate::error::*;
+use crate::expression::rename_symbols;
+use crate::instruction::*;
+use crate::source_map::SourceMap;
+use crate::warning::Warning;
+use collections::hashmap::HashMap;
+use parser::parser::*;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+// Line of a source file an expanded line comes from. Lines of a macro body come from
+// the line that invokes the macro.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    pub file: String,
+    pub line: usize,
+}
+
+// Plain assembly with the origin of each line
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Expansion {
+    pub lines: Vec<String>,
+    pub origins: Vec<Origin>,
+}
+
+struct Definition {
+    parameters: Vec<String>,
+    body: Vec<String>,
+}
+
+struct Expander<'r> {
+    read: &'r dyn Fn(&str) -> Result<String, String>,
+    macros: HashMap<String, Rc<Definition>>,
+    // Files being included, to detect cycles
+    files: Vec<String>,
+    expansions: usize,
+    expansion: Expansion,
+    errors: Vec<AssembleError>,
+}
+
+// Replaces "{parameter}" with its argument in a line of a macro body
+fn substitute(line: &str, parameters: &[String], arguments: &[String]) -> String {
+    parameters
+        .iter()
+        .zip(arguments)
+        .fold(line.to_string(), |line, (parameter, argument)| {
+            line.replace(&format!("{{{}}}", parameter), argument)
+        })
+}
+
+impl Expander<'_> {
+    fn error(&mut self, origin: &Origin, source: &str, text: &str, kind: ErrorKind) {
+        self.errors.push(AssembleError::new(
+            &origin.file,
+            origin.line,
+            source,
+            text,
+            kind,
+        ));
+    }
+
+    fn file(&mut self, file: &str, lines: &[&str]) {
+        let instruction = instruction();
+        // Name, parameters, body and origin of the macro being defined
+        let mut definition: Option<(String, Vec<String>, Vec<String>, Origin)> = None;
+
+        for (index, &line) in lines.iter().enumerate() {
+            let origin = Origin {
+                file: file.to_string(),
+                line: index + 1,
+            };
+            let parsed = instruction.parse(line);
+            definition = match (definition, parsed) {
+                (Some((name, parameters, body, start)), Ok(("", Instruction::EndMacro))) => {
+                    if self.macros.get(&name).is_some() {
+                        self.error(
+                            &start,
+                            lines[start.line - 1],
+                            &name,
+                            ErrorKind::DuplicateMacro(name.clone()),
+                        );
+                    } else {
+                        self.macros = self
+                            .macros
+                            .insert(name, Rc::new(Definition { parameters, body }));
+                    }
+                    None
+                }
+                (Some(definition), Ok(("", Instruction::Macro(name, _)))) => {
+                    self.error(&origin, line, &name, ErrorKind::NestedMacro(name.clone()));
+                    Some(definition)
+                }
+                (Some((name, parameters, mut body, start)), _) => {
+                    body.push(line.to_string());
+                    Some((name, parameters, body, start))
+                }
+                (None, Ok(("", Instruction::Macro(name, parameters)))) => {
+                    Some((name, parameters, Vec::new(), origin))
+                }
+                (None, Ok(("", Instruction::EndMacro))) => {
+                    self.error(&origin, line, "", ErrorKind::UnexpectedEndMacro);
+                    None
+                }
+                (None, _) => {
+                    self.line(line, &origin, &mut Vec::new());
+                    None
+                }
+            };
+        }
+
+        if let Some((name, _, _, start)) = definition {
+            self.error(
+                &start,
+                lines[start.line - 1],
+                &name,
+                ErrorKind::UnterminatedMacro(name.clone()),
+            );
+        }
+    }
+
+    // Expands an include or a macro invocation, or keeps a line of plain assembly.
+    // `invoking` are the macros being expanded, to detect recursion.
+    fn line(&mut self, line: &str, origin: &Origin, invoking: &mut Vec<String>) {
+        match instruction().parse(line) {
+            Ok(("", Instruction::Include(path))) => self.include(line, &path, origin),
+            Ok(("", Instruction::Invoke(name, arguments))) => {
+                self.invoke(line, &name, &arguments, origin, invoking)
+            }
+            // A macro name that is also a valid comp, e.g. "PUSHD"
+            Ok(("", Instruction::C(None, name, None))) if self.macros.get(&name).is_some() => {
+                self.invoke(line, &name, &[], origin, invoking)
+            }
+            Ok(("", Instruction::Macro(name, _))) => {
+                self.error(origin, line, &name, ErrorKind::NestedMacro(name.clone()))
+            }
+            _ => {
+                self.expansion.lines.push(line.to_string());
+                self.expansion.origins.push(origin.clone());
+            }
+        }
+    }
+
+    // Includes a file, found relative to the including file
+    fn include(&mut self, line: &str, path: &str, origin: &Origin) {
+        let file = normalize(
+            &Path::new(&origin.file)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(path),
+        );
+        if self.files.contains(&file) {
+            self.error(
+                origin,
+                line,
+                path,
+                ErrorKind::RecursiveInclude(path.to_string()),
+            );
+            return;
+        }
+        match (self.read)(&file) {
+            Ok(content) => {
+                self.files.push(file.clone());
+                self.file(&file, &content.lines().collect::<Vec<&str>>());
+                self.files.pop();
+            }
+            Err(message) => self.error(
+                origin,
+                line,
+                path,
+                ErrorKind::Include {
+                    path: path.to_string(),
+                    message,
+                },
+            ),
+        }
+    }
+
+    // Expands a macro. Labels defined in its body are renamed "LABEL$n" for the n-th
+    // expansion, so that every expansion has its own.
+    fn invoke(
+        &mut self,
+        line: &str,
+        name: &str,
+        arguments: &[String],
+        origin: &Origin,
+        invoking: &mut Vec<String>,
+    ) {
+        let definition = match self.macros.get(&name.to_string()) {
+            Some(definition) => definition.clone(),
+            None => {
+                return self.error(
+                    origin,
+                    line,
+                    name,
+                    ErrorKind::UnknownMacro(name.to_string()),
+                )
+            }
+        };
+        if definition.parameters.len() != arguments.len() {
+            return self.error(
+                origin,
+                line,
+                name,
+                ErrorKind::ArgumentCount {
+                    name: name.to_string(),
+                    expected: definition.parameters.len(),
+                    actual: arguments.len(),
+                },
+            );
+        }
+        if invoking.iter().any(|invoked| invoked == name) {
+            return self.error(
+                origin,
+                line,
+                name,
+                ErrorKind::RecursiveMacro(name.to_string()),
+            );
+        }
+
+        self.expansions += 1;
+        let body = definition
+            .body
+            .iter()
+            .map(|line| substitute(line, &definition.parameters, arguments))
+            .collect::<Vec<String>>();
+        let instruction = instruction();
+        let labels = body
+            .iter()
+            .filter_map(|line| match instruction.parse(line) {
+                Ok(("", Instruction::L(label))) => Some(label),
+                _ => None,
+            })
+            .collect::<Vec<String>>();
+        let local = |label: &str| format!("{}${}", label, self.expansions);
+        let body = body
+            .iter()
+            .map(|line| match instruction.parse(line) {
+                Ok(("", Instruction::L(label))) => {
+                    line.replacen(&format!("({})", label), &format!("({})", local(&label)), 1)
+                }
+                Ok(("", Instruction::A(value))) => {
+                    let renamed = rename_symbols(&value, &|symbol| {
+                        Some(local(symbol)).filter(|_| labels.iter().any(|label| label == symbol))
+                    });
+                    line.replacen(&format!("@{}", value), &format!("@{}", renamed), 1)
+                }
+                _ => line.clone(),
+            })
+            .collect::<Vec<String>>();
+
+        invoking.push(name.to_string());
+        for line in &body {
+            self.line(line, origin, invoking);
+        }
+        invoking.pop();
+    }
+}
+
+// Returns a path with its "." components removed and its ".." components collapsed with
+// the preceding directories, e.g. "D.asm" for "sub/../D.asm", so that a file included
+// through different paths is recognized
+fn normalize(path: &Path) -> String {
+    let mut components: Vec<Component> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if matches!(components.last(), Some(Component::Normal(_))) => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components
+        .iter()
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Expands the macros and includes of a file into plain assembly. `read` returns the
+// content of an included file, whose path is relative to the including file.
+// All errors are reported, at the line of the directive or invocation.
+pub fn expand(
+    file: &str,
+    lines: &[&str],
+    read: &dyn Fn(&str) -> Result<String, String>,
+) -> Result<Expansion, Vec<AssembleError>> {
+    let mut expander = Expander {
+        read,
+        macros: HashMap::new(),
+        files: vec![normalize(Path::new(file))],
+        expansions: 0,
+        expansion: Expansion::default(),
+        errors: Vec::new(),
+    };
+    expander.file(file, lines);
+    if expander.errors.is_empty() {
+        Ok(expander.expansion)
+    } else {
+        Err(expander.errors)
+    }
+}
+
+impl Expansion {
+    pub fn lines(&self) -> Vec<&str> {
+        self.lines.iter().map(String::as_str).collect()
+    }
+
+    // Moves errors found in the expanded lines to the lines they come from
+    pub fn locate(&self, errors: Vec<AssembleError>) -> Vec<AssembleError> {
+        errors
+            .into_iter()
+            .map(|error| match self.origins.get(error.line - 1) {
+                Some(origin) => AssembleError {
+                    file: origin.file.clone(),
+                    line: origin.line,
+                    ..error
+                },
+                None => error,
+            })
+            .collect()
+    }
+
+    // Moves warnings found in the expanded lines to the lines they come from
+    pub fn locate_warnings(&self, warnings: Vec<Warning>) -> Vec<Warning> {
+        warnings
+            .into_iter()
+            .map(|warning| match self.origins.get(warning.line - 1) {
+                Some(origin) => Warning {
+                    file: origin.file.clone(),
+                    line: origin.line,
+                    ..warning
+                },
+                None => warning,
+            })
+            .collect()
+    }
+
+    // Moves the entries of a source map of the expanded lines to the lines they come from.
+    // Like source_map, entries name files without their directory.
+    pub fn locate_map(&self, mut source_map: SourceMap) -> SourceMap {
+        for entry in source_map.entries.iter_mut() {
+            if let Some(origin) = self.origins.get(entry.line - 1) {
+                entry.file = Path::new(&origin.file)
+                    .file_name()
+                    .map_or(origin.file.clone(), |name| {
+                        name.to_string_lossy().into_owned()
+                    });
+                entry.line = origin.line;
+            }
+        }
+        source_map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_files(path: &str) -> Result<String, String> {
+        Err(format!("{} not found", path))
+    }
+
+    #[test]
+    fn expand_macros() {
+        let lines = [
+            ".macro PUSH_SEGMENT segment, index",
+            "  @{segment}",
+            "  D=M",
+            "  @{index}",
+            "  A=D+A",
+            "  D=M",
+            "  PUSH_D",
+            ".endm",
+            ".macro PUSH_D",
+            "(LOOP)   // local",
+            "  @LOOP",
+            "  @LOOP+1",
+            ".endm",
+            "PUSH_SEGMENT LCL, 2",
+            "PUSH_D",
+        ];
+        let expansion = expand("Test.asm", &lines, &no_files).unwrap();
+        assert_eq!(
+            vec![
+                "  @LCL",
+                "  D=M",
+                "  @2",
+                "  A=D+A",
+                "  D=M",
+                "(LOOP$2)   // local",
+                "  @LOOP$2",
+                "  @LOOP$2+1",
+                "(LOOP$3)   // local",
+                "  @LOOP$3",
+                "  @LOOP$3+1",
+            ],
+            expansion.lines
+        );
+        assert_eq!(
+            vec![14, 14, 14, 14, 14, 14, 14, 14, 15, 15, 15],
+            expansion
+                .origins
+                .iter()
+                .map(|origin| origin.line)
+                .collect::<Vec<usize>>()
+        );
+    }
+
+    #[test]
+    fn include_files() {
+        let read = |path: &str| match path {
+            "lib/stack.asm" => {
+                Ok(".macro POP\n@SP\nAM=M-1\n.endm\n.include \"stack.asm\"".to_string())
+            }
+            _ => Err(format!("{} not found", path)),
+        };
+        let lines = [".include \"stack.asm\"", "POP", ".include \"missing.asm\""];
+        let errors = expand("lib/Main.asm", &lines, &read).unwrap_err();
+        assert_eq!(
+            vec![
+                (
+                    "lib/stack.asm".to_string(),
+                    5,
+                    ErrorKind::RecursiveInclude("stack.asm".to_string())
+                ),
+                (
+                    "lib/Main.asm".to_string(),
+                    3,
+                    ErrorKind::Include {
+                        path: "missing.asm".to_string(),
+                        message: "lib/missing.asm not found".to_string()
+                    }
+                ),
+            ],
+            errors
+                .into_iter()
+                .map(|error| (error.file, error.line, error.kind))
+                .collect::<Vec<_>>()
+        );
+        let expansion = expand("lib/Main.asm", &lines[..2], &|path| {
+            read(path).map(|content| content.replace(".include \"stack.asm\"", ""))
+        })
+        .unwrap();
+        assert_eq!(vec!["@SP", "AM=M-1"], expansion.lines);
+    }
+
+    #[test]
+    fn detect_include_cycles_through_parent_directories() {
+        let read = |path: &str| match path {
+            "sub/E.asm" => Ok("@1\n.include \"../D.asm\"".to_string()),
+            "D.asm" => Ok(".include \"sub/E.asm\"".to_string()),
+            _ => Err(format!("{} not found", path)),
+        };
+        let errors = expand("./D.asm", &[".include \"sub/E.asm\""], &read).unwrap_err();
+        assert_eq!(
+            vec![(
+                "sub/E.asm".to_string(),
+                2,
+                ErrorKind::RecursiveInclude("../D.asm".to_string())
+            )],
+            errors
+                .into_iter()
+                .map(|error| (error.file, error.line, error.kind))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn report_macro_errors() {
+        let lines = [
+            ".macro LOOP",
+            "LOOP",
+            ".endm",
+            "LOOP",
+            "MISSING 1",
+            ".macro TWO a, b",
+            ".endm",
+            "TWO 1",
+            ".endm",
+            ".macro OPEN",
+        ];
+        let errors = expand("Test.asm", &lines, &no_files).unwrap_err();
+        assert_eq!(
+            vec![
+                (4, ErrorKind::RecursiveMacro("LOOP".to_string())),
+                (5, ErrorKind::UnknownMacro("MISSING".to_string())),
+                (
+                    8,
+                    ErrorKind::ArgumentCount {
+                        name: "TWO".to_string(),
+                        expected: 2,
+                        actual: 1
+                    }
+                ),
+                (9, ErrorKind::UnexpectedEndMacro),
+                (10, ErrorKind::UnterminatedMacro("OPEN".to_string())),
+            ],
+            errors
+                .into_iter()
+                .map(|error| (error.line, error.kind))
+                .collect::<Vec<_>>()
+        );
+    }
+}
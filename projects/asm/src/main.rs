@@ -1,150 +1,385 @@
+use asm::assembler::*;
+use asm::disassembler::*;
+use asm::error::*;
+use asm::format::*;
+use asm::linker::{self, link};
+use asm::listing::*;
+use asm::macros::*;
+use asm::object::{self, compile, Object};
+use asm::optimizer::*;
+use asm::source_map::*;
+use asm::warning::{self, check, Lint, Warning, LINTS};
 use collections::deque::*;
-use collections::hashmap::*;
 use collections::Empty;
 use functional::functor::*;
 use functional::io::*;
-use instruction::*;
-use parser::parser::*;
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process;
-use translation::*;
 
-mod instruction;
-mod translation;
-
-fn preprocess<'a>(lines: &[&str]) -> Result<HashMap<String, u32>, &'a str> {
-    let instruction = instruction();
-    let symbol_table = symbol_table();
+// Expands the macros and includes of a file, and optimizes it if asked for
+fn expand_file(input: &str, content: &str, optimized: bool) -> Result<Expansion, String> {
+    let lines = content.lines().collect::<Vec<&str>>();
+    let read = |path: &str| fs::read_to_string(path).map_err(|error| error.to_string());
+    match expand(input, &lines, &read) {
+        Ok(expansion) if optimized => Ok(optimize(&expansion)),
+        Ok(expansion) => Ok(expansion),
+        Err(errors) => Err(report("assemble", input, &errors)),
+    }
+}
 
-    lines
-        .iter()
-        .try_fold(
-            (0, symbol_table),
-            |(line_number, symbol_table), &line| match instruction.parse(line) {
-                Ok(("", Instruction::L(symbol))) => {
-                    Ok((line_number, symbol_table.insert(symbol, line_number)))
-                }
-                Ok(("", Instruction::A(_))) | Ok(("", Instruction::C(_, _, _))) => {
-                    Ok((line_number + 1, symbol_table))
-                }
-                Err(_) => Ok((line_number, symbol_table)),
-                _ => Err("Filed to preprocess"),
-            },
-        )
-        .map(|(_, symbol_table)| symbol_table)
+// Assembles a file into `output`, with the source map and listing beside it if asked for
+fn run(input: String, output: String, options: &Options) -> Result<(), String> {
+    let format = options.format;
+    let stem = output
+        .strip_suffix(&format!(".{}", format.extension()))
+        .unwrap_or(&output)
+        .to_string();
+    let beside = |enabled: bool, extension: &str| {
+        Some(format!("{}.{}", stem, extension)).filter(|_| enabled)
+    };
+    let map_output = beside(options.map, "map");
+    let list_output = beside(options.list, "lst");
+    IO::<String>::read_file(input.clone())
+        .flat_map(move |content| {
+            let expansion = match expand_file(&input, &content, options.optimize) {
+                Ok(expansion) => expansion,
+                Err(error) => return IO::Error(error),
+            };
+            let assembly = expansion.lines();
+            let mut words = Vec::new();
+            translate_into(&input, &assembly, options.extended, &mut |word| {
+                words.push(word)
+            })
+            .map_or_else(
+                |errors| IO::Error(report("assemble", &input, &expansion.locate(errors))),
+                |symbol_table| {
+                    let warnings = expansion
+                        .locate_warnings(check(&input, &assembly, &symbol_table))
+                        .into_iter()
+                        .filter(|warning| options.lints.contains(&warning.lint))
+                        .collect::<Vec<Warning>>();
+                    if !warnings.is_empty() {
+                        let report = warning::report(&input, &warnings, options.warnings_as_errors);
+                        if options.warnings_as_errors {
+                            return IO::Error(report);
+                        }
+                        eprintln!("{}", report);
+                    }
+                    let listing = list_output.map(|list_output| {
+                        (
+                            list_output,
+                            listing(&input, &expansion, &symbol_table, &words),
+                        )
+                    });
+                    let machine_code = format.encode(&words);
+                    let source_map = map_output.map(|map_output| {
+                        let file = PathBuf::from(&input)
+                            .file_name()
+                            .unwrap()
+                            .to_string_lossy()
+                            .into_owned();
+                        (
+                            map_output,
+                            expansion
+                                .locate_map(source_map(&file, &assembly, &symbol_table))
+                                .to_string(),
+                        )
+                    });
+                    IO::<String>::write_bytes(output, machine_code)
+                        .flat_map(|_| match source_map {
+                            Some((map_output, source_map)) => {
+                                IO::<String>::write_file(map_output, source_map)
+                            }
+                            None => IO::Return(()),
+                        })
+                        .flat_map(|_| match listing {
+                            Some((list_output, listing)) => {
+                                IO::<String>::write_file(list_output, listing)
+                            }
+                            None => IO::Return(()),
+                        })
+                },
+            )
+        })
+        .unsafe_run()
 }
 
-fn assemble<'a, D: Deque<String>>(
-    lines: &[&str],
-    symbol_table: HashMap<String, u32>,
-    code: D,
-) -> Result<D, &'a str> {
-    let available_address = 16;
-    let code = code;
-    let instruction = instruction();
-    let dest_table = dest_table();
-    let comp_table = comp_table();
-    let jump_table = jump_table();
+// Assembles a module into a relocatable object file
+fn run_compiler(input: String, output: String, options: &Options) -> Result<(), String> {
+    IO::<String>::read_file(input.clone())
+        .flat_map(move |content| {
+            let expansion = match expand_file(&input, &content, options.optimize) {
+                Ok(expansion) => expansion,
+                Err(error) => return IO::Error(error),
+            };
+            compile(&input, &expansion.lines(), options.extended).map_or_else(
+                |errors| IO::Error(report("assemble", &input, &expansion.locate(errors))),
+                |object| IO::<String>::write_file(output, object.to_string()),
+            )
+        })
+        .unsafe_run()
+}
 
-    lines
+// Links object files into a program, placing their code in the order given
+fn run_linker(inputs: &[String], output: String, format: Format) -> Result<(), String> {
+    let objects = inputs
         .iter()
-        .try_fold(
-            (symbol_table, available_address, code),
-            |(symbol_table, available_address, code), &line| match instruction.parse(line) {
-                Ok(("", Instruction::L(_))) => Ok((symbol_table, available_address, code)),
-                Ok(("", Instruction::A(symbol))) => match symbol.parse::<u32>() {
-                    Ok(decimal) => Ok((
-                        symbol_table,
-                        available_address,
-                        code.push_back(format!("{:016b}", decimal)),
-                    )),
-                    Err(_) => match symbol_table.get(&symbol) {
-                        Some(&decimal) => Ok((
-                            symbol_table,
-                            available_address,
-                            code.push_back(format!("{:016b}", decimal)),
-                        )),
-                        None => Ok((
-                            symbol_table.insert(symbol, available_address),
-                            available_address + 1,
-                            code.push_back(format!("{:016b}", available_address)),
-                        )),
-                    },
-                },
-                Ok(("", Instruction::C(dest, comp, jump))) => {
-                    let binary = (
-                        dest.as_deref().map_or(Some(&"000"), |c| dest_table.get(&c)),
-                        comp_table.get(&comp.as_str()),
-                        jump.as_deref().map_or(Some(&"000"), |j| jump_table.get(&j)),
-                    );
-
-                    match binary {
-                        (Some(&dest_bin), Some(&comp_bin), Some(&jump_bin)) => Ok((
-                            symbol_table,
-                            available_address,
-                            code.push_back(format!("111{}{}{}", comp_bin, dest_bin, jump_bin)),
-                        )),
-                        _ => Err("Failed to assemble"),
-                    }
-                }
-                Err(_) => Ok((symbol_table, available_address, code)),
-                _ => Err("Failed to assemble"),
-            },
-        )
-        .map(|(_, _, code)| code)
+        .map(|input| {
+            IO::<String>::read_file(input.clone())
+                .unsafe_run()
+                .and_then(|content| object::parse(&content))
+                .map_err(|error| format!("{}: {}", input, error))
+        })
+        .collect::<Result<Vec<Object>, String>>()?;
+    let words = link(&objects).map_err(|errors| linker::report(&output, &errors))?;
+    IO::<String>::write_bytes(output, format.encode(&words)).unsafe_run()
 }
 
-fn run<D: Deque<String>>(input: String, output: String, code: D) {
-    IO::<String>::read_file(input)
-        .flat_map(|content| {
-            let assembly = content.lines().collect::<Vec<&str>>();
-            preprocess(&assembly).map_or_else(
-                |error| IO::Error(error.to_string()),
-                |symbol_table| {
-                    assemble::<D>(&assembly, symbol_table, code).map_or_else(
-                        |error| IO::Error(error.to_string()),
-                        |binary| {
-                            let lines = binary
-                                .iter()
-                                .map(|s| s.as_ref().clone())
-                                .collect::<Vec<String>>()
-                                .join("\n");
-                            IO::<String>::write_file(output, lines)
-                        },
-                    )
+fn run_disassembler(
+    input: String,
+    output: String,
+    labels: bool,
+    extended: bool,
+    format: Format,
+) -> Result<(), String> {
+    IO::<String>::read_bytes(input.clone())
+        .flat_map(move |content| {
+            // Other formats are decoded first, and their words disassembled as .hack lines
+            let content = match format {
+                Format::Hack => String::from_utf8_lossy(&content).into_owned(),
+                _ => match format.decode(&content) {
+                    Ok(words) => words
+                        .iter()
+                        .map(|word| format!("{:016b}", word))
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                    Err(error) => return IO::Error(format!("{}: {}", input, error)),
                 },
+            };
+            let binary = content.lines().collect::<Vec<&str>>();
+            disassemble(&input, &binary, labels, extended).map_or_else(
+                |errors| IO::Error(report("disassemble", &input, &errors)),
+                |assembly| IO::<String>::write_file(output, assembly.join("\n")),
             )
         })
         .unsafe_run()
-        .unwrap_or_else(|e| panic!("Failed to process a file: {}", e));
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+const OBJECT_EXTENSION: &str = "obj";
 
-    if args.len() == 1 {
-        eprintln!("Usage: {} <asm file name>", &args[0]);
-        process::exit(1);
+struct Options {
+    inputs: Vec<String>,
+    output: Option<String>,
+    map: bool,
+    list: bool,
+    optimize: bool,
+    format: Format,
+    // Enabled lints of the warnings pass
+    lints: Vec<Lint>,
+    warnings_as_errors: bool,
+    // Accept and decode the undocumented comp bit patterns
+    extended: bool,
+    // Write relocatable object files, or link them
+    compile: bool,
+    link: bool,
+    disassemble: bool,
+    labels: bool,
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        inputs: Vec::new(),
+        output: None,
+        map: false,
+        list: false,
+        optimize: false,
+        format: Format::Hack,
+        lints: LINTS.to_vec(),
+        warnings_as_errors: false,
+        extended: false,
+        compile: false,
+        link: false,
+        disassemble: false,
+        labels: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--map" => options.map = true,
+            "--list" => options.list = true,
+            "-O" => options.optimize = true,
+            "--format" => options.format = Format::from_name(args.next()?)?,
+            "-w" => options.lints.clear(),
+            "-Werror" => options.warnings_as_errors = true,
+            lint if lint.starts_with("-Wno-") => {
+                let lint = Lint::from_name(&lint["-Wno-".len()..])?;
+                options.lints.retain(|&enabled| enabled != lint);
+            }
+            lint if lint.starts_with("-W") => {
+                let lint = Lint::from_name(&lint["-W".len()..])?;
+                if !options.lints.contains(&lint) {
+                    options.lints.push(lint);
+                }
+            }
+            "--extended" => options.extended = true,
+            "-c" => options.compile = true,
+            "--link" => options.link = true,
+            "--disassemble" => options.disassemble = true,
+            "--labels" => options.labels = true,
+            "-o" => options.output = Some(args.next()?.to_string()),
+            input if !input.starts_with('-') => options.inputs.push(input.to_string()),
+            _ => return None,
+        }
     }
+    Some(options).filter(|options| !options.inputs.is_empty())
+}
+
+// Extensions may have several dots, e.g. "be.bin"
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().ends_with(&format!(".{}", ext)))
+}
 
-    let output = format!(
-        "{}.hack",
-        PathBuf::from(&args[1])
-            .file_stem()
-            .unwrap()
-            .to_string_lossy()
-            .into_owned()
+fn get_file_paths<D: Deque<String>>(dir: &str, ext: &str, paths: D) -> D {
+    let mut files = fs::read_dir(dir).map_or(Vec::new(), |entries| {
+        entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && has_extension(path, ext))
+            .collect::<Vec<PathBuf>>()
+    });
+    files.sort();
+    files.iter().fold(paths, |paths, path| {
+        paths.push_back(path.to_string_lossy().into_owned())
+    })
+}
+
+// Collects the input files with extension `ext`, and those of each input directory sorted by name
+fn get_source<D: Deque<String>>(inputs: &[String], ext: &str, paths: D) -> Result<D, String> {
+    inputs.iter().try_fold(paths, |paths, input| {
+        let path = Path::new(input);
+        if path.is_dir() {
+            Ok(get_file_paths(input, ext, paths))
+        } else if has_extension(path, ext) {
+            Ok(paths.push_back(input.clone()))
+        } else {
+            Err(format!("{}: not a .{} file or a directory", input, ext))
+        }
+    })
+}
+
+// Returns the output file of an input with the given extension: beside the input,
+// or in the `-o` directory. A single input may be given the output file name itself.
+fn output_path(input: &str, extension: &str, output: Option<&str>, single: bool) -> String {
+    let path = PathBuf::from(input);
+    let name = format!(
+        "{}.{}",
+        path.file_stem().unwrap().to_string_lossy(),
+        extension
     );
+    match output {
+        Some(output) if single && output.ends_with(&format!(".{}", extension)) => {
+            output.to_string()
+        }
+        Some(output) => Path::new(output).join(name).to_string_lossy().into_owned(),
+        None => path.with_file_name(name).to_string_lossy().into_owned(),
+    }
+}
 
-    const STACK_SIZE: usize = 16 * 1024 * 1024;
-    std::thread::Builder::new()
-        .stack_size(STACK_SIZE)
-        .spawn(move || {
-            let code = BankersDeque::empty();
-            run(args[1].clone(), output, code)
+// Assembles or disassembles every input file, going on after a failure.
+// Returns the errors of all failed files.
+fn run_all(options: Options) -> Result<(), String> {
+    let ext = if options.disassemble {
+        options.format.extension()
+    } else if options.link {
+        OBJECT_EXTENSION
+    } else {
+        "asm"
+    };
+    let paths = get_source(&options.inputs, ext, BankersDeque::<String>::empty())?;
+    if paths.is_empty() {
+        return Err(format!(
+            "no .{} files in {}",
+            ext,
+            options.inputs.join(", ")
+        ));
+    }
+    // All objects are linked into one program, named after the first one by default
+    if options.link {
+        let inputs = paths
+            .iter()
+            .map(|path| path.as_ref().clone())
+            .collect::<Vec<String>>();
+        let output = options
+            .output
+            .clone()
+            .unwrap_or_else(|| output_path(&inputs[0], options.format.extension(), None, true));
+        return run_linker(&inputs, output, options.format);
+    }
+    let single = paths.len() == 1;
+    let output = options.output.as_deref();
+    if let Some(dir) = output.filter(|output| !(single && Path::new(output).extension().is_some()))
+    {
+        fs::create_dir_all(dir).map_err(|error| format!("{}: {}", dir, error))?;
+    }
+    let errors = paths
+        .iter()
+        .filter_map(|input| {
+            let input = input.as_ref().clone();
+            if options.disassemble {
+                let output = output_path(&input, "dis.asm", output, single);
+                run_disassembler(
+                    input,
+                    output,
+                    options.labels,
+                    options.extended,
+                    options.format,
+                )
+                .err()
+            } else if options.compile {
+                let object = output_path(&input, OBJECT_EXTENSION, output, single);
+                run_compiler(input, object, &options).err()
+            } else {
+                let code = output_path(&input, options.format.extension(), output, single);
+                run(input, code, &options).err()
+            }
         })
-        .unwrap()
-        .join()
-        .unwrap();
+        .collect::<Vec<String>>();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    match parse_options(&args[1..]) {
+        Some(options) => {
+            run_all(options).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(1);
+            });
+        }
+        None => {
+            eprintln!(
+                "Usage: {0} [-O] [--extended] [--map] [--list] [--format FORMAT] [-w] [-WLINT] [-Wno-LINT] [-Werror] [-o OUTPUT] <asm file name|dir name>...\n       {0} -c [-O] [--extended] [-o OUTPUT] <asm file name|dir name>...\n       {0} --link [--format FORMAT] [-o OUTPUT] <obj file name|dir name>...\n       {0} --disassemble [--labels] [--extended] [--format FORMAT] [-o OUTPUT] <machine code file name|dir name>...\nFormats: {1}\nLints: {2}",
+                &args[0],
+                FORMATS
+                    .iter()
+                    .map(|format| format.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                LINTS
+                    .iter()
+                    .map(|lint| format!("{} ({})", lint.code(), lint.name()))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+            process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,495 @@
+use crate::assembler::{preprocess_at, variables, Fields, MAX_ADDRESS};
+use crate::error::*;
+use crate::expression::{evaluate, expression, Expression, Operator};
+use crate::instruction::*;
+use parser::parser::*;
+use std::fmt;
+use std::path::Path;
+
+// What the linker adds to a relocated word
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    // The ROM address the module is placed at
+    Code,
+    // The RAM address allocated to a data symbol of the module
+    Data(String),
+    // The value of a symbol exported by another module
+    Import(String),
+}
+
+// A word of the module's code to relocate
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub address: u32,
+    pub target: Target,
+}
+
+// The value of an exported symbol
+#[derive(Debug, Clone, PartialEq)]
+pub enum Export {
+    // A ROM address relative to the start of the module
+    Code(u32),
+    // A data symbol of the module
+    Data,
+    Constant(u32),
+}
+
+// A relocatable module. Relocated words of `code` hold the constant to add to the address
+// of their target, in 16-bit two's complement.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Object {
+    pub module: String,
+    pub exports: Vec<(String, Export)>,
+    pub imports: Vec<String>,
+    // Per-module data symbols in order of first use, allocated by the linker
+    pub data: Vec<String>,
+    pub code: Vec<u16>,
+    pub relocations: Vec<Relocation>,
+}
+
+// Returns whether an expression is a constant or a relocated symbol plus or minus a
+// constant, the only values that stay right wherever the linker moves the symbol.
+// `relocated` tells the symbols the linker moves, and `code` those that move with the
+// module, whose differences are constants.
+fn linear(
+    expression: &Expression,
+    relocated: &dyn Fn(&str) -> bool,
+    code: &dyn Fn(&str) -> bool,
+) -> bool {
+    let symbol = |expression: &Expression| matches!(expression, Expression::Symbol(symbol) if relocated(symbol));
+    let constant = |expression: &Expression| match expression {
+        Expression::Binary(Operator::Subtract, left, right) => match (&**left, &**right) {
+            (Expression::Symbol(left), Expression::Symbol(right)) if code(left) && code(right) => {
+                true
+            }
+            _ => !expression.symbols().into_iter().any(relocated),
+        },
+        _ => !expression.symbols().into_iter().any(relocated),
+    };
+    match expression {
+        _ if constant(expression) || symbol(expression) => true,
+        Expression::Binary(Operator::Add, left, right) => {
+            (symbol(left) && constant(right)) || (constant(left) && symbol(right))
+        }
+        Expression::Binary(Operator::Subtract, left, right) => symbol(left) && constant(right),
+        _ => false,
+    }
+}
+
+// Splits the value of an A-instruction into a constant and at most one target whose address
+// is added to it. Each target is moved by 1 and 2 in turn: an expression that moves with
+// it by the same amount is relocated, one that does not move is constant.
+fn relocate<T, V>(
+    text: &str,
+    expression: &Expression,
+    target: T,
+    value: V,
+) -> Result<(i64, Option<Target>), ErrorKind>
+where
+    T: Fn(&str) -> Option<Target>,
+    V: Fn(&str, Option<&Target>, i64) -> Result<i64, ErrorKind>,
+{
+    let relocated = |symbol: &str| target(symbol).is_some();
+    let code = |symbol: &str| target(symbol) == Some(Target::Code);
+    if !linear(expression, &relocated, &code) {
+        return Err(ErrorKind::NotRelocatable(text.to_string()));
+    }
+    let value_at = |moved: Option<&Target>, offset: i64| {
+        evaluate(expression, &mut |symbol| value(symbol, moved, offset))
+    };
+    let constant = value_at(None, 0)?;
+
+    let mut targets: Vec<Target> = Vec::new();
+    for symbol in expression.symbols() {
+        match target(symbol) {
+            Some(symbol_target) if !targets.contains(&symbol_target) => targets.push(symbol_target),
+            _ => (),
+        }
+    }
+    let mut relocated = None;
+    for symbol_target in targets {
+        let once = value_at(Some(&symbol_target), 1)? - constant;
+        let twice = value_at(Some(&symbol_target), 2)? - constant;
+        match (once, twice) {
+            (0, 0) => (),
+            (1, 2) if relocated.is_none() => relocated = Some(symbol_target),
+            _ => return Err(ErrorKind::NotRelocatable(text.to_string())),
+        }
+    }
+    Ok((constant, relocated))
+}
+
+// Assembles a module into an object. Symbols named by ".import" are left for the linker,
+// as are the variables, which become data symbols of the module. Labels and constants
+// computed from them are relative to the start of the module, the others are absolute.
+pub fn compile(file: &str, lines: &[&str], extended: bool) -> Result<Object, Vec<AssembleError>> {
+    let instruction = instruction();
+    let fields = Fields::new(extended);
+    let instructions = lines
+        .iter()
+        .map(|line| match instruction.parse(line) {
+            Ok(("", instruction)) => Some(instruction),
+            _ => None,
+        })
+        .collect::<Vec<Option<Instruction>>>();
+    let (symbol_table, mut errors) = preprocess_at(file, lines, 0);
+    let (once, _) = preprocess_at(file, lines, 1);
+    let (twice, _) = preprocess_at(file, lines, 2);
+    // Labels and the constants computed from them move with the module
+    let moves = |symbol: &str| {
+        let symbol = symbol.to_string();
+        symbol_table.get(&symbol) != once.get(&symbol)
+    };
+
+    let mut relative = Vec::new();
+    let mut imports: Vec<String> = Vec::new();
+    let mut exports = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        let error =
+            |text: &str, kind| AssembleError::new(file, index + 1, lines[index], text, kind);
+        match instruction {
+            Some(Instruction::L(label)) => relative.push(label.clone()),
+            Some(Instruction::Equ(name, value)) => {
+                let values = (symbol_table.get(name), once.get(name), twice.get(name));
+                let linear = match expression().parse(value) {
+                    Ok((_, expression)) => linear(&expression, &moves, &moves),
+                    Err(_) => true,
+                };
+                match values {
+                    (Some(_), _, _) if !linear => {
+                        errors.push(error(value, ErrorKind::NotRelocatable(value.clone())))
+                    }
+                    (Some(&value), Some(&once), Some(&twice))
+                        if once == value && twice == value => {}
+                    (Some(&value), Some(&once), Some(&twice))
+                        if once == value + 1 && twice == value + 2 =>
+                    {
+                        relative.push(name.clone())
+                    }
+                    (Some(_), _, _) => {
+                        errors.push(error(value, ErrorKind::NotRelocatable(value.clone())))
+                    }
+                    // Reported by preprocess
+                    _ => (),
+                }
+            }
+            Some(Instruction::Import(name)) => {
+                if symbol_table.get(name).is_some() {
+                    errors.push(error(name, ErrorKind::DuplicateSymbol(name.clone())));
+                } else if !imports.contains(name) {
+                    imports.push(name.clone());
+                }
+            }
+            Some(Instruction::Export(name)) => exports.push((name.clone(), index)),
+            _ => (),
+        }
+    }
+
+    let mut data = variables(lines)
+        .into_iter()
+        .map(|(variable, _)| variable)
+        .filter(|variable| !imports.contains(variable))
+        .collect::<Vec<String>>();
+    let mut object_exports: Vec<(String, Export)> = Vec::new();
+    for (name, index) in exports {
+        let export = if imports.contains(&name) {
+            errors.push(AssembleError::new(
+                file,
+                index + 1,
+                lines[index],
+                &name,
+                ErrorKind::DuplicateSymbol(name.clone()),
+            ));
+            continue;
+        } else if relative.contains(&name) {
+            Export::Code(*symbol_table.get(&name).unwrap())
+        } else if let Some(&value) = symbol_table.get(&name) {
+            Export::Constant(value)
+        } else {
+            if !data.contains(&name) {
+                data.push(name.clone());
+            }
+            Export::Data
+        };
+        if !object_exports.iter().any(|(exported, _)| *exported == name) {
+            object_exports.push((name, export));
+        }
+    }
+
+    let target = |symbol: &str| {
+        let symbol = symbol.to_string();
+        if imports.contains(&symbol) {
+            Some(Target::Import(symbol))
+        } else if data.contains(&symbol) {
+            Some(Target::Data(symbol))
+        } else if relative.contains(&symbol) {
+            Some(Target::Code)
+        } else {
+            None
+        }
+    };
+    // The value of a symbol with `moved` moved by `offset`. Data and imports are at 0.
+    let value = |symbol: &str, moved: Option<&Target>, offset: i64| {
+        let symbol_target = target(symbol);
+        let offset = if symbol_target.is_some() && symbol_target.as_ref() == moved {
+            offset
+        } else {
+            0
+        };
+        match symbol_target {
+            Some(Target::Import(_)) | Some(Target::Data(_)) => Ok(offset),
+            _ => symbol_table
+                .get(&symbol.to_string())
+                .map(|&value| value as i64 + offset)
+                .ok_or_else(|| ErrorKind::UndefinedSymbol(symbol.to_string())),
+        }
+    };
+
+    let mut code = Vec::new();
+    let mut relocations = Vec::new();
+    for (index, instruction) in instructions.into_iter().enumerate() {
+        let line = lines[index];
+        match instruction {
+            Some(Instruction::A(text)) => {
+                let error = |kind| AssembleError::new(file, index + 1, line, &text, kind);
+                let relocated = match expression().parse(&text) {
+                    Ok((_, expression)) => relocate(&text, &expression, target, value),
+                    Err(_) => Err(ErrorKind::InvalidInstruction),
+                };
+                match relocated {
+                    Ok((constant, None)) if (0..=MAX_ADDRESS as i64).contains(&constant) => {
+                        code.push(constant as u16)
+                    }
+                    Ok((constant, Some(target))) if i16::try_from(constant).is_ok() => {
+                        relocations.push(Relocation {
+                            address: code.len() as u32,
+                            target,
+                        });
+                        code.push(constant as i16 as u16);
+                    }
+                    Ok((constant, _)) => {
+                        errors.push(error(ErrorKind::AddressOutOfRange(constant.to_string())))
+                    }
+                    Err(kind) => errors.push(error(kind)),
+                }
+            }
+            Some(Instruction::C(dest, comp, jump)) => match fields.encode(&dest, &comp, &jump) {
+                Ok(word) => code.push(word),
+                Err(field_errors) => errors.extend(
+                    field_errors
+                        .into_iter()
+                        .map(|(text, kind)| AssembleError::new(file, index + 1, line, &text, kind)),
+                ),
+            },
+            _ => (),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Object {
+            module: Path::new(file)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            exports: object_exports,
+            imports,
+            data,
+            code,
+            relocations,
+        })
+    } else {
+        errors.sort_by_key(|error| (error.line, error.column));
+        Err(errors)
+    }
+}
+
+// Sections of tab-separated records, one per line:
+// [module] name, [exports] "name code offset", "name data" or "name constant value",
+// [imports] name, [data] name, [code] binary word,
+// [relocations] "address code", "address data name" or "address import name"
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[module]\n{}", self.module)?;
+        writeln!(f, "[exports]")?;
+        for (name, export) in &self.exports {
+            match export {
+                Export::Code(offset) => writeln!(f, "{}\tcode\t{}", name, offset)?,
+                Export::Data => writeln!(f, "{}\tdata", name)?,
+                Export::Constant(value) => writeln!(f, "{}\tconstant\t{}", name, value)?,
+            }
+        }
+        writeln!(f, "[imports]")?;
+        for name in &self.imports {
+            writeln!(f, "{}", name)?;
+        }
+        writeln!(f, "[data]")?;
+        for name in &self.data {
+            writeln!(f, "{}", name)?;
+        }
+        writeln!(f, "[code]")?;
+        for word in &self.code {
+            writeln!(f, "{:016b}", word)?;
+        }
+        writeln!(f, "[relocations]")?;
+        for relocation in &self.relocations {
+            match &relocation.target {
+                Target::Code => writeln!(f, "{}\tcode", relocation.address)?,
+                Target::Data(name) => writeln!(f, "{}\tdata\t{}", relocation.address, name)?,
+                Target::Import(name) => writeln!(f, "{}\timport\t{}", relocation.address, name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads an object file as written by Display. Errors give the line.
+pub fn parse(content: &str) -> Result<Object, String> {
+    let mut object = Object::default();
+    let mut section = "";
+    for (index, line) in content.lines().enumerate() {
+        let error = || {
+            format!(
+                "line {}: invalid record `{}` in [{}]",
+                index + 1,
+                line,
+                section
+            )
+        };
+        let number = |text: &str| text.parse::<u32>().map_err(|_| error());
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = match name {
+                "module" | "exports" | "imports" | "data" | "code" | "relocations" => name,
+                _ => return Err(format!("line {}: unknown section `{}`", index + 1, line)),
+            };
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        match (section, &fields[..]) {
+            ("module", [name]) => object.module = name.to_string(),
+            ("exports", [name, "code", offset]) => object
+                .exports
+                .push((name.to_string(), Export::Code(number(offset)?))),
+            ("exports", [name, "data"]) => object.exports.push((name.to_string(), Export::Data)),
+            ("exports", [name, "constant", value]) => object
+                .exports
+                .push((name.to_string(), Export::Constant(number(value)?))),
+            ("imports", [name]) => object.imports.push(name.to_string()),
+            ("data", [name]) => object.data.push(name.to_string()),
+            ("code", [word]) if word.len() == 16 => object
+                .code
+                .push(u16::from_str_radix(word, 2).map_err(|_| error())?),
+            ("relocations", [address, kind, rest @ ..]) => {
+                let address = number(address)?;
+                if address as usize >= object.code.len() {
+                    return Err(error());
+                }
+                let target = match (*kind, rest) {
+                    ("code", []) => Target::Code,
+                    ("data", [name]) => Target::Data(name.to_string()),
+                    ("import", [name]) => Target::Import(name.to_string()),
+                    _ => return Err(error()),
+                };
+                object.relocations.push(Relocation { address, target });
+            }
+            _ => return Err(error()),
+        }
+    }
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_module() {
+        let lines = [
+            ".export mult",
+            ".export result",
+            ".export WIDTH",
+            ".import add",
+            ".equ WIDTH 16",
+            ".equ AFTER LOOP + 1",
+            "(mult)",
+            "@i",
+            "(LOOP)",
+            "@AFTER",
+            "@add - 1",
+            "@result",
+            "@END - mult",
+            "@SCREEN",
+            "(END)",
+        ];
+        let object = compile("lib/Math.asm", &lines, false).unwrap();
+        assert_eq!(
+            Object {
+                module: "Math".to_string(),
+                exports: vec![
+                    ("mult".to_string(), Export::Code(0)),
+                    ("result".to_string(), Export::Data),
+                    ("WIDTH".to_string(), Export::Constant(16)),
+                ],
+                imports: vec!["add".to_string()],
+                data: vec!["i".to_string(), "result".to_string()],
+                code: vec![0, 2, 0xFFFF, 0, 6, 16384],
+                relocations: vec![
+                    Relocation {
+                        address: 0,
+                        target: Target::Data("i".to_string()),
+                    },
+                    Relocation {
+                        address: 1,
+                        target: Target::Code,
+                    },
+                    Relocation {
+                        address: 2,
+                        target: Target::Import("add".to_string()),
+                    },
+                    Relocation {
+                        address: 3,
+                        target: Target::Data("result".to_string()),
+                    },
+                ],
+            },
+            object
+        );
+        assert_eq!(Ok(object.clone()), parse(&object.to_string()));
+    }
+
+    #[test]
+    fn report_module_errors() {
+        let lines = [
+            ".import LOOP",
+            ".import x",
+            ".export x",
+            ".equ TWICE LOOP * 2",
+            "(LOOP)",
+            "@x + y",
+            "@LOOP & 1",
+        ];
+        assert_eq!(
+            vec![
+                (1, ErrorKind::DuplicateSymbol("LOOP".to_string())),
+                (3, ErrorKind::DuplicateSymbol("x".to_string())),
+                (4, ErrorKind::NotRelocatable("LOOP * 2".to_string())),
+                (6, ErrorKind::NotRelocatable("x + y".to_string())),
+                (7, ErrorKind::NotRelocatable("LOOP & 1".to_string())),
+            ],
+            compile("Test.asm", &lines, false)
+                .unwrap_err()
+                .into_iter()
+                .map(|error| (error.line, error.kind))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Err("line 2: invalid record `x\tcode` in [exports]".to_string()),
+            parse("[exports]\nx\tcode\n")
+        );
+    }
+}
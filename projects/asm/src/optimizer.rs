@@ -0,0 +1,457 @@
+use crate::expression::{expression, symbol, Expression};
+use crate::instruction::*;
+use crate::macros::{Expansion, Origin};
+use crate::translation::symbol_table;
+use collections::hashmap::HashMap;
+use parser::parser::*;
+
+// A line of the program. `instruction` is set for labels, A- and C-instructions,
+// the only lines the passes look at.
+struct Line {
+    text: String,
+    origin: Origin,
+    instruction: Option<Instruction>,
+    removed: bool,
+}
+
+// Returns the indices of the labels and instructions that have not been removed
+fn code(lines: &[Line]) -> Vec<usize> {
+    (0..lines.len())
+        .filter(|&index| !lines[index].removed && lines[index].instruction.is_some())
+        .collect()
+}
+
+fn remove(line: &mut Line) {
+    line.removed = true;
+    line.instruction = None;
+}
+
+// Removes the instructions of each occurrence of `pattern` except those at the indices in `keep`.
+// The instructions of an occurrence are consecutive, with no label between them.
+fn replace(lines: &mut [Line], pattern: &[&str], keep: &[usize]) -> bool {
+    let code = code(lines);
+    let mut changed = false;
+    let mut start = 0;
+    while start + pattern.len() <= code.len() {
+        let window = &code[start..start + pattern.len()];
+        let found = window.iter().zip(pattern).all(|(&index, &text)| {
+            lines[index]
+                .instruction
+                .as_ref()
+                .is_some_and(|instruction| instruction.to_string() == text)
+        });
+        if found {
+            for (offset, &index) in window.iter().enumerate() {
+                if !keep.contains(&offset) {
+                    remove(&mut lines[index]);
+                }
+            }
+            changed = true;
+            start += pattern.len();
+        } else {
+            start += 1;
+        }
+    }
+    changed
+}
+
+// A push immediately followed by a pop, as emitted by the VM translator:
+// incrementing then decrementing SP is a no-op, and the popped value is already in D
+fn push_pop(lines: &mut [Line]) -> bool {
+    let counter = replace(lines, &["@SP", "M=M+1", "@SP", "M=M-1"], &[0]);
+    let reload = replace(
+        lines,
+        &["@SP", "A=M", "M=D", "@SP", "A=M", "D=M"],
+        &[0, 1, 2],
+    );
+    counter || reload
+}
+
+// An A-instruction immediately followed by another one is never used
+fn dead_loads(lines: &mut [Line]) -> bool {
+    let code = code(lines);
+    let mut changed = false;
+    for pair in code.windows(2) {
+        if let (Some(Instruction::A(_)), Some(Instruction::A(_))) =
+            (&lines[pair[0]].instruction, &lines[pair[1]].instruction)
+        {
+            remove(&mut lines[pair[0]]);
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Instructions after an unconditional jump are never executed, up to the next label
+fn unreachable(lines: &mut [Line]) -> bool {
+    let mut changed = false;
+    let mut reachable = true;
+    for index in code(lines) {
+        match &lines[index].instruction {
+            Some(Instruction::L(_)) => reachable = true,
+            Some(Instruction::C(_, _, Some(jump))) if reachable && jump == "JMP" => {
+                reachable = false
+            }
+            _ if !reachable => {
+                remove(&mut lines[index]);
+                changed = true;
+            }
+            _ => (),
+        }
+    }
+    changed
+}
+
+// Returns the label an A-instruction loads, if it is a plain symbol
+fn label(instruction: &Option<Instruction>) -> Option<&String> {
+    match instruction {
+        Some(Instruction::A(value)) if matches!(symbol(value), Ok(("", _))) => Some(value),
+        _ => None,
+    }
+}
+
+// A jump to a label whose first instructions are "@TARGET" and an unconditional jump
+// goes to TARGET directly. A conditional jump is only redirected if the next instruction
+// loads A, since A holds the new target when the jump is not taken, and no jump is
+// redirected if its instruction reads or writes A or M.
+fn jump_chains(lines: &mut [Line]) -> bool {
+    let code = code(lines);
+    let instruction = |position: usize| {
+        code.get(position)
+            .and_then(|&index| lines[index].instruction.as_ref())
+    };
+
+    let jumps = (0..code.len()).fold(HashMap::new(), |jumps, position| {
+        let Some(Instruction::L(name)) = instruction(position) else {
+            return jumps;
+        };
+        let next = (position..code.len())
+            .find(|&next| !matches!(instruction(next), Some(Instruction::L(_))))
+            .unwrap_or(code.len());
+        match (
+            code.get(next)
+                .and_then(|&index| label(&lines[index].instruction)),
+            instruction(next + 1),
+        ) {
+            (Some(target), Some(Instruction::C(None, _, Some(jump)))) if jump == "JMP" => {
+                jumps.insert(name.clone(), target.clone())
+            }
+            _ => jumps,
+        }
+    });
+
+    let mut retargets = Vec::new();
+    for position in 0..code.len() {
+        let (Some(name), Some(Instruction::C(dest, comp, Some(jump)))) = (
+            label(&lines[code[position]].instruction),
+            instruction(position + 1),
+        ) else {
+            continue;
+        };
+        // A is also the address of M, and an input of the computation
+        let addresses = |text: &str| text.contains('A') || text.contains('M');
+        if addresses(comp) || dest.as_deref().is_some_and(addresses) {
+            continue;
+        }
+        if jump != "JMP" && !matches!(instruction(position + 2), Some(Instruction::A(_))) {
+            continue;
+        }
+        let mut chain = vec![name.clone()];
+        while let Some(next) = jumps.get(chain.last().unwrap()) {
+            if chain.contains(next) {
+                break;
+            }
+            chain.push(next.clone());
+        }
+        if chain.len() > 1 {
+            retargets.push((code[position], name.clone(), chain.pop().unwrap()));
+        }
+    }
+
+    let changed = !retargets.is_empty();
+    for (index, name, target) in retargets {
+        let line = &mut lines[index];
+        line.text = line
+            .text
+            .replacen(&format!("@{}", name), &format!("@{}", target), 1);
+        line.instruction = Some(Instruction::A(target));
+    }
+    changed
+}
+
+// Returns whether the program computes an address from a label, as in "@LOOP+2", or jumps
+// to a numeric address, as in "@3 0;JMP": removing instructions would make either point
+// elsewhere. Constants defined from labels count as labels.
+fn computed_addresses(lines: &[Line], constants: &[(String, String)]) -> bool {
+    let mut labels = lines
+        .iter()
+        .filter_map(|line| match &line.instruction {
+            Some(Instruction::L(name)) => Some(name.clone()),
+            _ => None,
+        })
+        .collect::<Vec<String>>();
+    let refers = |text: &str, labels: &[String]| match expression().parse(text) {
+        Ok((_, expression)) => expression
+            .symbols()
+            .iter()
+            .any(|symbol| labels.iter().any(|label| label == symbol)),
+        Err(_) => false,
+    };
+    while let Some((name, _)) = constants
+        .iter()
+        .find(|(name, value)| !labels.contains(name) && refers(value, &labels))
+    {
+        labels.push(name.clone());
+    }
+
+    let mut values = lines
+        .iter()
+        .filter_map(|line| match &line.instruction {
+            Some(Instruction::A(value)) => Some(value.as_str()),
+            _ => None,
+        })
+        .chain(constants.iter().map(|(_, value)| value.as_str()));
+    let arithmetic = values.any(|value| {
+        let bare = matches!(
+            expression().parse(value),
+            Ok(("", Expression::Symbol(_) | Expression::Number(_)))
+        );
+        !bare && refers(value, &labels)
+    });
+
+    // Made of numbers, predefined symbols and constants not defined from labels
+    let predefined = symbol_table();
+    let numeric = |value: &str| match expression().parse(value) {
+        Ok(("", expression)) => expression.symbols().iter().all(|&symbol| {
+            predefined.get(&symbol.to_string()).is_some()
+                || (!labels.iter().any(|label| label == symbol)
+                    && constants.iter().any(|(name, _)| name == symbol))
+        }),
+        _ => false,
+    };
+    let code = code(lines);
+    let numeric_jump = code.windows(2).any(|pair| {
+        match (&lines[pair[0]].instruction, &lines[pair[1]].instruction) {
+            (Some(Instruction::A(value)), Some(Instruction::C(_, _, Some(_)))) => numeric(value),
+            _ => false,
+        }
+    });
+    arithmetic || numeric_jump
+}
+
+// Runs the peephole passes until none of them changes the program. Removed instructions
+// are dropped with their lines, so that the origins of the remaining lines stay correct,
+// and labels are kept. Jumps are assumed to go to labels only, so a program with label
+// arithmetic or jumps to numeric addresses is returned unchanged, as is a program with
+// invalid lines, for `translate` to report them.
+pub fn optimize(expansion: &Expansion) -> Expansion {
+    let instruction = instruction();
+    let mut lines = Vec::new();
+    let mut constants = Vec::new();
+    for (text, origin) in expansion.lines.iter().zip(&expansion.origins) {
+        let instruction = match instruction.parse(text) {
+            Ok((
+                "",
+                instruction @ (Instruction::A(_) | Instruction::C(_, _, _) | Instruction::L(_)),
+            )) => Some(instruction),
+            Ok(("", Instruction::Equ(name, value))) => {
+                constants.push((name, value));
+                None
+            }
+            Ok(("", Instruction::Export(_) | Instruction::Import(_))) => None,
+            _ if text.split("//").next().unwrap_or("").trim().is_empty() => None,
+            _ => return expansion.clone(),
+        };
+        lines.push(Line {
+            text: text.clone(),
+            origin: origin.clone(),
+            instruction,
+            removed: false,
+        });
+    }
+    if computed_addresses(&lines, &constants) {
+        return expansion.clone();
+    }
+
+    loop {
+        let mut changed = false;
+        for pass in [push_pop, dead_loads, unreachable, jump_chains] {
+            changed |= pass(&mut lines);
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let (lines, origins) = lines
+        .into_iter()
+        .filter(|line| !line.removed)
+        .map(|line| (line.text, line.origin))
+        .unzip();
+    Expansion { lines, origins }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimized(lines: &[&str]) -> Vec<String> {
+        let expansion = Expansion {
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+            origins: (1..=lines.len())
+                .map(|line| Origin {
+                    file: "Test.asm".to_string(),
+                    line,
+                })
+                .collect(),
+        };
+        optimize(&expansion).lines
+    }
+
+    #[test]
+    fn remove_push_pop_pairs() {
+        let lines = [
+            "// push constant 7",
+            "@7",
+            "D=A",
+            "@SP",
+            "A=M",
+            "M=D",
+            "@SP",
+            "M=M+1",
+            "// pop temp 0",
+            "@SP",
+            "M=M-1",
+            "A=M",
+            "D=M",
+            "@5",
+            "M=D",
+        ];
+        assert_eq!(
+            vec![
+                "// push constant 7",
+                "@7",
+                "D=A",
+                "@SP",
+                "A=M",
+                "M=D",
+                "// pop temp 0",
+                "@5",
+                "M=D"
+            ],
+            optimized(&lines)
+        );
+    }
+
+    #[test]
+    fn remove_unreachable_code() {
+        let lines = ["@END", "0;JMP", "@1", "D=A", "(END)", "@END", "0;JMP", "@2"];
+        assert_eq!(
+            vec!["@END", "0;JMP", "(END)", "@END", "0;JMP"],
+            optimized(&lines)
+        );
+    }
+
+    #[test]
+    fn shorten_jump_chains() {
+        let lines = [
+            "@A // first",
+            "D;JGT",
+            "@B",
+            "0;JMP",
+            "(A)",
+            "(C)",
+            "@B",
+            "0;JMP",
+            "(B)",
+            "@A",
+            "D;JEQ",
+            "D=0",
+        ];
+        assert_eq!(
+            vec![
+                "@B // first",
+                "D;JGT",
+                "@B",
+                "0;JMP",
+                "(A)",
+                "(C)",
+                "@B",
+                "0;JMP",
+                "(B)",
+                "@A",
+                "D;JEQ",
+                "D=0"
+            ],
+            optimized(&lines)
+        );
+    }
+
+    #[test]
+    fn keep_programs_with_label_arithmetic() {
+        let lines = [
+            "@SKIP+2", "0;JMP", "(SKIP)", "@SP", "M=M+1", "@SP", "M=M-1", "@5", "D=A", "@0", "M=D",
+        ];
+        assert_eq!(lines.to_vec(), optimized(&lines));
+        let lines = [
+            ".equ AFTER SKIP + 2",
+            "@AFTER",
+            "0;JMP",
+            "(SKIP)",
+            "@SP",
+            "M=M+1",
+            "@SP",
+            "M=M-1",
+        ];
+        assert_eq!(lines.to_vec(), optimized(&lines));
+        // Bare labels and arithmetic on other symbols are fine
+        let lines = [
+            "@SCREEN+1",
+            "D=A",
+            "@SKIP",
+            "0;JMP",
+            "(SKIP)",
+            "@SP",
+            "M=M+1",
+            "@SP",
+            "M=M-1",
+        ];
+        assert_eq!(
+            vec!["@SCREEN+1", "D=A", "@SKIP", "0;JMP", "(SKIP)", "@SP"],
+            optimized(&lines)
+        );
+    }
+
+    #[test]
+    fn keep_programs_with_numeric_jumps() {
+        let lines = [
+            "@3", "0;JMP", "@7", "D=A", "@0", "M=D", "(END)", "@END", "0;JMP",
+        ];
+        assert_eq!(lines.to_vec(), optimized(&lines));
+        let lines = [
+            ".equ START 0",
+            "@START",
+            "D;JGT",
+            "@SP",
+            "M=M+1",
+            "@SP",
+            "M=M-1",
+        ];
+        assert_eq!(lines.to_vec(), optimized(&lines));
+    }
+
+    #[test]
+    fn keep_jumps_using_their_address() {
+        let lines = [
+            "@A", "D=M;JGT", "@1", "D=A", "@A", "M=D;JMP", "(A)", "@B", "0;JMP", "(B)", "@A",
+            "D;JEQ", "D=0",
+        ];
+        assert_eq!(lines.to_vec(), optimized(&lines));
+    }
+
+    #[test]
+    fn keep_invalid_programs() {
+        let lines = ["@1", "@2", "%"];
+        assert_eq!(vec!["@1", "@2", "%"], optimized(&lines));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::instruction::*;
+use collections::hashmap::HashMap;
+use parser::parser::*;
+use std::fmt;
+
+// Source location of the instruction stored at a ROM address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub address: u32,
+    pub file: String,
+    pub line: usize,
+    pub comment: Option<String>,
+}
+
+// Maps every ROM address back to the line that produced it,
+// and every label to the ROM address it refers to
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap {
+    pub entries: Vec<Entry>,
+    pub labels: Vec<(String, u32)>,
+}
+
+// Returns the text of the comment in a line, if any
+fn comment(line: &str) -> Option<String> {
+    line.find("//")
+        .map(|index| line[index + 2..].trim().to_string())
+        .filter(|comment| !comment.is_empty())
+}
+
+pub fn source_map(file: &str, lines: &[&str], symbol_table: &HashMap<String, u32>) -> SourceMap {
+    let instruction = instruction();
+
+    let (_, _, source_map) = lines.iter().enumerate().fold(
+        (0, None, SourceMap::default()),
+        |(address, last_comment, mut source_map), (index, &line)| {
+            let last_comment = comment(line).or(last_comment);
+            match instruction.parse(line) {
+                Ok(("", Instruction::L(label))) => {
+                    if let Some(&label_address) = symbol_table.get(&label) {
+                        source_map.labels.push((label, label_address));
+                    }
+                    (address, last_comment, source_map)
+                }
+                Ok(("", Instruction::A(_))) | Ok(("", Instruction::C(_, _, _))) => {
+                    source_map.entries.push(Entry {
+                        address,
+                        file: file.to_string(),
+                        line: index + 1,
+                        comment: last_comment.clone(),
+                    });
+                    (address + 1, last_comment, source_map)
+                }
+                _ => (address, last_comment, source_map),
+            }
+        },
+    );
+
+    source_map
+}
+
+// Two sections of tab-separated records, one per line: "[rom]" with "address file line
+// comment" for every ROM address, then "[labels]" with "label address" for every label
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[rom]")?;
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}",
+                entry.address,
+                entry.file,
+                entry.line,
+                entry.comment.as_deref().unwrap_or("")
+            )?;
+        }
+        writeln!(f, "[labels]")?;
+        for (label, address) in &self.labels {
+            writeln!(f, "{}\t{}", label, address)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collections::Empty;
+
+    #[test]
+    fn map_addresses_to_lines() {
+        let lines = [
+            "// push constant 7",
+            "@7",
+            "D=A // load",
+            "(LOOP)",
+            "@LOOP",
+            "0;JMP",
+        ];
+        let symbol_table = HashMap::empty().insert("LOOP".to_string(), 2);
+        let source_map = source_map("Test.asm", &lines, &symbol_table);
+
+        assert_eq!(
+            vec![
+                (0, 2, Some("push constant 7".to_string())),
+                (1, 3, Some("load".to_string())),
+                (2, 5, Some("load".to_string())),
+                (3, 6, Some("load".to_string())),
+            ],
+            source_map
+                .entries
+                .iter()
+                .map(|entry| (entry.address, entry.line, entry.comment.clone()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![("LOOP".to_string(), 2)], source_map.labels);
+        assert_eq!(
+            "[rom]\n0\tTest.asm\t2\tpush constant 7\n1\tTest.asm\t3\tload\n2\tTest.asm\t5\tload\n3\tTest.asm\t6\tload\n[labels]\nLOOP\t2\n",
+            source_map.to_string()
+        );
+    }
+}
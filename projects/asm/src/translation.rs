@@ -1,4 +1,4 @@
-use collections::{Empty, hashmap::HashMap};
+use collections::{hashmap::HashMap, Empty};
 
 pub fn symbol_table() -> HashMap<String, u32> {
     HashMap::empty()
@@ -27,56 +27,248 @@ pub fn symbol_table() -> HashMap<String, u32> {
         .insert("KBD".to_string(), 24576)
 }
 
-pub fn comp_table() -> HashMap<&'static str, &'static str> {
-    HashMap::empty()
-        .insert("0", "0101010")
-        .insert("1", "0111111")
-        .insert("-1", "0111010")
-        .insert("D", "0001100")
-        .insert("A", "0110000")
-        .insert("!D", "0001101")
-        .insert("!A", "0110001")
-        .insert("-D", "0001111")
-        .insert("-A", "0110011")
-        .insert("D+1", "0011111")
-        .insert("A+1", "0110111")
-        .insert("D-1", "0001110")
-        .insert("A-1", "0110010")
-        .insert("D+A", "0000010")
-        .insert("D-A", "0010011")
-        .insert("A-D", "0000111")
-        .insert("D&A", "0000000")
-        .insert("D|A", "0010101")
-        .insert("M", "1110000")
-        .insert("!M", "1110001")
-        .insert("M+1", "1110111")
-        .insert("M-1", "1110010")
-        .insert("D+M", "1000010")
-        .insert("D-M", "1010011")
-        .insert("M-D", "1000111")
-        .insert("D&M", "1000000")
-        .insert("D|M", "1010101")
+// Returns a table from mnemonics to bits
+fn table(pairs: &[(&'static str, &'static str)]) -> HashMap<&'static str, &'static str> {
+    pairs
+        .iter()
+        .fold(HashMap::empty(), |table, &(mnemonic, bits)| {
+            table.insert(mnemonic, bits)
+        })
+}
+
+// Returns a table from bits to the first mnemonic that produces them
+fn inverse_table(pairs: &[(&'static str, &'static str)]) -> HashMap<&'static str, &'static str> {
+    pairs
+        .iter()
+        .fold(HashMap::empty(), |table, &(mnemonic, bits)| {
+            if table.get(&bits).is_some() {
+                table
+            } else {
+                table.insert(bits, mnemonic)
+            }
+        })
+}
+
+// Mnemonics of the comp field and their a-c1..c6 bits
+pub const COMP: [(&str, &str); 28] = [
+    ("0", "0101010"),
+    ("1", "0111111"),
+    ("-1", "0111010"),
+    ("D", "0001100"),
+    ("A", "0110000"),
+    ("!D", "0001101"),
+    ("!A", "0110001"),
+    ("-D", "0001111"),
+    ("-A", "0110011"),
+    ("D+1", "0011111"),
+    ("A+1", "0110111"),
+    ("D-1", "0001110"),
+    ("A-1", "0110010"),
+    ("D+A", "0000010"),
+    ("D-A", "0010011"),
+    ("A-D", "0000111"),
+    ("D&A", "0000000"),
+    ("D|A", "0010101"),
+    ("M", "1110000"),
+    ("!M", "1110001"),
+    ("-M", "1110011"),
+    ("M+1", "1110111"),
+    ("M-1", "1110010"),
+    ("D+M", "1000010"),
+    ("D-M", "1010011"),
+    ("M-D", "1000111"),
+    ("D&M", "1000000"),
+    ("D|M", "1010101"),
+];
+
+// Mnemonics of the undocumented comp bit patterns that compute functions missing from COMP,
+// accepted in extended mode
+pub const EXTENDED_COMP: [(&str, &str); 26] = [
+    ("-2", "0111110"),
+    ("-D-2", "0011110"),
+    ("-A-2", "0110110"),
+    ("D+A+1", "0010111"),
+    ("D-A-1", "0000110"),
+    ("A-D-1", "0010010"),
+    ("-D-A-1", "0000011"),
+    ("-D-A-2", "0010110"),
+    ("D&!A", "0000100"),
+    ("!D&A", "0010000"),
+    ("!D&!A", "0010100"),
+    ("!D|!A", "0000001"),
+    ("D|!A", "0010001"),
+    ("!D|A", "0000101"),
+    ("-M-2", "1110110"),
+    ("D+M+1", "1010111"),
+    ("D-M-1", "1000110"),
+    ("M-D-1", "1010010"),
+    ("-D-M-1", "1000011"),
+    ("-D-M-2", "1010110"),
+    ("D&!M", "1000100"),
+    ("!D&M", "1010000"),
+    ("!D&!M", "1010100"),
+    ("!D|!M", "1000001"),
+    ("D|!M", "1010001"),
+    ("!D|M", "1000101"),
+];
+
+// Returns the comp table, with the mnemonics of EXTENDED_COMP in extended mode
+pub fn comp_table(extended: bool) -> HashMap<&'static str, &'static str> {
+    if extended {
+        table(&[&COMP[..], &EXTENDED_COMP[..]].concat())
+    } else {
+        table(&COMP)
+    }
 }
 
+// Returns the comp with the operands of its only +, & or | swapped, e.g. "A+D" for "D+A"
+pub fn commuted(comp: &str) -> Option<String> {
+    match comp.match_indices(['+', '&', '|']).collect::<Vec<_>>()[..] {
+        [(index, operator)] if index > 0 => Some(format!(
+            "{}{}{}",
+            &comp[index + 1..],
+            operator,
+            &comp[..index]
+        )),
+        _ => None,
+    }
+}
+
+// Returns the dest with its registers in the order A, D, M, e.g. "AM" for "MA",
+// or None if a register is unknown or repeated
+pub fn ordered_dest(dest: &str) -> Option<String> {
+    let ordered = "ADM"
+        .chars()
+        .filter(|&register| dest.contains(register))
+        .collect::<String>();
+    Some(ordered).filter(|ordered| ordered.len() == dest.len())
+}
+
+// Computes the Hack ALU output of the c1..c6 control bits
+pub fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let x = if control & 0b100000 != 0 { 0 } else { x };
+    let x = if control & 0b010000 != 0 { !x } else { x };
+    let y = if control & 0b001000 != 0 { 0 } else { y };
+    let y = if control & 0b000100 != 0 { !y } else { y };
+    let out = if control & 0b000010 != 0 {
+        x.wrapping_add(y)
+    } else {
+        x & y
+    };
+    if control & 0b000001 != 0 {
+        !out
+    } else {
+        out
+    }
+}
+
+// Mnemonics of the dest field and their d1..d3 bits.
+// The first spelling of each bit pattern is the canonical one. Other permutations of the
+// registers are looked up in the order of ordered_dest.
+pub const DEST: [(&str, &str); 8] = [
+    ("M", "001"),
+    ("D", "010"),
+    ("MD", "011"),
+    ("DM", "011"),
+    ("A", "100"),
+    ("AM", "101"),
+    ("AD", "110"),
+    ("ADM", "111"),
+];
+
 pub fn dest_table() -> HashMap<&'static str, &'static str> {
-    HashMap::empty()
-        .insert("M", "001")
-        .insert("D", "010")
-        .insert("MD", "011")
-        .insert("DM", "011")
-        .insert("A", "100")
-        .insert("AM", "101")
-        .insert("AD", "110")
-        .insert("ADM", "111")
+    table(&DEST)
 }
 
+// Mnemonics of the jump field and their j1..j3 bits
+pub const JUMP: [(&str, &str); 7] = [
+    ("JGT", "001"),
+    ("JEQ", "010"),
+    ("JGE", "011"),
+    ("JLT", "100"),
+    ("JNE", "101"),
+    ("JLE", "110"),
+    ("JMP", "111"),
+];
+
 pub fn jump_table() -> HashMap<&'static str, &'static str> {
-    HashMap::empty()
-        .insert("JGT", "001")
-        .insert("JEQ", "010")
-        .insert("JGE", "011")
-        .insert("JLT", "100")
-        .insert("JNE", "101")
-        .insert("JLE", "110")
-        .insert("JMP", "111")
+    table(&JUMP)
+}
+
+pub fn comp_mnemonic_table(extended: bool) -> HashMap<&'static str, &'static str> {
+    if extended {
+        inverse_table(&[&COMP[..], &EXTENDED_COMP[..]].concat())
+    } else {
+        inverse_table(&COMP)
+    }
+}
+
+pub fn dest_mnemonic_table() -> HashMap<&'static str, &'static str> {
+    inverse_table(&DEST)
+}
+
+pub fn jump_mnemonic_table() -> HashMap<&'static str, &'static str> {
+    inverse_table(&JUMP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Evaluates a comp mnemonic from left to right, e.g. "-D-A-1"
+    fn evaluate(mnemonic: &str, d: u16, y: u16) -> u16 {
+        let mut chars = mnemonic.chars().peekable();
+        let operand = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let prefix = chars.next_if(|&c| c == '-' || c == '!');
+            let value = match chars.next().unwrap() {
+                'D' => d,
+                'A' | 'M' => y,
+                digit => digit.to_digit(10).unwrap() as u16,
+            };
+            match prefix {
+                Some('-') => value.wrapping_neg(),
+                Some('!') => !value,
+                _ => value,
+            }
+        };
+        let mut value = operand(&mut chars);
+        while let Some(operator) = chars.next() {
+            let right = operand(&mut chars);
+            value = match operator {
+                '+' => value.wrapping_add(right),
+                '-' => value.wrapping_sub(right),
+                '&' => value & right,
+                _ => value | right,
+            };
+        }
+        value
+    }
+
+    #[test]
+    fn compute_alu() {
+        assert_eq!(0, alu(5, 7, 0b101010));
+        assert_eq!(1, alu(5, 7, 0b111111));
+        assert_eq!(0xFFFF, alu(5, 7, 0b111010));
+        assert_eq!(12, alu(5, 7, 0b000010));
+        assert_eq!((-2i16) as u16, alu(5, 7, 0b010011));
+        assert_eq!(2, alu(5, 7, 0b000111));
+        assert_eq!(5 & 7, alu(5, 7, 0b000000));
+        assert_eq!(5 | 7, alu(5, 7, 0b010101));
+    }
+
+    #[test]
+    fn comp_mnemonics() {
+        for &(mnemonic, bits) in COMP.iter().chain(EXTENDED_COMP.iter()) {
+            let control = u16::from_str_radix(&bits[1..], 2).unwrap();
+            for (d, y) in [(0, 0), (1, 2), (0x1234, 0xFF00), (0x8000, 0x7FFF)] {
+                assert_eq!(evaluate(mnemonic, d, y), alu(d, y, control), "{}", mnemonic);
+            }
+            assert_eq!(Some(&mnemonic), comp_mnemonic_table(true).get(&bits));
+        }
+        assert_eq!(Some("A+D".to_string()), commuted("D+A"));
+        assert_eq!(Some("D+1".to_string()), commuted("1+D"));
+        assert_eq!(None, commuted("D+A+1"));
+        assert_eq!(Some("ADM".to_string()), ordered_dest("MDA"));
+        assert_eq!(None, ordered_dest("MM"));
+    }
 }
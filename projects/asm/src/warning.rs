@@ -0,0 +1,288 @@
+use crate::assembler::variables;
+use crate::error::{column, write_diagnostic, ErrorKind};
+use crate::expression::{evaluate, expression, symbol};
+use crate::instruction::*;
+use collections::hashmap::{HashMap, HashSet};
+use parser::parser::*;
+use std::fmt;
+
+// The highest RAM address of the memory map, that of the keyboard
+pub const KBD: i64 = 24576;
+
+// A check of the warnings pass. Codes and names are stable, for -W options and scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    // "@x" before a jump, where x is not a label but a variable
+    JumpToVariable,
+    // A variable named like a label but for its case, e.g. "@loop" and "(LOOP)"
+    LabelCase,
+    // M accessed at an address beyond the keyboard
+    MemoryMap,
+    // A C-instruction that writes A from M and jumps, to the previous value of A. Writing A
+    // from M alone, as in "A=M" or "AM=M-1", is how pointers are followed, so it is not
+    // reported: only with a jump is it a mistake.
+    WriteAReadMJump,
+}
+
+pub const LINTS: [Lint; 4] = [
+    Lint::JumpToVariable,
+    Lint::LabelCase,
+    Lint::MemoryMap,
+    Lint::WriteAReadMJump,
+];
+
+impl Lint {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::JumpToVariable => "W001",
+            Lint::LabelCase => "W002",
+            Lint::MemoryMap => "W003",
+            Lint::WriteAReadMJump => "W004",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::JumpToVariable => "jump-to-variable",
+            Lint::LabelCase => "label-case",
+            Lint::MemoryMap => "memory-map",
+            Lint::WriteAReadMJump => "write-a-read-m-jump",
+        }
+    }
+
+    // Returns the lint of a code or a name, e.g. "W001" or "jump-to-variable"
+    pub fn from_name(name: &str) -> Option<Lint> {
+        LINTS
+            .into_iter()
+            .find(|lint| lint.code() == name || lint.name() == name)
+    }
+}
+
+// A warning located in a source file, like AssembleError
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub source: String,
+    pub lint: Lint,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(
+        file: &str,
+        line: usize,
+        source: &str,
+        text: &str,
+        lint: Lint,
+        message: String,
+    ) -> Self {
+        Warning {
+            file: file.to_string(),
+            line,
+            column: column(source, text),
+            source: source.to_string(),
+            lint,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(
+            f,
+            &self.file,
+            self.line,
+            self.column,
+            &format!("warning[{}]", self.lint.code()),
+            &format!("{} [{}]", self.message, self.lint.name()),
+            &self.source,
+        )
+    }
+}
+
+// Formats all warnings followed by a summary line, which is an error if warnings are errors
+pub fn report(file: &str, warnings: &[Warning], as_errors: bool) -> String {
+    let plural = if warnings.len() == 1 { "" } else { "s" };
+    let summary = if as_errors {
+        format!(
+            "error: could not assemble `{}` due to {} warning{} (-Werror)",
+            file,
+            warnings.len(),
+            plural
+        )
+    } else {
+        format!(
+            "warning: `{}` generated {} warning{}",
+            file,
+            warnings.len(),
+            plural
+        )
+    };
+    warnings
+        .iter()
+        .map(|warning| format!("{warning}\n"))
+        .chain(std::iter::once(summary))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Reports the mistakes that assemble silently in a program that assembles.
+// `symbol_table` is the one returned by translate.
+pub fn check(file: &str, lines: &[&str], symbol_table: &HashMap<String, u32>) -> Vec<Warning> {
+    let instruction = instruction();
+    let variables = variables(lines);
+    let variable_set = variables.iter().fold(HashSet::new(), |set, (variable, _)| {
+        set.insert(variable.clone(), ())
+    });
+    let is_variable = |name: &str| variable_set.get(&name.to_string()).is_some();
+    let value = |text: &str| {
+        let (_, expression) = expression().parse(text).ok()?;
+        evaluate(&expression, &mut |symbol| {
+            symbol_table
+                .get(&symbol.to_string())
+                .map(|&value| value as i64)
+                .ok_or_else(|| ErrorKind::UndefinedSymbol(symbol.to_string()))
+        })
+        .ok()
+    };
+
+    let mut warnings = Vec::new();
+    // Labels by their name in uppercase
+    let mut labels: HashMap<String, String> = HashMap::new();
+    // The A-instruction just before the current line, if any, with its line index
+    let mut load: Option<(usize, String)> = None;
+    for (index, &line) in lines.iter().enumerate() {
+        match instruction.parse(line) {
+            Ok(("", Instruction::A(text))) => load = Some((index, text)),
+            Ok(("", Instruction::C(dest, comp, jump))) => {
+                let dest = dest.unwrap_or_default();
+                if let (Some((load_index, text)), Some(_)) = (&load, &jump) {
+                    if matches!(symbol(text), Ok(("", _))) && is_variable(text) {
+                        warnings.push(Warning::new(
+                            file,
+                            load_index + 1,
+                            lines[*load_index],
+                            text,
+                            Lint::JumpToVariable,
+                            format!("jump to `{}`, which is a variable and not a label", text),
+                        ));
+                    }
+                }
+                if let Some((load_index, text)) = &load {
+                    match value(text) {
+                        Some(address)
+                            if address > KBD && (dest.contains('M') || comp.contains('M')) =>
+                        {
+                            warnings.push(Warning::new(
+                                file,
+                                load_index + 1,
+                                lines[*load_index],
+                                text,
+                                Lint::MemoryMap,
+                                format!(
+                                    "RAM address {} is beyond the memory map (0..={})",
+                                    address, KBD
+                                ),
+                            ))
+                        }
+                        _ => (),
+                    }
+                }
+                if dest.contains('A') && comp.contains('M') && jump.is_some() {
+                    warnings.push(Warning::new(
+                        file,
+                        index + 1,
+                        line,
+                        "",
+                        Lint::WriteAReadMJump,
+                        "writes A while reading M, but jumps to the previous value of A"
+                            .to_string(),
+                    ));
+                }
+                load = None;
+            }
+            Ok(("", Instruction::L(label))) => {
+                labels = labels.insert(label.to_ascii_uppercase(), label);
+                load = None;
+            }
+            _ => (),
+        }
+    }
+
+    for (variable, index) in &variables {
+        if let Some(label) = labels.get(&variable.to_ascii_uppercase()) {
+            warnings.push(Warning::new(
+                file,
+                index + 1,
+                lines[*index],
+                variable,
+                Lint::LabelCase,
+                format!(
+                    "variable `{}` differs from label `{}` only in case",
+                    variable, label
+                ),
+            ));
+        }
+    }
+    warnings.sort_by_key(|warning| (warning.line, warning.column));
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::translate;
+    use collections::deque::*;
+    use collections::Empty;
+
+    fn warnings(lines: &[&str]) -> Vec<(usize, Lint)> {
+        let (symbol_table, _) =
+            translate("Test.asm", lines, false, BankersDeque::<String>::empty()).unwrap();
+        check("Test.asm", lines, &symbol_table)
+            .into_iter()
+            .map(|warning| (warning.line, warning.lint))
+            .collect()
+    }
+
+    #[test]
+    fn check_program() {
+        let lines = [
+            "(LOOP)", "@loop", "D;JGT", "@24577", "M=0", "@SP", "AM=M-1", "A=M", "@KBD", "D=M",
+            "@R13", "A=M;JMP",
+        ];
+        assert_eq!(
+            vec![
+                (2, Lint::JumpToVariable),
+                (2, Lint::LabelCase),
+                (4, Lint::MemoryMap),
+                (12, Lint::WriteAReadMJump),
+            ],
+            warnings(&lines)
+        );
+    }
+
+    #[test]
+    fn format_warning() {
+        let warning = Warning::new(
+            "Test.asm",
+            2,
+            "  A=M;JMP",
+            "",
+            Lint::WriteAReadMJump,
+            "writes A while reading M, but jumps to the previous value of A".to_string(),
+        );
+        assert_eq!(
+            "Test.asm:2:3: warning[W004]: writes A while reading M, but jumps to the previous value of A [write-a-read-m-jump]\n  |\n2 |   A=M;JMP\n  |   ^",
+            warning.to_string()
+        );
+        assert_eq!(Some(Lint::LabelCase), Lint::from_name("W002"));
+        assert_eq!(Some(Lint::LabelCase), Lint::from_name("label-case"));
+        assert_eq!(
+            Some(Lint::WriteAReadMJump),
+            Lint::from_name("write-a-read-m-jump")
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use asm::assembler::translate_into;
+use asm::disassembler::disassemble;
+use asm::format::Format;
+use asm::macros::expand;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The directory of a project of the course, e.g. "06"
+fn project(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(name)
+}
+
+// The .asm files of projects/06, sorted by path
+fn programs() -> Vec<PathBuf> {
+    let mut programs = fs::read_dir(project("06"))
+        .unwrap()
+        .flat_map(|dir| fs::read_dir(dir.unwrap().path()).unwrap())
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "asm"))
+        .collect::<Vec<PathBuf>>();
+    programs.sort();
+    assert!(!programs.is_empty());
+    programs
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
+
+fn assemble_lines(file: &str, lines: &[&str]) -> Vec<u16> {
+    let read = |path: &str| fs::read_to_string(path).map_err(|error| error.to_string());
+    let expansion = expand(file, lines, &read).unwrap();
+    let mut code = Vec::new();
+    translate_into(file, &expansion.lines(), false, &mut |word| code.push(word))
+        .unwrap_or_else(|errors| panic!("{}: {:?}", file, errors));
+    code
+}
+
+fn assemble_file(path: &Path) -> Vec<u16> {
+    let content = fs::read_to_string(path).unwrap();
+    let lines = content.lines().collect::<Vec<&str>>();
+    assemble_lines(&path.to_string_lossy(), &lines)
+}
+
+#[test]
+fn labelled_and_label_less_programs_match() {
+    let programs = programs();
+    let pairs = programs
+        .iter()
+        .filter_map(|labelled| {
+            let label_less = labelled.with_file_name(format!("{}L.asm", stem(labelled)));
+            programs
+                .contains(&label_less)
+                .then_some((labelled, label_less))
+        })
+        .collect::<Vec<(&PathBuf, PathBuf)>>();
+    assert_eq!(3, pairs.len());
+    for (labelled, label_less) in pairs {
+        assert_eq!(
+            assemble_file(labelled),
+            assemble_file(&label_less),
+            "{}",
+            stem(labelled)
+        );
+    }
+}
+
+#[test]
+fn programs_match_known_good_binaries() {
+    let mut compared = 0;
+    for program in programs() {
+        let binary = project("05").join(format!("{}.hack", stem(&program)));
+        if let Ok(content) = fs::read(&binary) {
+            assert_eq!(
+                Format::Hack.decode(&content).unwrap(),
+                assemble_file(&program),
+                "{}",
+                stem(&program)
+            );
+            compared += 1;
+        }
+    }
+    assert_eq!(3, compared);
+}
+
+#[test]
+fn disassembled_programs_reassemble() {
+    for program in programs() {
+        let code = assemble_file(&program);
+        let binary = code
+            .iter()
+            .map(|word| format!("{:016b}", word))
+            .collect::<Vec<String>>();
+        let binary = binary.iter().map(String::as_str).collect::<Vec<&str>>();
+        for labels in [false, true] {
+            let file = format!("{}.hack", stem(&program));
+            let assembly = disassemble(&file, &binary, labels, false).unwrap();
+            let assembly = assembly.iter().map(String::as_str).collect::<Vec<&str>>();
+            assert_eq!(
+                code,
+                assemble_lines(&file, &assembly),
+                "{} (labels: {})",
+                stem(&program),
+                labels
+            );
+        }
+    }
+}
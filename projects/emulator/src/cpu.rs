@@ -0,0 +1,181 @@
+use asm::translation::alu;
+
+pub const RAM_SIZE: usize = 32768;
+pub const ROM_SIZE: usize = 32768;
+pub const SCREEN: u16 = 16384;
+pub const SCREEN_SIZE: usize = 8192;
+pub const KBD: u16 = 24576;
+
+// Why a run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // The program entered an infinite loop on itself, e.g. "(END) @END 0;JMP"
+    Halted,
+    // The program counter left the loaded program
+    EndOfProgram,
+    // The cycle limit was reached
+    CycleLimit,
+}
+
+// Hack CPU with its instruction and data memories
+#[derive(Clone)]
+pub struct Cpu {
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub ram: Vec<u16>,
+    pub rom: Vec<u16>,
+    pub cycles: u64,
+}
+
+impl Cpu {
+    // Creates a CPU with the program loaded at ROM address 0 and cleared RAM
+    pub fn new(program: &[u16]) -> Cpu {
+        Cpu {
+            a: 0,
+            d: 0,
+            pc: 0,
+            ram: vec![0; RAM_SIZE],
+            rom: program[..program.len().min(ROM_SIZE)].to_vec(),
+            cycles: 0,
+        }
+    }
+
+    // Resets the program counter and registers, keeping the memory
+    pub fn reset(&mut self) {
+        self.a = 0;
+        self.d = 0;
+        self.pc = 0;
+        self.cycles = 0;
+    }
+
+    // Returns the instruction at the program counter, or None outside the program
+    pub fn current(&self) -> Option<u16> {
+        self.rom.get(self.pc as usize).copied()
+    }
+
+    pub fn peek(&self, address: u16) -> u16 {
+        self.ram[address as usize % RAM_SIZE]
+    }
+
+    pub fn poke(&mut self, address: u16, value: u16) {
+        self.ram[address as usize % RAM_SIZE] = value;
+    }
+
+    // Sets the key code read from the keyboard register, 0 for no key
+    pub fn set_key(&mut self, key: u16) {
+        self.poke(KBD, key);
+    }
+
+    pub fn screen(&self) -> &[u16] {
+        &self.ram[SCREEN as usize..SCREEN as usize + SCREEN_SIZE]
+    }
+
+    // Returns true if the instruction at the program counter jumps to itself unconditionally,
+    // either directly or through the "@pc-1" instruction before it
+    pub fn is_halted(&self) -> bool {
+        let is_goto =
+            |instruction: u16| instruction & 0xE000 == 0xE000 && instruction & 0x003F == 0x0007;
+        match self.current() {
+            Some(instruction) if is_goto(instruction) => {
+                self.a == self.pc
+                    || (self.pc > 0
+                        && self.a == self.pc - 1
+                        && self.rom.get(self.a as usize) == Some(&self.a))
+            }
+            _ => false,
+        }
+    }
+
+    // Executes one instruction. Returns false if the program counter is outside the program.
+    pub fn step(&mut self) -> bool {
+        let instruction = match self.current() {
+            Some(instruction) => instruction,
+            None => return false,
+        };
+
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc += 1;
+        } else {
+            let y = if instruction & 0x1000 != 0 {
+                self.peek(self.a)
+            } else {
+                self.a
+            };
+            let out = alu(self.d, y, (instruction >> 6) & 0x3F);
+            let address = self.a;
+
+            if instruction & 0x0008 != 0 {
+                self.poke(address, out);
+            }
+            if instruction & 0x0020 != 0 {
+                self.a = out;
+            }
+            if instruction & 0x0010 != 0 {
+                self.d = out;
+            }
+
+            let value = out as i16;
+            let jump = (instruction & 0x0004 != 0 && value < 0)
+                || (instruction & 0x0002 != 0 && value == 0)
+                || (instruction & 0x0001 != 0 && value > 0);
+            self.pc = if jump { address } else { self.pc + 1 };
+        }
+
+        self.cycles += 1;
+        true
+    }
+
+    // Runs until the program halts, leaves the ROM or executes `max_cycles` instructions
+    pub fn run(&mut self, max_cycles: u64) -> Stop {
+        for _ in 0..max_cycles {
+            if self.is_halted() {
+                return Stop::Halted;
+            }
+            if !self.step() {
+                return Stop::EndOfProgram;
+            }
+        }
+        Stop::CycleLimit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::load;
+
+    fn max_program() -> Vec<u16> {
+        let path = format!("{}/../05/Max.hack", env!("CARGO_MANIFEST_DIR"));
+        load(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn run_until_halt() {
+        let mut cpu = Cpu::new(&max_program());
+        cpu.poke(0, 3);
+        cpu.poke(1, 9);
+        assert_eq!(Stop::Halted, cpu.run(1000));
+        assert_eq!(9, cpu.peek(2));
+
+        cpu.reset();
+        cpu.poke(0, (-3i16) as u16);
+        cpu.poke(1, (-9i16) as u16);
+        assert_eq!(Stop::Halted, cpu.run(1000));
+        assert_eq!((-3i16) as u16, cpu.peek(2));
+    }
+
+    #[test]
+    fn stop_at_limits() {
+        // @0, D=A: runs off the end of the program
+        let mut cpu = Cpu::new(&[0, 0b1110110000010000]);
+        assert_eq!(Stop::EndOfProgram, cpu.run(10));
+        assert_eq!(2, cpu.cycles);
+
+        // @1, M=M+1, @0, 0;JMP: loops forever but is not a halt loop
+        let mut cpu = Cpu::new(&[1, 0b1111110111001000, 0, 0b1110101010000111]);
+        assert_eq!(Stop::CycleLimit, cpu.run(40));
+        assert_eq!(10, cpu.peek(1));
+    }
+}
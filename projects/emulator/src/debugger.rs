@@ -0,0 +1,417 @@
+use crate::cpu::*;
+use asm::disassembler::disassemble;
+use asm::translation::symbol_table;
+use collections::hashmap::HashMap;
+
+// Why execution gave control back to the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    // The requested instructions were executed
+    Stepped,
+    Breakpoint(u16),
+    // A watched RAM cell was written
+    Watchpoint { address: u16, old: u16, new: u16 },
+    Stopped(Stop),
+}
+
+// Step debugger over a CPU. Commands are read one line at a time, either typed or
+// from a script:
+//   step [N], next, continue         execute N instructions, step over a jump that
+//                                    comes back (a call of translated VM code), or run
+//   break ADDRESS|LABEL, delete ...  set or remove a breakpoint on a ROM address
+//   watch ADDRESS, unwatch ADDRESS   stop when a RAM cell (n, RAM[n] or R0, SP, ...) is written
+//   print A|D|PC|ADDRESS|N..M        show registers or RAM
+//   set A|D|PC|ADDRESS VALUE         change registers or RAM
+//   registers, breakpoints, list [N], quit
+pub struct Debugger {
+    pub cpu: Cpu,
+    // Labels of the program with their ROM addresses, sorted by address
+    labels: Vec<(String, u16)>,
+    symbols: HashMap<String, u32>,
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<u16>,
+    max_cycles: u64,
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    text.parse::<i16>()
+        .map(|value| value as u16)
+        .or_else(|_| text.parse::<u16>())
+        .map_err(|_| format!("invalid number `{}`", text))
+}
+
+impl Debugger {
+    // `labels` are the labels of the program's .asm file, see rom::labels.
+    // `continue` and `next` give up after `max_cycles` instructions.
+    pub fn new(program: &[u16], labels: Vec<(String, u16)>, max_cycles: u64) -> Debugger {
+        Debugger {
+            cpu: Cpu::new(program),
+            labels,
+            symbols: symbol_table(),
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            max_cycles,
+        }
+    }
+
+    // Returns the ROM address of "N" or of a label
+    fn rom_address(&self, location: &str) -> Result<u16, String> {
+        match self.labels.iter().find(|(label, _)| label == location) {
+            Some(&(_, address)) => Ok(address),
+            None => location
+                .parse::<u16>()
+                .ok()
+                .filter(|&address| (address as usize) < ROM_SIZE)
+                .ok_or_else(|| format!("unknown ROM address or label `{}`", location)),
+        }
+    }
+
+    // Returns the RAM address of "N", "RAM[N]" or of a predefined symbol such as R0 or SCREEN
+    fn ram_address(&self, name: &str) -> Result<u16, String> {
+        let number = name
+            .strip_prefix("RAM[")
+            .and_then(|name| name.strip_suffix(']'))
+            .unwrap_or(name);
+        number
+            .parse::<u16>()
+            .ok()
+            .or_else(|| {
+                self.symbols
+                    .get(&name.to_string())
+                    .map(|&address| address as u16)
+            })
+            .filter(|&address| (address as usize) < RAM_SIZE)
+            .ok_or_else(|| format!("unknown RAM address `{}`", name))
+    }
+
+    // Returns "N <LABEL+OFFSET>" for the closest label at or before a ROM address
+    pub fn location(&self, address: u16) -> String {
+        let closest = self
+            .labels
+            .iter()
+            .filter(|&&(_, label_address)| label_address <= address)
+            .map(|&(_, label_address)| label_address)
+            .max();
+        let label = closest.and_then(|closest| {
+            self.labels
+                .iter()
+                .find(|&&(_, label_address)| label_address == closest)
+        });
+        match label {
+            Some((label, label_address)) if *label_address == address => {
+                format!("{} <{}>", address, label)
+            }
+            Some((label, label_address)) => {
+                format!("{} <{}+{}>", address, label, address - label_address)
+            }
+            None => address.to_string(),
+        }
+    }
+
+    // Decodes the instruction at a ROM address back into assembly, including the
+    // undocumented comps the CPU runs as well. An address loaded right before a jump
+    // is annotated with its label.
+    pub fn decode(&self, address: u16) -> String {
+        let word = match self.cpu.rom.get(address as usize) {
+            Some(&word) => word,
+            None => return "(outside the program)".to_string(),
+        };
+        let assembly = disassemble("ROM", &[&format!("{:016b}", word)], false, true)
+            .ok()
+            .and_then(|lines| lines.into_iter().next())
+            .unwrap_or_else(|| format!("(invalid instruction {:016b})", word));
+        let jumps = self
+            .cpu
+            .rom
+            .get(address as usize + 1)
+            .is_some_and(|&next| next & 0xE000 == 0xE000 && next & 0x0007 != 0);
+        match self.labels.iter().find(|&&(_, target)| target == word) {
+            Some((label, _)) if word & 0x8000 == 0 && jumps => {
+                format!("{:<12}// {}", assembly, label)
+            }
+            _ => assembly,
+        }
+    }
+
+    fn current(&self) -> String {
+        format!(
+            "=> {}: {}",
+            self.location(self.cpu.pc),
+            self.decode(self.cpu.pc)
+        )
+    }
+
+    // Returns the RAM address the current instruction writes, if any
+    fn written(&self) -> Option<u16> {
+        self.cpu
+            .current()
+            .filter(|&instruction| instruction & 0xE000 == 0xE000 && instruction & 0x0008 != 0)
+            .map(|_| self.cpu.a)
+    }
+
+    // Executes instructions until `done(executed)` holds, a breakpoint or watchpoint is hit,
+    // the program stops or `max_cycles` instructions were executed.
+    // A breakpoint on the first instruction is passed, so that execution can leave it.
+    pub fn resume(&mut self, max_cycles: u64, done: impl Fn(&Cpu, u64) -> bool) -> Event {
+        for executed in 0..max_cycles {
+            if executed > 0 && self.breakpoints.contains(&self.cpu.pc) {
+                return Event::Breakpoint(self.cpu.pc);
+            }
+            if self.cpu.is_halted() {
+                return Event::Stopped(Stop::Halted);
+            }
+            let written = self.written();
+            let old = written.map(|address| self.cpu.peek(address));
+            if !self.cpu.step() {
+                return Event::Stopped(Stop::EndOfProgram);
+            }
+            if let (Some(address), Some(old)) = (written, old) {
+                if self.watchpoints.contains(&address) {
+                    let new = self.cpu.peek(address);
+                    return Event::Watchpoint { address, old, new };
+                }
+            }
+            if done(&self.cpu, executed + 1) {
+                return Event::Stepped;
+            }
+        }
+        Event::Stopped(Stop::CycleLimit)
+    }
+
+    fn report(&self, event: Event) -> String {
+        let message = match event {
+            Event::Stepped => None,
+            Event::Breakpoint(address) => Some(format!("Breakpoint at {}", self.location(address))),
+            Event::Watchpoint { address, old, new } => Some(format!(
+                "Watchpoint RAM[{}]: {} -> {}",
+                address, old as i16, new as i16
+            )),
+            Event::Stopped(Stop::Halted) => {
+                Some(format!("Program halted after {} cycles", self.cpu.cycles))
+            }
+            Event::Stopped(Stop::EndOfProgram) => {
+                Some(format!("Program ended after {} cycles", self.cpu.cycles))
+            }
+            Event::Stopped(Stop::CycleLimit) => Some(format!(
+                "Stopped at the cycle limit after {} cycles",
+                self.cpu.cycles
+            )),
+        };
+        message
+            .into_iter()
+            .chain(std::iter::once(self.current()))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn print(&self, name: &str) -> Result<String, String> {
+        match name {
+            "A" => Ok(format!("A = {}", self.cpu.a as i16)),
+            "D" => Ok(format!("D = {}", self.cpu.d as i16)),
+            "PC" => Ok(format!("PC = {}", self.cpu.pc)),
+            _ => {
+                let (start, end) = match name.split_once("..") {
+                    Some((start, end)) => (self.ram_address(start)?, self.ram_address(end)?),
+                    None => {
+                        let address = self.ram_address(name)?;
+                        (address, address + 1)
+                    }
+                };
+                Ok((start..end)
+                    .map(|address| format!("RAM[{}] = {}", address, self.cpu.peek(address) as i16))
+                    .collect::<Vec<String>>()
+                    .join("\n"))
+            }
+        }
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<String, String> {
+        let value = parse_number(value)?;
+        match name {
+            "A" => self.cpu.a = value,
+            "D" => self.cpu.d = value,
+            "PC" => self.cpu.pc = value,
+            _ => {
+                let address = self.ram_address(name)?;
+                self.cpu.poke(address, value)
+            }
+        }
+        self.print(name)
+    }
+
+    // Runs one command line and returns what it prints, or None for `quit`.
+    // Blank lines and lines starting with "//" or "#" do nothing.
+    pub fn execute(&mut self, line: &str) -> Result<Option<String>, String> {
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let output = match words.as_slice() {
+            [] => String::new(),
+            [comment, ..] if comment.starts_with("//") || comment.starts_with('#') => String::new(),
+            ["step" | "s"] => {
+                let event = self.resume(1, |_, _| true);
+                self.report(event)
+            }
+            ["step" | "s", count] => {
+                let count = count
+                    .parse::<u64>()
+                    .map_err(|_| format!("invalid count `{}`", count))?;
+                let event = self.resume(count, |_, executed| executed == count);
+                self.report(event)
+            }
+            ["next" | "n"] => {
+                let jumps = self.cpu.current().is_some_and(|instruction| {
+                    instruction & 0xE000 == 0xE000 && instruction & 0x0007 != 0
+                });
+                let after = self.cpu.pc + 1;
+                let event = if jumps {
+                    self.resume(self.max_cycles, |cpu, _| cpu.pc == after)
+                } else {
+                    self.resume(1, |_, _| true)
+                };
+                self.report(event)
+            }
+            ["continue" | "c"] => {
+                let event = self.resume(self.max_cycles, |_, _| false);
+                self.report(event)
+            }
+            ["break" | "b", location] => {
+                let address = self.rom_address(location)?;
+                if !self.breakpoints.contains(&address) {
+                    self.breakpoints.push(address);
+                }
+                format!("Breakpoint at {}", self.location(address))
+            }
+            ["delete" | "d", location] => {
+                let address = self.rom_address(location)?;
+                self.breakpoints.retain(|&breakpoint| breakpoint != address);
+                format!("Deleted breakpoint at {}", self.location(address))
+            }
+            ["watch" | "w", name] => {
+                let address = self.ram_address(name)?;
+                if !self.watchpoints.contains(&address) {
+                    self.watchpoints.push(address);
+                }
+                format!("Watchpoint on RAM[{}]", address)
+            }
+            ["unwatch", name] => {
+                let address = self.ram_address(name)?;
+                self.watchpoints.retain(|&watchpoint| watchpoint != address);
+                format!("Deleted watchpoint on RAM[{}]", address)
+            }
+            ["breakpoints"] => self
+                .breakpoints
+                .iter()
+                .map(|&address| format!("break {}", self.location(address)))
+                .chain(
+                    self.watchpoints
+                        .iter()
+                        .map(|address| format!("watch RAM[{}]", address)),
+                )
+                .collect::<Vec<String>>()
+                .join("\n"),
+            ["registers" | "r"] => format!(
+                "PC = {}  A = {}  D = {}  cycles = {}",
+                self.location(self.cpu.pc),
+                self.cpu.a as i16,
+                self.cpu.d as i16,
+                self.cpu.cycles
+            ),
+            ["print" | "p", name] => self.print(name)?,
+            ["set", name, value] => self.set(name, value)?,
+            ["list" | "l"] => self.current(),
+            ["list" | "l", count] => {
+                let count = count
+                    .parse::<u16>()
+                    .map_err(|_| format!("invalid count `{}`", count))?;
+                (self.cpu.pc..self.cpu.pc.saturating_add(count))
+                    .filter(|&address| (address as usize) < self.cpu.rom.len())
+                    .map(|address| {
+                        let marker = if address == self.cpu.pc { "=>" } else { "  " };
+                        format!(
+                            "{} {}: {}",
+                            marker,
+                            self.location(address),
+                            self.decode(address)
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+            ["quit" | "q"] => return Ok(None),
+            _ => return Err(format!("unknown command `{}`", line.trim())),
+        };
+        Ok(Some(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::{assemble, labels};
+
+    fn max_debugger() -> Debugger {
+        let path = format!("{}/../06/max/Max.asm", env!("CARGO_MANIFEST_DIR"));
+        let content = std::fs::read_to_string(path).unwrap();
+        let program = assemble("Max.asm", &content).unwrap();
+        Debugger::new(&program, labels("Max.asm", &content), 1000)
+    }
+
+    fn run(debugger: &mut Debugger, script: &str) -> Vec<String> {
+        script
+            .lines()
+            .map(|line| debugger.execute(line).unwrap().unwrap())
+            .filter(|output| !output.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn break_on_labels() {
+        let mut debugger = max_debugger();
+        assert_eq!(
+            vec![
+                "RAM[0] = 5",
+                "RAM[1] = 3",
+                "Breakpoint at 10 <OUTPUT_FIRST>",
+                "Breakpoint at 10 <OUTPUT_FIRST>\n=> 10 <OUTPUT_FIRST>: @0",
+                "=> 11 <OUTPUT_FIRST+1>: D=M",
+                "PC = 11 <OUTPUT_FIRST+1>  A = 0  D = 2  cycles = 7",
+                "Program halted after 11 cycles\n=> 15 <INFINITE_LOOP+1>: 0;JMP",
+                "RAM[0] = 5\nRAM[1] = 3\nRAM[2] = 5",
+            ],
+            run(
+                &mut debugger,
+                "set R0 5\nset RAM[1] 3\n\n// run to the label\nbreak OUTPUT_FIRST\ncontinue\nstep\nregisters\ncontinue\nprint 0..3",
+            )
+        );
+    }
+
+    #[test]
+    fn stop_on_watchpoints() {
+        let mut debugger = max_debugger();
+        assert_eq!(
+            vec![
+                "RAM[0] = 7",
+                "RAM[1] = -1",
+                "Watchpoint on RAM[2]",
+                "Watchpoint RAM[2]: 0 -> 7\n=> 14 <INFINITE_LOOP>: @14         // INFINITE_LOOP",
+            ],
+            run(&mut debugger, "set R0 7\nset 1 -1\nwatch R2\nc")
+        );
+    }
+
+    #[test]
+    fn step_over_jumps() {
+        let mut debugger = max_debugger();
+        debugger.execute("step 5").unwrap();
+        assert_eq!(
+            "=> 5: D;JGT\n   6: @1\n   7: D=M",
+            debugger.execute("list 3").unwrap().unwrap()
+        );
+        // D = 0, so the jump falls through to the next instruction
+        assert_eq!("=> 6: @1", debugger.execute("next").unwrap().unwrap());
+        assert_eq!(
+            Err("unknown ROM address or label `MISSING`".to_string()),
+            debugger.execute("break MISSING")
+        );
+        assert_eq!(None, debugger.execute("quit").unwrap());
+    }
+}
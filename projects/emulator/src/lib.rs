@@ -0,0 +1,4 @@
+pub mod cpu;
+pub mod debugger;
+pub mod rom;
+pub mod test_script;
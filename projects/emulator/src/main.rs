@@ -0,0 +1,225 @@
+use emulator::cpu::*;
+use emulator::debugger::Debugger;
+use emulator::rom::{assemble, decode, labels};
+use functional::functor::*;
+use functional::io::*;
+use script::runner;
+use script::script::parse_script;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+const DEFAULT_CYCLES: u64 = 1_000_000;
+
+struct Options {
+    input: String,
+    cycles: u64,
+    sets: Vec<(u16, u16)>,
+    dumps: Vec<(u16, u16)>,
+    debug: bool,
+    symbols: Option<String>,
+    script: Option<String>,
+}
+
+// Parses "N" or "N..M" (exclusive) into an address range
+fn parse_range(range: &str) -> Option<(u16, u16)> {
+    match range.split_once("..") {
+        Some((start, end)) => Some((start.parse().ok()?, end.parse().ok()?)),
+        None => range
+            .parse()
+            .ok()
+            .and_then(|address: u16| Some((address, address.checked_add(1)?))),
+    }
+    .filter(|&(_, end)| end as usize <= RAM_SIZE)
+}
+
+// Parses "ADDRESS=VALUE", where the value may be negative
+fn parse_set(set: &str) -> Option<(u16, u16)> {
+    let (address, value) = set.split_once('=')?;
+    Some((address.parse().ok()?, value.parse::<i16>().ok()? as u16))
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        input: String::new(),
+        cycles: DEFAULT_CYCLES,
+        sets: Vec::new(),
+        dumps: Vec::new(),
+        debug: false,
+        symbols: None,
+        script: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cycles" => options.cycles = args.next()?.parse().ok()?,
+            "--set" => options.sets.push(parse_set(args.next()?)?),
+            "--dump" => options.dumps.push(parse_range(args.next()?)?),
+            "--debug" => options.debug = true,
+            "--symbols" => options.symbols = Some(args.next()?.to_string()),
+            "--script" => options.script = Some(args.next()?.to_string()),
+            input if !input.starts_with("--") && options.input.is_empty() => {
+                options.input = input.to_string()
+            }
+            _ => return None,
+        }
+    }
+    Some(options).filter(|options| !options.input.is_empty())
+}
+
+fn run(options: Options) -> Result<(), String> {
+    IO::<String>::read_bytes(options.input.clone())
+        .flat_map(move |content| match decode(&options.input, &content) {
+            Ok(program) => {
+                let mut cpu = Cpu::new(&program);
+                options
+                    .sets
+                    .iter()
+                    .for_each(|&(address, value)| cpu.poke(address, value));
+                let stop = cpu.run(options.cycles);
+                println!("{:?} after {} cycles", stop, cpu.cycles);
+                options.dumps.iter().for_each(|&(start, end)| {
+                    (start..end).for_each(|address| {
+                        println!("RAM[{}] = {}", address, cpu.peek(address) as i16)
+                    })
+                });
+                IO::Return(())
+            }
+            Err(error) => IO::Error(error),
+        })
+        .unsafe_run()
+}
+
+// Runs the debugger on a .hack or .asm program, reading commands from the script file
+// or from the standard input. Labels come from the .asm program or the --symbols file.
+fn debug(options: Options) -> Result<(), String> {
+    let read =
+        |file: &str| fs::read_to_string(file).map_err(|error| format!("{}: {}", file, error));
+    let (program, labels) = if options.input.ends_with(".asm") {
+        let content = read(&options.input)?;
+        (
+            assemble(&options.input, &content)?,
+            labels(&options.input, &content),
+        )
+    } else {
+        let content =
+            fs::read(&options.input).map_err(|error| format!("{}: {}", options.input, error))?;
+        let program = decode(&options.input, &content)?;
+        match &options.symbols {
+            Some(symbols) => (program, labels(symbols, &read(symbols)?)),
+            None => (program, Vec::new()),
+        }
+    };
+    let mut debugger = Debugger::new(&program, labels, options.cycles);
+    options
+        .sets
+        .iter()
+        .for_each(|&(address, value)| debugger.cpu.poke(address, value));
+
+    let interactive = options.script.is_none() && io::stdin().is_terminal();
+    let lines: Box<dyn Iterator<Item = io::Result<String>>> = match &options.script {
+        Some(script) => Box::new(
+            read(script)?
+                .lines()
+                .map(|line| Ok(line.to_string()))
+                .collect::<Vec<_>>()
+                .into_iter(),
+        ),
+        None => Box::new(io::stdin().lock().lines()),
+    };
+    if interactive {
+        println!(
+            "{}",
+            debugger.execute("list").map(Option::unwrap_or_default)?
+        );
+    }
+    let prompt = || {
+        if interactive {
+            print!("(hack) ");
+            io::stdout().flush().ok();
+        }
+    };
+    prompt();
+    for (index, line) in lines.enumerate() {
+        let line = line.map_err(|error| error.to_string())?;
+        match debugger.execute(&line) {
+            Ok(Some(output)) if output.is_empty() => {}
+            Ok(Some(output)) => println!("{}", output),
+            Ok(None) => break,
+            // Typing errors are forgiven, but a script stops at its first error
+            Err(error) if interactive => eprintln!("error: {}", error),
+            Err(error) => {
+                let file = options.script.as_deref().unwrap_or("<stdin>");
+                return Err(format!("{}:{}: error: {}", file, index + 1, error));
+            }
+        }
+        prompt();
+    }
+    Ok(())
+}
+
+// Runs a .tst script, writes its output file and compares it with the compare file
+fn run_script(input: String) -> Result<(), String> {
+    let dir = Path::new(&input)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."));
+    IO::<String>::read_file(input.clone())
+        .flat_map(move |content| {
+            match parse_script(&content)
+                .map_err(|error| format!("{}: {}", input, error))
+                .and_then(|script| runner::run(&script, &dir, &mut Cpu::new(&[])))
+            {
+                Ok(outcome) => {
+                    let text = outcome.output_text();
+                    let write = match outcome.output_file.clone() {
+                        Some(output) => {
+                            IO::<String>::write_file(output.to_string_lossy().into_owned(), text)
+                        }
+                        None => IO::Return(()),
+                    };
+                    write.flat_map(move |_| match outcome.mismatch {
+                        None => {
+                            println!("{}: End of script - Comparison ended successfully", input);
+                            IO::Return(())
+                        }
+                        Some(mismatch) => IO::Error(format!(
+                            "{}: Comparison failure at line {}\nexpected: {}\nactual:   {}",
+                            input, mismatch.line, mismatch.expected, mismatch.actual
+                        )),
+                    })
+                }
+                Err(error) => IO::Error(error),
+            }
+        })
+        .unsafe_run()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    match parse_options(&args[1..]) {
+        Some(options) => {
+            let result = if options.input.ends_with(".tst") {
+                run_script(options.input)
+            } else if options.debug {
+                debug(options)
+            } else {
+                run(options)
+            };
+            result.unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(1);
+            })
+        }
+        None => {
+            eprintln!(
+                "Usage: {0} [--cycles N] [--set ADDRESS=VALUE]... [--dump START[..END]]... <machine code file name>\n       {0} --debug [--cycles N] [--set ADDRESS=VALUE]... [--symbols ASM FILE] [--script FILE] <machine code or asm file name>\n       {0} <tst file name>",
+                &args[0]
+            );
+            process::exit(1);
+        }
+    }
+}
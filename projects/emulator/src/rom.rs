@@ -0,0 +1,102 @@
+use asm::assembler::{preprocess, translate_into};
+use asm::error::report;
+use asm::format::{Format, FORMATS};
+use asm::macros::{expand, Expansion};
+use asm::source_map::source_map;
+use std::fs;
+
+// Parses the text of a .hack file, one 16-bit binary word per line, into ROM words
+pub fn load(content: &str) -> Result<Vec<u16>, String> {
+    Format::Hack.decode(content.as_bytes())
+}
+
+// Decodes machine code in the format of the file's extension, e.g. ".hex" for Intel HEX.
+// Files with an unknown extension are taken for .hack files.
+pub fn decode(file: &str, content: &[u8]) -> Result<Vec<u16>, String> {
+    let format = FORMATS
+        .into_iter()
+        .filter(|format| file.ends_with(&format!(".{}", format.extension())))
+        .max_by_key(|format| format.extension().len())
+        .unwrap_or(Format::Hack);
+    format
+        .decode(content)
+        .map_err(|error| format!("{}: {}", file, error))
+}
+
+// Expands the macros and includes of a .asm file, reading included files from disk
+fn expand_file(file: &str, content: &str) -> Result<Expansion, String> {
+    let lines = content.lines().collect::<Vec<&str>>();
+    let read = |path: &str| fs::read_to_string(path).map_err(|error| error.to_string());
+    expand(file, &lines, &read).map_err(|errors| report("assemble", file, &errors))
+}
+
+// Assembles the text of a .asm file into ROM words, with the undocumented comps the CPU runs
+pub fn assemble(file: &str, content: &str) -> Result<Vec<u16>, String> {
+    let expansion = expand_file(file, content)?;
+    let mut code = Vec::new();
+    translate_into(file, &expansion.lines(), true, &mut |word| code.push(word))
+        .map_err(|errors| report("assemble", file, &expansion.locate(errors)))?;
+    Ok(code)
+}
+
+// Returns the labels of a .asm file with their ROM addresses, sorted by address
+pub fn labels(file: &str, content: &str) -> Vec<(String, u16)> {
+    let expansion = expand_file(file, content).unwrap_or_default();
+    let lines = expansion.lines();
+    let (symbol_table, _) = preprocess(file, &lines);
+    let mut labels = source_map(file, &lines, &symbol_table)
+        .labels
+        .into_iter()
+        .map(|(label, address)| (label, address as u16))
+        .collect::<Vec<(String, u16)>>();
+    labels.sort_by_key(|&(_, address)| address);
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_words() {
+        assert_eq!(
+            Ok(vec![2, 0b1110110000010000]),
+            load("0000000000000010\r\n1110110000010000\r\n\r\n")
+        );
+        assert_eq!(
+            Err("line 2: invalid machine instruction `10`".to_string()),
+            load("0000000000000010\n10")
+        );
+    }
+
+    #[test]
+    fn decode_by_extension() {
+        assert_eq!(Ok(vec![2, 0xEC10]), decode("Test.bin", &[2, 0, 0x10, 0xEC]));
+        assert_eq!(
+            Ok(vec![2, 0xEC10]),
+            decode("Test.be.bin", &[0, 2, 0xEC, 0x10])
+        );
+        assert_eq!(Ok(vec![2, 0xEC10]), decode("Test.memh", b"0002 ec10"));
+        assert_eq!(
+            Err("Test.hack: line 1: invalid machine instruction `2`".to_string()),
+            decode("Test.hack", b"2")
+        );
+    }
+
+    #[test]
+    fn assemble_words() {
+        assert_eq!(
+            Ok(vec![2, 0b1110110000010000]),
+            assemble("Test.asm", "@2\nD=A")
+        );
+        assert!(assemble("Test.asm", "D=X").is_err());
+    }
+
+    #[test]
+    fn find_labels() {
+        assert_eq!(
+            vec![("LOOP".to_string(), 0), ("END".to_string(), 2)],
+            labels("Test.asm", "(LOOP)\n@2\n0;JMP\n(END)\n@END\n0;JMP")
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use crate::cpu::*;
+use crate::rom;
+use script::runner::Simulator;
+use script::script::Value;
+use std::fs;
+use std::path::Path;
+
+// Returns n of "RAM[n]"
+fn ram_address(name: &str) -> Option<u16> {
+    name.strip_prefix("RAM[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+        .filter(|&address| (address as usize) < RAM_SIZE)
+}
+
+// Runs CPU emulator scripts such as projects/04/mult/Mult.tst.
+// "ticktock" executes one instruction.
+impl Simulator for Cpu {
+    fn load(&mut self, dir: &Path, path: Option<&str>) -> Result<(), String> {
+        let path = path.ok_or("`load` needs a program")?;
+        let file = dir.join(path);
+        let content = fs::read(&file).map_err(|error| format!("{}: {}", file.display(), error))?;
+        let program = if path.ends_with(".asm") {
+            rom::assemble(&file.to_string_lossy(), &String::from_utf8_lossy(&content))?
+        } else {
+            rom::decode(path, &content)?
+        };
+        *self = Cpu::new(&program);
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: i64) -> Result<(), String> {
+        let value = value as u16;
+        match name {
+            "PC" => self.pc = value,
+            "A" => self.a = value,
+            "D" => self.d = value,
+            _ => match ram_address(name) {
+                Some(address) => self.poke(address, value),
+                None => return Err(format!("unknown variable `{}`", name)),
+            },
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Value, String> {
+        let value = match name {
+            "PC" => self.pc,
+            "A" => self.a,
+            "D" => self.d,
+            "time" => return Ok(Value::Number(self.cycles as i64)),
+            _ => self.peek(ram_address(name).ok_or(format!("unknown variable `{}`", name))?),
+        };
+        Ok(Value::Number(value as i16 as i64))
+    }
+
+    fn action(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        match (name, args) {
+            ("ticktock", []) => {
+                self.step();
+                Ok(())
+            }
+            _ => Err(format!("unknown command `{}`", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use script::runner::run;
+    use script::script::parse_script;
+
+    #[test]
+    fn run_mult_test() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../04/mult");
+        let script = parse_script(&fs::read_to_string(dir.join("Mult.tst")).unwrap()).unwrap();
+        let outcome = run(&script, &dir, &mut Cpu::new(&[])).unwrap();
+        assert_eq!(None, outcome.mismatch);
+        assert_eq!(7, outcome.output.len());
+    }
+
+    #[test]
+    fn report_first_mismatch() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../04/mult");
+        // Skipping the program leaves RAM[2] at -1
+        let script = parse_script(
+            "load Mult.asm, compare-to Mult.cmp, output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+             set RAM[2] -1, output;",
+        )
+        .unwrap();
+        let outcome = run(&script, &dir, &mut Cpu::new(&[])).unwrap();
+        let mismatch = outcome.mismatch.unwrap();
+        assert_eq!(2, mismatch.line);
+        assert_eq!("|       0  |       0  |      -1  |", mismatch.actual);
+    }
+
+    #[test]
+    fn load_includes_beside_the_program() {
+        let dir = std::env::temp_dir().join("emulator-load-includes");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Main.asm"), ".include \"Set.asm\"\n@7\nD=A\nSET\n").unwrap();
+        fs::write(dir.join("Set.asm"), ".macro SET\n@0\nM=D\n.endm\n").unwrap();
+        let script = parse_script("load Main.asm, repeat 4 { ticktock; }").unwrap();
+        let mut cpu = Cpu::new(&[]);
+        run(&script, &dir, &mut cpu).unwrap();
+        assert_eq!(7, cpu.peek(0));
+    }
+}
@@ -0,0 +1,68 @@
+// A pin reference, optionally narrowed to a sub-bus, e.g. "a" or "a[0..7]"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bus {
+    pub name: String,
+    pub range: Option<(usize, usize)>,
+}
+
+// Right hand side of a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signal {
+    Bus(Bus),
+    Constant(bool),
+}
+
+// "pin=signal" in a part, where `pin` belongs to the part
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    pub pin: Bus,
+    pub signal: Signal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub name: String,
+    pub connections: Vec<Connection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    pub name: String,
+    pub width: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    Parts(Vec<Part>),
+    // "BUILTIN Name; CLOCKED pin, ...;": implemented by the simulator itself
+    Builtin { name: String, clocked: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chip {
+    pub name: String,
+    pub inputs: Vec<Pin>,
+    pub outputs: Vec<Pin>,
+    pub body: Body,
+}
+
+impl Bus {
+    // Returns the first and last bit selected from a pin of `width` bits
+    pub fn bits(&self, width: usize) -> Option<(usize, usize)> {
+        match self.range {
+            None => Some((0, width.checked_sub(1)?)),
+            Some((first, last)) if first <= last && last < width => Some((first, last)),
+            Some(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Bus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.range {
+            None => write!(f, "{}", self.name),
+            Some((first, last)) if first == last => write!(f, "{}[{}]", self.name, first),
+            Some((first, last)) => write!(f, "{}[{}..{}]", self.name, first, last),
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// The chips of the course, for parts that have no .hdl file in the search path.
+// Nand and DFF are the primitives; memories of 64 words and more, the screen, the keyboard
+// and the ROM are simulated natively, as flattening them would mean millions of gates.
+// Everything else is written in HDL on top of those, so it elaborates down to Nand and DFF.
+
+const PRIMITIVES: [(&str, &str); 9] = [
+    ("Nand", "IN a, b; OUT out; BUILTIN Nand;"),
+    ("DFF", "IN in; OUT out; BUILTIN DFF; CLOCKED in;"),
+    (
+        "RAM64",
+        "IN in[16], load, address[6]; OUT out[16]; BUILTIN RAM64; CLOCKED in, load;",
+    ),
+    (
+        "RAM512",
+        "IN in[16], load, address[9]; OUT out[16]; BUILTIN RAM512; CLOCKED in, load;",
+    ),
+    (
+        "RAM4K",
+        "IN in[16], load, address[12]; OUT out[16]; BUILTIN RAM4K; CLOCKED in, load;",
+    ),
+    (
+        "RAM16K",
+        "IN in[16], load, address[14]; OUT out[16]; BUILTIN RAM16K; CLOCKED in, load;",
+    ),
+    (
+        "Screen",
+        "IN in[16], load, address[13]; OUT out[16]; BUILTIN Screen; CLOCKED in, load;",
+    ),
+    ("Keyboard", "OUT out[16]; BUILTIN Keyboard;"),
+    ("ROM32K", "IN address[15]; OUT out[16]; BUILTIN ROM32K;"),
+];
+
+// Names of the built-in chips whose behavior is a memory of words
+pub const MEMORIES: [&str; 7] = [
+    "RAM64", "RAM512", "RAM4K", "RAM16K", "Screen", "Keyboard", "ROM32K",
+];
+
+// One part per bit, e.g. "Not(in=in[0], out=out[0]); Not(in=in[1], out=out[1]); ..."
+fn bitwise(part: &str, inputs: &[&str]) -> String {
+    (0..16)
+        .map(|bit| {
+            let pins = inputs
+                .iter()
+                .map(|pin| format!("{pin}={pin}[{bit}], "))
+                .collect::<String>();
+            format!("{part}({pins}out=out[{bit}]);")
+        })
+        .collect()
+}
+
+fn mux16() -> String {
+    (0..16)
+        .map(|bit| format!("Mux(a=a[{bit}], b=b[{bit}], sel=sel, out=out[{bit}]);"))
+        .collect()
+}
+
+fn add16() -> String {
+    let bits = (1..16)
+        .map(|bit| {
+            format!(
+                "FullAdder(a=a[{bit}], b=b[{bit}], c=c{}, sum=out[{bit}], carry=c{bit});",
+                bit - 1
+            )
+        })
+        .collect::<String>();
+    format!("HalfAdder(a=a[0], b=b[0], sum=out[0], carry=c0);{bits}")
+}
+
+fn register() -> String {
+    (0..16)
+        .map(|bit| format!("Bit(in=in[{bit}], load=load, out=out[{bit}]);"))
+        .collect()
+}
+
+fn or8way() -> String {
+    (1..8)
+        .map(|bit| {
+            let previous = if bit == 1 {
+                "in[0]".to_string()
+            } else {
+                format!("o{}", bit - 1)
+            };
+            let out = if bit == 7 {
+                "out".to_string()
+            } else {
+                format!("o{bit}")
+            };
+            format!("Or(a={previous}, b=in[{bit}], out={out});")
+        })
+        .collect()
+}
+
+fn ram8() -> String {
+    let registers = (0..8)
+        .map(|word| format!("Register(in=in, load=l{word}, out=r{word});"))
+        .collect::<String>();
+    format!(
+        "DMux8Way(in=load, sel=address, a=l0, b=l1, c=l2, d=l3, e=l4, f=l5, g=l6, h=l7);
+         {registers}
+         Mux8Way16(a=r0, b=r1, c=r2, d=r3, e=r4, f=r5, g=r6, h=r7, sel=address, out=out);"
+    )
+}
+
+// Returns the interface and parts of a chip in HDL
+fn chip(name: &str) -> Option<(&'static str, String)> {
+    let parts = |text: &str| text.to_string();
+    Some(match name {
+        "Not" => ("IN in; OUT out;", parts("Nand(a=in, b=in, out=out);")),
+        "And" => (
+            "IN a, b; OUT out;",
+            parts("Nand(a=a, b=b, out=n); Not(in=n, out=out);"),
+        ),
+        "Or" => (
+            "IN a, b; OUT out;",
+            parts("Not(in=a, out=na); Not(in=b, out=nb); Nand(a=na, b=nb, out=out);"),
+        ),
+        "Xor" => (
+            "IN a, b; OUT out;",
+            parts(
+                "Nand(a=a, b=b, out=n); Nand(a=a, b=n, out=x); Nand(a=n, b=b, out=y);
+                 Nand(a=x, b=y, out=out);",
+            ),
+        ),
+        "Mux" => (
+            "IN a, b, sel; OUT out;",
+            parts(
+                "Not(in=sel, out=ns); Nand(a=a, b=ns, out=x); Nand(a=sel, b=b, out=y);
+                 Nand(a=x, b=y, out=out);",
+            ),
+        ),
+        "DMux" => (
+            "IN in, sel; OUT a, b;",
+            parts("Not(in=sel, out=ns); And(a=in, b=ns, out=a); And(a=in, b=sel, out=b);"),
+        ),
+        "Not16" => ("IN in[16]; OUT out[16];", bitwise("Not", &["in"])),
+        "And16" => ("IN a[16], b[16]; OUT out[16];", bitwise("And", &["a", "b"])),
+        "Or16" => ("IN a[16], b[16]; OUT out[16];", bitwise("Or", &["a", "b"])),
+        "Mux16" => ("IN a[16], b[16], sel; OUT out[16];", mux16()),
+        "Or8Way" => ("IN in[8]; OUT out;", or8way()),
+        "Mux4Way16" => (
+            "IN a[16], b[16], c[16], d[16], sel[2]; OUT out[16];",
+            parts(
+                "Mux16(a=a, b=b, sel=sel[0], out=ab); Mux16(a=c, b=d, sel=sel[0], out=cd);
+                 Mux16(a=ab, b=cd, sel=sel[1], out=out);",
+            ),
+        ),
+        "Mux8Way16" => (
+            "IN a[16], b[16], c[16], d[16], e[16], f[16], g[16], h[16], sel[3]; OUT out[16];",
+            parts(
+                "Mux4Way16(a=a, b=b, c=c, d=d, sel=sel[0..1], out=abcd);
+                 Mux4Way16(a=e, b=f, c=g, d=h, sel=sel[0..1], out=efgh);
+                 Mux16(a=abcd, b=efgh, sel=sel[2], out=out);",
+            ),
+        ),
+        "DMux4Way" => (
+            "IN in, sel[2]; OUT a, b, c, d;",
+            parts(
+                "DMux(in=in, sel=sel[1], a=ab, b=cd); DMux(in=ab, sel=sel[0], a=a, b=b);
+                 DMux(in=cd, sel=sel[0], a=c, b=d);",
+            ),
+        ),
+        "DMux8Way" => (
+            "IN in, sel[3]; OUT a, b, c, d, e, f, g, h;",
+            parts(
+                "DMux(in=in, sel=sel[2], a=abcd, b=efgh);
+                 DMux4Way(in=abcd, sel=sel[0..1], a=a, b=b, c=c, d=d);
+                 DMux4Way(in=efgh, sel=sel[0..1], a=e, b=f, c=g, d=h);",
+            ),
+        ),
+        "HalfAdder" => (
+            "IN a, b; OUT sum, carry;",
+            parts("Xor(a=a, b=b, out=sum); And(a=a, b=b, out=carry);"),
+        ),
+        "FullAdder" => (
+            "IN a, b, c; OUT sum, carry;",
+            parts(
+                "HalfAdder(a=a, b=b, sum=s, carry=c1); HalfAdder(a=s, b=c, sum=sum, carry=c2);
+                 Or(a=c1, b=c2, out=carry);",
+            ),
+        ),
+        "Add16" => ("IN a[16], b[16]; OUT out[16];", add16()),
+        "Inc16" => (
+            "IN in[16]; OUT out[16];",
+            parts("Add16(a=in, b[0]=true, b[1..15]=false, out=out);"),
+        ),
+        "ALU" => (
+            "IN x[16], y[16], zx, nx, zy, ny, f, no; OUT out[16], zr, ng;",
+            parts(
+                "Mux16(a=x, b=false, sel=zx, out=x1); Not16(in=x1, out=nx1);
+                 Mux16(a=x1, b=nx1, sel=nx, out=x2);
+                 Mux16(a=y, b=false, sel=zy, out=y1); Not16(in=y1, out=ny1);
+                 Mux16(a=y1, b=ny1, sel=ny, out=y2);
+                 Add16(a=x2, b=y2, out=sum); And16(a=x2, b=y2, out=and);
+                 Mux16(a=and, b=sum, sel=f, out=o1); Not16(in=o1, out=no1);
+                 Mux16(a=o1, b=no1, sel=no, out=out, out[15]=ng, out[0..7]=low, out[8..15]=high);
+                 Or8Way(in=low, out=zl); Or8Way(in=high, out=zh); Or(a=zl, b=zh, out=nz);
+                 Not(in=nz, out=zr);",
+            ),
+        ),
+        "Bit" => (
+            "IN in, load; OUT out;",
+            parts("Mux(a=o, b=in, sel=load, out=d); DFF(in=d, out=o, out=out);"),
+        ),
+        "Register" => ("IN in[16], load; OUT out[16];", register()),
+        "ARegister" | "DRegister" => (
+            "IN in[16], load; OUT out[16];",
+            parts("Register(in=in, load=load, out=out);"),
+        ),
+        "PC" => (
+            "IN in[16], load, inc, reset; OUT out[16];",
+            parts(
+                "Inc16(in=o, out=incremented); Mux16(a=o, b=incremented, sel=inc, out=w1);
+                 Mux16(a=w1, b=in, sel=load, out=w2); Mux16(a=w2, b=false, sel=reset, out=w3);
+                 Register(in=w3, load=true, out=o, out=out);",
+            ),
+        ),
+        "RAM8" => ("IN in[16], load, address[3]; OUT out[16];", ram8()),
+        _ => return None,
+    })
+}
+
+// Returns the HDL text of a built-in chip
+pub fn source(name: &str) -> Option<String> {
+    match PRIMITIVES.iter().find(|(primitive, _)| *primitive == name) {
+        Some((_, body)) => Some(format!("CHIP {} {{ {} }}", name, body)),
+        None => chip(name).map(|(interface, parts)| {
+            format!("CHIP {} {{ {} PARTS: {} }}", name, interface, parts)
+        }),
+    }
+}
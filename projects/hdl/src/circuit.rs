@@ -0,0 +1,908 @@
+use crate::ast::*;
+use crate::builtins::MEMORIES;
+use crate::error::Error;
+use crate::library::Library;
+use std::path::Path;
+
+type Wire = usize;
+
+const FALSE: Wire = 0;
+const TRUE: Wire = 1;
+
+// A wire is either driven by a node, a constant or a top level input, or stands for
+// a pin that some part output drives, once elaboration has found that part
+enum Net {
+    Source,
+    Alias(Option<Wire>),
+}
+
+enum Node {
+    Nand {
+        a: Wire,
+        b: Wire,
+        out: Wire,
+    },
+    Dff {
+        input: Wire,
+        out: Wire,
+        state: bool,
+        next: bool,
+    },
+    // RAMs, the screen, the keyboard and the ROM. `in` and `load` are clocked, `address` is not.
+    Memory {
+        address: Vec<Wire>,
+        input: Vec<Wire>,
+        load: Option<Wire>,
+        out: Vec<Wire>,
+        words: Vec<u16>,
+        pending: Option<(usize, u16)>,
+    },
+}
+
+// A part of the chip hierarchy, so scripts can read e.g. "DRegister[]" or "RAM16K[7]"
+struct Instance {
+    chip: String,
+    out: Vec<Wire>,
+    memory: Option<usize>,
+    // The flip-flop driving each bit of `out`, if any
+    latches: Vec<Option<usize>>,
+}
+
+// A pin of the top level chip: its inputs, outputs and internal pins
+struct PinWires {
+    name: String,
+    wires: Vec<Wire>,
+}
+
+// A chip flattened into Nand gates, flip-flops and memories
+pub struct Circuit {
+    pub name: String,
+    signals: Vec<PinWires>,
+    inputs: usize,
+    outputs: usize,
+    wires: Vec<bool>,
+    nodes: Vec<Node>,
+    // Nodes in the order of combinational evaluation
+    order: Vec<usize>,
+    instances: Vec<Instance>,
+}
+
+struct Builder<'a> {
+    library: &'a Library,
+    nets: Vec<Net>,
+    nodes: Vec<Node>,
+    paths: Vec<String>,
+    instances: Vec<Instance>,
+    stack: Vec<String>,
+    errors: Vec<Error>,
+}
+
+fn pin_index(pins: &[Pin], name: &str) -> Option<usize> {
+    pins.iter().position(|pin| pin.name == name)
+}
+
+impl Builder<'_> {
+    fn wires(&mut self, width: usize, net: fn() -> Net) -> Vec<Wire> {
+        (0..width)
+            .map(|_| {
+                self.nets.push(net());
+                self.nets.len() - 1
+            })
+            .collect()
+    }
+
+    fn error(&mut self, error: Error) {
+        if !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
+
+    fn find(&self, mut wire: Wire) -> Wire {
+        while let Net::Alias(target) = self.nets[wire] {
+            wire = target.unwrap_or(FALSE);
+        }
+        wire
+    }
+
+    // Returns the wires of the output pins of `chip`, given the wires of its input pins
+    fn elaborate(&mut self, chip: &Chip, path: &str, inputs: Vec<Vec<Wire>>) -> Vec<PinWires> {
+        let mut signals = chip
+            .inputs
+            .iter()
+            .zip(inputs)
+            .map(|(pin, wires)| PinWires {
+                name: pin.name.clone(),
+                wires,
+            })
+            .collect::<Vec<PinWires>>();
+        if self.stack.contains(&chip.name) {
+            self.error(Error::RecursiveChip(chip.name.clone()));
+            for pin in &chip.outputs {
+                let wires = self.wires(pin.width, || Net::Alias(None));
+                signals.push(PinWires {
+                    name: pin.name.clone(),
+                    wires,
+                });
+            }
+        } else {
+            self.stack.push(chip.name.clone());
+            match &chip.body {
+                Body::Parts(parts) => {
+                    for pin in &chip.outputs {
+                        let wires = self.wires(pin.width, || Net::Alias(None));
+                        signals.push(PinWires {
+                            name: pin.name.clone(),
+                            wires,
+                        });
+                    }
+                    for part in parts {
+                        self.part(chip, path, part, &mut signals);
+                    }
+                    self.check_connected(chip, &signals);
+                }
+                Body::Builtin { name, .. } => {
+                    for pin in &chip.outputs {
+                        let wires = self.wires(pin.width, || Net::Source);
+                        signals.push(PinWires {
+                            name: pin.name.clone(),
+                            wires,
+                        });
+                    }
+                    self.builtin(chip, name, path, &signals);
+                }
+            }
+            self.stack.pop();
+        }
+        signals
+    }
+
+    fn builtin(&mut self, chip: &Chip, name: &str, path: &str, signals: &[PinWires]) {
+        let pin = |name: &str| {
+            signals
+                .iter()
+                .find(|signal| signal.name == name)
+                .map(|signal| signal.wires.clone())
+                .unwrap_or_default()
+        };
+        let node = match name {
+            "Nand" if pin("a").len() == 1 && pin("b").len() == 1 && pin("out").len() == 1 => {
+                Node::Nand {
+                    a: pin("a")[0],
+                    b: pin("b")[0],
+                    out: pin("out")[0],
+                }
+            }
+            "DFF" if pin("in").len() == 1 && pin("out").len() == 1 => Node::Dff {
+                input: pin("in")[0],
+                out: pin("out")[0],
+                state: false,
+                next: false,
+            },
+            _ if MEMORIES.contains(&name) && pin("out").len() == 16 => {
+                let address = pin("address");
+                Node::Memory {
+                    words: vec![0; 1 << address.len()],
+                    address,
+                    input: pin("in"),
+                    load: pin("load").first().copied(),
+                    out: pin("out"),
+                    pending: None,
+                }
+            }
+            _ => return self.error(Error::UnknownBuiltin(chip.name.clone())),
+        };
+        if let Node::Memory { out, .. } = &node {
+            self.instances.push(Instance {
+                chip: chip.name.clone(),
+                out: out.clone(),
+                memory: Some(self.nodes.len()),
+                latches: Vec::new(),
+            });
+        }
+        self.nodes.push(node);
+        self.paths.push(path.to_string());
+    }
+
+    fn part(&mut self, chip: &Chip, path: &str, part: &Part, signals: &mut Vec<PinWires>) {
+        let definition = match self.library.chip(&part.name) {
+            Ok(definition) => definition,
+            Err(error) => return self.error(error),
+        };
+        let mut inputs = definition
+            .inputs
+            .iter()
+            .map(|pin| vec![FALSE; pin.width])
+            .collect::<Vec<Vec<Wire>>>();
+        let mut outputs = Vec::new();
+
+        for connection in &part.connections {
+            let (pins, is_input) = match pin_index(&definition.inputs, &connection.pin.name) {
+                Some(_) => (&definition.inputs, true),
+                None => (&definition.outputs, false),
+            };
+            let index = match pin_index(pins, &connection.pin.name) {
+                Some(index) => index,
+                None => {
+                    self.error(Error::UnknownPin {
+                        chip: chip.name.clone(),
+                        part: part.name.clone(),
+                        pin: connection.pin.name.clone(),
+                    });
+                    continue;
+                }
+            };
+            let (first, last) = match connection.pin.bits(pins[index].width) {
+                Some(bits) => bits,
+                None => {
+                    self.error(Error::BusOutOfRange {
+                        chip: chip.name.clone(),
+                        bus: connection.pin.to_string(),
+                        width: pins[index].width,
+                    });
+                    continue;
+                }
+            };
+            if is_input {
+                if let Some(wires) = self.source(chip, part, connection, last - first + 1, signals)
+                {
+                    inputs[index][first..=last].copy_from_slice(&wires);
+                }
+            } else {
+                outputs.push((index, first, last, connection));
+            }
+        }
+
+        let part_path = format!("{}/{}", path, part.name);
+        let part_signals = self.elaborate(&definition, &part_path, inputs);
+        let part_outputs = &part_signals[definition.inputs.len()..];
+        if let Some(out) = part_outputs.iter().find(|signal| signal.name == "out") {
+            if !matches!(definition.body, Body::Builtin { .. }) {
+                self.instances.push(Instance {
+                    chip: definition.name.clone(),
+                    out: out.wires.clone(),
+                    memory: None,
+                    latches: Vec::new(),
+                });
+            }
+        }
+        for (index, first, last, connection) in outputs {
+            let drivers = part_outputs[index].wires[first..=last].to_vec();
+            self.drive(chip, part, connection, drivers, signals);
+        }
+    }
+
+    // Returns the wires a part input is connected to
+    fn source(
+        &mut self,
+        chip: &Chip,
+        part: &Part,
+        connection: &Connection,
+        width: usize,
+        signals: &mut Vec<PinWires>,
+    ) -> Option<Vec<Wire>> {
+        let bus = match &connection.signal {
+            Signal::Constant(value) => return Some(vec![if *value { TRUE } else { FALSE }; width]),
+            Signal::Bus(bus) => bus,
+        };
+        let wires = match signals.iter().find(|signal| signal.name == bus.name) {
+            Some(signal) => self.select(chip, bus, &signal.wires)?,
+            None if bus.range.is_none() => {
+                // An internal pin used before the part that drives it
+                let wires = self.wires(width, || Net::Alias(None));
+                signals.push(PinWires {
+                    name: bus.name.clone(),
+                    wires: wires.clone(),
+                });
+                wires
+            }
+            None => {
+                self.error(Error::UnconnectedPin {
+                    chip: chip.name.clone(),
+                    pin: bus.name.clone(),
+                });
+                return None;
+            }
+        };
+        self.check_width(chip, part, connection, width, wires.len())
+            .then_some(wires)
+    }
+
+    // Connects the output bits of a part to a pin of the chip
+    fn drive(
+        &mut self,
+        chip: &Chip,
+        part: &Part,
+        connection: &Connection,
+        drivers: Vec<Wire>,
+        signals: &mut Vec<PinWires>,
+    ) {
+        let invalid = Error::InvalidTarget {
+            chip: chip.name.clone(),
+            part: part.name.clone(),
+            signal: match &connection.signal {
+                Signal::Bus(bus) => bus.to_string(),
+                Signal::Constant(value) => value.to_string(),
+            },
+        };
+        let bus = match &connection.signal {
+            Signal::Bus(bus) if pin_index(&chip.inputs, &bus.name).is_none() => bus,
+            _ => return self.error(invalid),
+        };
+        let targets = match signals.iter().find(|signal| signal.name == bus.name) {
+            Some(signal) => match self.select(chip, bus, &signal.wires) {
+                Some(targets) => targets,
+                None => return,
+            },
+            None if bus.range.is_none() => {
+                signals.push(PinWires {
+                    name: bus.name.clone(),
+                    wires: drivers,
+                });
+                return;
+            }
+            None => return self.error(invalid),
+        };
+        if !self.check_width(chip, part, connection, drivers.len(), targets.len()) {
+            return;
+        }
+        for (target, driver) in targets.into_iter().zip(drivers) {
+            match self.nets[target] {
+                Net::Alias(None) => self.nets[target] = Net::Alias(Some(driver)),
+                _ => self.error(Error::MultipleDrivers {
+                    chip: chip.name.clone(),
+                    pin: bus.name.clone(),
+                }),
+            }
+        }
+    }
+
+    fn select(&mut self, chip: &Chip, bus: &Bus, wires: &[Wire]) -> Option<Vec<Wire>> {
+        match bus.bits(wires.len()) {
+            Some((first, last)) => Some(wires[first..=last].to_vec()),
+            None => {
+                self.error(Error::BusOutOfRange {
+                    chip: chip.name.clone(),
+                    bus: bus.to_string(),
+                    width: wires.len(),
+                });
+                None
+            }
+        }
+    }
+
+    fn check_width(
+        &mut self,
+        chip: &Chip,
+        part: &Part,
+        connection: &Connection,
+        expected: usize,
+        actual: usize,
+    ) -> bool {
+        if expected != actual {
+            self.error(Error::WidthMismatch {
+                chip: chip.name.clone(),
+                part: part.name.clone(),
+                pin: connection.pin.to_string(),
+                expected,
+                actual,
+            });
+        }
+        expected == actual
+    }
+
+    // Output and internal pins must be driven by some part
+    fn check_connected(&mut self, chip: &Chip, signals: &[PinWires]) {
+        for signal in &signals[chip.inputs.len()..] {
+            if signal
+                .wires
+                .iter()
+                .any(|&wire| matches!(self.nets[wire], Net::Alias(None)))
+            {
+                self.error(Error::UnconnectedPin {
+                    chip: chip.name.clone(),
+                    pin: signal.name.clone(),
+                });
+            }
+        }
+    }
+
+    // Sorts the nodes so every node comes after the nodes driving its unclocked inputs
+    fn order(&self, nodes: &[Node]) -> Result<Vec<usize>, Error> {
+        let mut producers = vec![None; self.nets.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            for wire in outputs(node) {
+                producers[wire] = Some(index);
+            }
+        }
+        let mut consumers = vec![Vec::new(); nodes.len()];
+        let mut pending = vec![0; nodes.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            for wire in unclocked_inputs(node) {
+                if let Some(producer) = producers[wire] {
+                    consumers[producer].push(index);
+                    pending[index] += 1;
+                }
+            }
+        }
+        let mut order = (0..nodes.len())
+            .filter(|&index| pending[index] == 0)
+            .collect::<Vec<usize>>();
+        let mut next = 0;
+        while next < order.len() {
+            for &consumer in &consumers[order[next]] {
+                pending[consumer] -= 1;
+                if pending[consumer] == 0 {
+                    order.push(consumer);
+                }
+            }
+            next += 1;
+        }
+        match (0..nodes.len()).find(|&index| pending[index] > 0) {
+            Some(index) => Err(Error::CombinationalCycle(self.paths[index].clone())),
+            None => Ok(order),
+        }
+    }
+}
+
+fn outputs(node: &Node) -> Vec<Wire> {
+    match node {
+        Node::Nand { out, .. } | Node::Dff { out, .. } => vec![*out],
+        Node::Memory { out, .. } => out.clone(),
+    }
+}
+
+fn unclocked_inputs(node: &Node) -> Vec<Wire> {
+    match node {
+        Node::Nand { a, b, .. } => vec![*a, *b],
+        Node::Dff { .. } => Vec::new(),
+        Node::Memory { address, .. } => address.clone(),
+    }
+}
+
+fn read(wires: &[bool], bits: &[Wire]) -> u64 {
+    bits.iter()
+        .rev()
+        .fold(0, |value, &bit| (value << 1) | wires[bit] as u64)
+}
+
+fn write(wires: &mut [bool], bits: &[Wire], value: u64) {
+    for (index, &bit) in bits.iter().enumerate() {
+        wires[bit] = (value >> index) & 1 == 1;
+    }
+}
+
+// Splits "name[3]" into ("name", Some(3))
+fn indexed(name: &str) -> (&str, Option<&str>) {
+    match name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+        Some((name, index)) => (name, Some(index)),
+        None => (name, None),
+    }
+}
+
+impl Circuit {
+    // Elaborates `chip` with parts found through `library`
+    pub fn new(chip: &Chip, library: &Library) -> Result<Circuit, Vec<Error>> {
+        let mut builder = Builder {
+            library,
+            nets: vec![Net::Source, Net::Source],
+            nodes: Vec::new(),
+            paths: Vec::new(),
+            instances: Vec::new(),
+            stack: Vec::new(),
+            errors: Vec::new(),
+        };
+        let inputs = chip
+            .inputs
+            .iter()
+            .map(|pin| builder.wires(pin.width, || Net::Source))
+            .collect();
+        let mut signals = builder.elaborate(chip, &chip.name, inputs);
+        if !builder.errors.is_empty() {
+            return Err(builder.errors);
+        }
+
+        // Replace the wires of pins by the wires of their drivers
+        let mut instances = builder.instances.split_off(0);
+        let mut nodes = builder.nodes.split_off(0);
+        let resolve = |wires: &mut Vec<Wire>| {
+            for wire in wires.iter_mut() {
+                *wire = builder.find(*wire);
+            }
+        };
+        signals
+            .iter_mut()
+            .for_each(|signal| resolve(&mut signal.wires));
+        instances
+            .iter_mut()
+            .for_each(|instance| resolve(&mut instance.out));
+        for node in nodes.iter_mut() {
+            match node {
+                Node::Nand { a, b, .. } => {
+                    *a = builder.find(*a);
+                    *b = builder.find(*b);
+                }
+                Node::Dff { input, .. } => *input = builder.find(*input),
+                Node::Memory {
+                    address,
+                    input,
+                    load,
+                    ..
+                } => {
+                    resolve(address);
+                    resolve(input);
+                    *load = load.map(|load| builder.find(load));
+                }
+            }
+        }
+
+        let mut latches = vec![None; builder.nets.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            if let Node::Dff { out, .. } = node {
+                latches[*out] = Some(index);
+            }
+        }
+        for instance in instances.iter_mut() {
+            instance.latches = instance.out.iter().map(|&wire| latches[wire]).collect();
+        }
+
+        let order = builder.order(&nodes).map_err(|error| vec![error])?;
+        let mut wires = vec![false; builder.nets.len()];
+        wires[TRUE] = true;
+        let mut circuit = Circuit {
+            name: chip.name.clone(),
+            signals,
+            inputs: chip.inputs.len(),
+            outputs: chip.outputs.len(),
+            wires,
+            nodes,
+            order,
+            instances,
+        };
+        circuit.eval();
+        Ok(circuit)
+    }
+
+    // Loads a .hdl file, looking for its parts next to it and then in `search_path`
+    pub fn load(file: &Path, search_path: &[&Path]) -> Result<Circuit, Vec<Error>> {
+        let name = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let dir = file.parent().unwrap_or(Path::new("."));
+        let library = Library::new(
+            std::iter::once(dir)
+                .chain(search_path.iter().copied())
+                .map(Path::to_path_buf)
+                .collect(),
+        );
+        let chip = library.chip(&name).map_err(|error| vec![error])?;
+        Circuit::new(&chip, &library)
+    }
+
+    // Returns the number of Nand gates, flip-flops and memories
+    pub fn size(&self) -> (usize, usize, usize) {
+        self.nodes
+            .iter()
+            .fold((0, 0, 0), |(nands, dffs, memories), node| match node {
+                Node::Nand { .. } => (nands + 1, dffs, memories),
+                Node::Dff { .. } => (nands, dffs + 1, memories),
+                Node::Memory { .. } => (nands, dffs, memories + 1),
+            })
+    }
+
+    // Returns the names of the input pins
+    pub fn inputs(&self) -> Vec<String> {
+        self.signals[..self.inputs]
+            .iter()
+            .map(|signal| signal.name.clone())
+            .collect()
+    }
+
+    // Returns the names of the output pins
+    pub fn outputs(&self) -> Vec<String> {
+        self.signals[self.inputs..self.inputs + self.outputs]
+            .iter()
+            .map(|signal| signal.name.clone())
+            .collect()
+    }
+
+    fn signal(&self, name: &str) -> Option<&PinWires> {
+        self.signals.iter().find(|signal| signal.name == name)
+    }
+
+    fn instance(&self, chip: &str) -> Option<&Instance> {
+        self.instances.iter().find(|instance| instance.chip == chip)
+    }
+
+    // Returns the width of a pin of the chip
+    pub fn width(&self, name: &str) -> Option<usize> {
+        self.signal(name).map(|signal| signal.wires.len())
+    }
+
+    // Sets an input pin, "pin" or "pin[n]", or a word of a memory part, "RAM16K[n]"
+    pub fn set(&mut self, name: &str, value: u64) -> Result<(), String> {
+        let (base, index) = indexed(name);
+        if let Some(position) = self.signals[..self.inputs]
+            .iter()
+            .position(|signal| signal.name == base)
+        {
+            let wires = &self.signals[position].wires;
+            let bits = match index.map(|index| index.parse::<usize>()) {
+                None => wires.clone(),
+                Some(Ok(index)) if index < wires.len() => vec![wires[index]],
+                Some(_) => return Err(format!("invalid pin `{}`", name)),
+            };
+            write(&mut self.wires, &bits, value);
+            return Ok(());
+        }
+        let memory = self.instance(base).and_then(|instance| instance.memory);
+        match (memory, index.and_then(|index| index.parse::<usize>().ok())) {
+            (Some(memory), Some(address)) => self.poke(memory, address, value as u16),
+            _ => Err(format!("unknown input pin `{}`", name)),
+        }
+    }
+
+    fn poke(&mut self, memory: usize, address: usize, value: u16) -> Result<(), String> {
+        match &mut self.nodes[memory] {
+            Node::Memory { words, .. } if address < words.len() => {
+                words[address] = value;
+                Ok(())
+            }
+            _ => Err(format!("address {} out of range", address)),
+        }
+    }
+
+    // Reads a pin of the chip, or "Part[]" for the output of a part and "Part[n]" for a word
+    // of a memory part. Returns the value and its width in bits.
+    pub fn get(&self, name: &str) -> Result<(u64, usize), String> {
+        let (base, index) = indexed(name);
+        if let Some(signal) = self.signal(base) {
+            return match index.map(|index| index.parse::<usize>()) {
+                None => Ok((read(&self.wires, &signal.wires), signal.wires.len())),
+                Some(Ok(index)) if index < signal.wires.len() => {
+                    Ok((self.wires[signal.wires[index]] as u64, 1))
+                }
+                Some(_) => Err(format!("invalid pin `{}`", name)),
+            };
+        }
+        let instance = self
+            .instance(base)
+            .filter(|_| index.is_some())
+            .ok_or_else(|| format!("unknown pin `{}`", name))?;
+        let address = index.and_then(|index| index.parse::<usize>().ok());
+        match (instance.memory.map(|memory| &self.nodes[memory]), address) {
+            (Some(Node::Memory { words, .. }), Some(address)) => words
+                .get(address)
+                .map(|&word| (word as u64, 16))
+                .ok_or_else(|| format!("address {} out of range", address)),
+            _ => Ok((self.latched(instance), instance.out.len())),
+        }
+    }
+
+    // Returns the output of a part, where bits driven by flip-flops show the value sampled
+    // on the last tick, as the Java simulator shows registers between tick and tock
+    fn latched(&self, instance: &Instance) -> u64 {
+        instance
+            .out
+            .iter()
+            .zip(&instance.latches)
+            .rev()
+            .fold(0, |value, (&wire, latch)| {
+                let bit = match latch.map(|latch| &self.nodes[latch]) {
+                    Some(Node::Dff { next, .. }) => *next,
+                    _ => self.wires[wire],
+                };
+                (value << 1) | bit as u64
+            })
+    }
+
+    // Replaces the contents of a memory part, e.g. the program in "ROM32K"
+    pub fn load_memory(&mut self, chip: &str, content: &[u16]) -> Result<(), String> {
+        let memory = self
+            .instance(chip)
+            .and_then(|instance| instance.memory)
+            .ok_or_else(|| format!("no memory part `{}`", chip))?;
+        if let Node::Memory { words, .. } = &mut self.nodes[memory] {
+            words.iter_mut().for_each(|word| *word = 0);
+            words
+                .iter_mut()
+                .zip(content)
+                .for_each(|(word, &value)| *word = value);
+        }
+        self.eval();
+        Ok(())
+    }
+
+    // Propagates the inputs and the state through the combinational logic
+    pub fn eval(&mut self) {
+        let wires = &mut self.wires;
+        for &index in &self.order {
+            match &self.nodes[index] {
+                Node::Nand { a, b, out } => wires[*out] = !(wires[*a] && wires[*b]),
+                Node::Dff { out, state, .. } => wires[*out] = *state,
+                Node::Memory {
+                    address,
+                    out,
+                    words,
+                    ..
+                } => {
+                    let word = words[read(wires, address) as usize];
+                    write(wires, out, word as u64);
+                }
+            }
+        }
+    }
+
+    // Rising edge of the clock: clocked parts sample their inputs
+    pub fn tick(&mut self) {
+        self.eval();
+        let wires = &self.wires;
+        for node in self.nodes.iter_mut() {
+            match node {
+                Node::Dff { input, next, .. } => *next = wires[*input],
+                Node::Memory {
+                    address,
+                    input,
+                    load: Some(load),
+                    pending,
+                    ..
+                } => {
+                    *pending = Some((read(wires, address) as usize, read(wires, input) as u16))
+                        .filter(|_| wires[*load]);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Falling edge of the clock: clocked parts commit what they sampled
+    pub fn tock(&mut self) {
+        for node in self.nodes.iter_mut() {
+            match node {
+                Node::Dff { state, next, .. } => *state = *next,
+                Node::Memory { words, pending, .. } => {
+                    if let Some((address, word)) = pending.take() {
+                        words[address] = word;
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.eval();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_chip;
+    use std::path::PathBuf;
+
+    fn project(dir: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join(dir)
+    }
+
+    fn circuit(text: &str) -> Result<Circuit, Vec<Error>> {
+        let chip = parse_chip("Test.hdl", text).unwrap();
+        Circuit::new(&chip, &Library::new(Vec::new()))
+    }
+
+    #[test]
+    fn simulate_mux() {
+        let mut mux = Circuit::load(&project("01").join("Mux.hdl"), &[]).unwrap();
+        for (a, b, sel, out) in [(0, 1, 0, 0), (0, 1, 1, 1), (1, 0, 0, 1), (1, 1, 1, 1)] {
+            mux.set("a", a).unwrap();
+            mux.set("b", b).unwrap();
+            mux.set("sel", sel).unwrap();
+            mux.eval();
+            assert_eq!(Ok((out, 1)), mux.get("out"));
+        }
+        assert_eq!(0, mux.size().1);
+    }
+
+    #[test]
+    fn simulate_alu() {
+        let mut alu = Circuit::load(&project("02").join("ALU.hdl"), &[]).unwrap();
+        // nx=1, f=1, no=1 computes x-y
+        alu.set("x", 5).unwrap();
+        alu.set("y", 3).unwrap();
+        for (pin, value) in [
+            ("zx", 0),
+            ("nx", 1),
+            ("zy", 0),
+            ("ny", 0),
+            ("f", 1),
+            ("no", 1),
+        ] {
+            alu.set(pin, value).unwrap();
+        }
+        alu.eval();
+        assert_eq!(Ok((2, 16)), alu.get("out"));
+        assert_eq!(Ok((0, 1)), alu.get("zr"));
+        alu.set("y", 7).unwrap();
+        alu.eval();
+        assert_eq!(Ok((0xFFFE, 16)), alu.get("out"));
+        assert_eq!(Ok((1, 1)), alu.get("ng"));
+    }
+
+    #[test]
+    fn simulate_sequential_chips() {
+        let mut pc = Circuit::load(&project("03/a").join("PC.hdl"), &[]).unwrap();
+        pc.set("inc", 1).unwrap();
+        pc.tick();
+        assert_eq!(Ok((0, 16)), pc.get("out"));
+        pc.tock();
+        assert_eq!(Ok((1, 16)), pc.get("out"));
+        pc.set("in", 100).unwrap();
+        pc.set("load", 1).unwrap();
+        pc.tick();
+        pc.tock();
+        assert_eq!(Ok((100, 16)), pc.get("out"));
+
+        let mut ram = Circuit::load(&project("03/b").join("RAM512.hdl"), &[]).unwrap();
+        ram.set("in", 1234).unwrap();
+        ram.set("load", 1).unwrap();
+        ram.set("address", 352).unwrap();
+        ram.tick();
+        ram.tock();
+        ram.set("load", 0).unwrap();
+        ram.eval();
+        assert_eq!(Ok((1234, 16)), ram.get("out"));
+        assert_eq!(Ok((1234, 16)), ram.get("RAM64[44]"));
+    }
+
+    #[test]
+    fn report_unconnected_pins() {
+        let errors = circuit("CHIP Test { IN a; OUT out, other; PARTS: Not(in=x, out=out); }")
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![
+                Error::UnconnectedPin {
+                    chip: "Test".to_string(),
+                    pin: "other".to_string()
+                },
+                Error::UnconnectedPin {
+                    chip: "Test".to_string(),
+                    pin: "x".to_string()
+                }
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn report_width_mismatches() {
+        let errors = circuit("CHIP Test { IN a[8]; OUT out[16]; PARTS: Not16(in=a, out=out); }")
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![Error::WidthMismatch {
+                chip: "Test".to_string(),
+                part: "Not16".to_string(),
+                pin: "in".to_string(),
+                expected: 16,
+                actual: 8
+            }],
+            errors
+        );
+    }
+
+    #[test]
+    fn report_combinational_cycles() {
+        let errors = circuit("CHIP Test { IN a; OUT out; PARTS: Nand(a=a, b=x, out=x, out=out); }")
+            .err()
+            .unwrap();
+        assert_eq!(
+            vec![Error::CombinationalCycle("Test/Nand".to_string())],
+            errors
+        );
+        // A flip-flop breaks the cycle
+        assert!(circuit(
+            "CHIP Test { IN a; OUT out; PARTS: Nand(a=a, b=q, out=d, out=out); DFF(in=d, out=q); }"
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+// Reason why a chip could not be loaded or elaborated. `chip` names the chip whose
+// HDL contains the problem; cycles are located by the path of a part instance in the cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Io {
+        file: String,
+        message: String,
+    },
+    Syntax {
+        file: String,
+        line: usize,
+    },
+    ChipNotFound(String),
+    NameMismatch {
+        file: String,
+        chip: String,
+    },
+    UnknownBuiltin(String),
+    RecursiveChip(String),
+    UnknownPin {
+        chip: String,
+        part: String,
+        pin: String,
+    },
+    BusOutOfRange {
+        chip: String,
+        bus: String,
+        width: usize,
+    },
+    WidthMismatch {
+        chip: String,
+        part: String,
+        pin: String,
+        expected: usize,
+        actual: usize,
+    },
+    InvalidTarget {
+        chip: String,
+        part: String,
+        signal: String,
+    },
+    MultipleDrivers {
+        chip: String,
+        pin: String,
+    },
+    UnconnectedPin {
+        chip: String,
+        pin: String,
+    },
+    CombinationalCycle(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { file, message } => write!(f, "{}: {}", file, message),
+            Error::Syntax { file, line } => write!(f, "{}:{}: syntax error", file, line),
+            Error::ChipNotFound(chip) => write!(f, "chip `{}` not found", chip),
+            Error::NameMismatch { file, chip } => {
+                write!(f, "{}: defines chip `{}`", file, chip)
+            }
+            Error::UnknownBuiltin(name) => write!(f, "no built-in implementation of `{}`", name),
+            Error::RecursiveChip(chip) => write!(f, "chip `{}` contains itself", chip),
+            Error::UnknownPin { chip, part, pin } => {
+                write!(f, "{}: part `{}` has no pin `{}`", chip, part, pin)
+            }
+            Error::BusOutOfRange { chip, bus, width } => {
+                write!(
+                    f,
+                    "{}: `{}` is out of range of a {}-bit pin",
+                    chip, bus, width
+                )
+            }
+            Error::WidthMismatch {
+                chip,
+                part,
+                pin,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: width mismatch at `{}` of part `{}`: {} bits connected to {} bits",
+                chip, pin, part, expected, actual
+            ),
+            Error::InvalidTarget { chip, part, signal } => write!(
+                f,
+                "{}: an output of part `{}` cannot drive `{}`",
+                chip, part, signal
+            ),
+            Error::MultipleDrivers { chip, pin } => {
+                write!(f, "{}: pin `{}` has more than one driver", chip, pin)
+            }
+            Error::UnconnectedPin { chip, pin } => {
+                write!(
+                    f,
+                    "{}: pin `{}` is not connected to any part output",
+                    chip, pin
+                )
+            }
+            Error::CombinationalCycle(path) => {
+                write!(f, "combinational cycle through `{}`", path)
+            }
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod ast;
+pub mod builtins;
+pub mod circuit;
+pub mod error;
+pub mod library;
+pub mod parser;
+pub mod test_script;
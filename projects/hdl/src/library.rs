@@ -0,0 +1,81 @@
+use crate::ast::Chip;
+use crate::builtins;
+use crate::error::Error;
+use crate::parser::parse_chip;
+use collections::hashmap::HashMap;
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// Finds chips by name: "Name.hdl" in the first directory of the search path that has one,
+// otherwise the built-in chip of that name, as the Java hardware simulator does.
+pub struct Library {
+    search_path: Vec<PathBuf>,
+    chips: RefCell<HashMap<String, Rc<Chip>>>,
+}
+
+impl Library {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Library {
+            search_path,
+            chips: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn chip(&self, name: &str) -> Result<Rc<Chip>, Error> {
+        if let Some(chip) = self.chips.borrow().get(&name.to_string()) {
+            return Ok(chip.clone());
+        }
+        let file = self
+            .search_path
+            .iter()
+            .map(|dir| dir.join(format!("{}.hdl", name)))
+            .find(|file| file.is_file());
+        let chip = match file {
+            Some(file) => {
+                let file = file.to_string_lossy().into_owned();
+                let content = fs::read_to_string(&file).map_err(|error| Error::Io {
+                    file: file.clone(),
+                    message: error.to_string(),
+                })?;
+                parse_chip(&file, &content)?
+            }
+            None => {
+                let content =
+                    builtins::source(name).ok_or_else(|| Error::ChipNotFound(name.to_string()))?;
+                parse_chip(&format!("<builtin {}>", name), &content)?
+            }
+        };
+        if chip.name != name {
+            return Err(Error::NameMismatch {
+                file: format!("{}.hdl", name),
+                chip: chip.name,
+            });
+        }
+        let chip = Rc::new(chip);
+        let chips = self.chips.borrow().insert(name.to_string(), chip.clone());
+        *self.chips.borrow_mut() = chips;
+        Ok(chip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Body;
+
+    #[test]
+    fn prefer_files_over_builtins() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../01");
+        let library = Library::new(vec![dir]);
+        assert!(matches!(library.chip("Mux").unwrap().body, Body::Parts(_)));
+        // Not in projects/01, so the built-in one
+        let ram = library.chip("RAM64").unwrap();
+        assert!(matches!(&ram.body, Body::Builtin { name, .. } if name == "RAM64"));
+        assert_eq!(
+            Err(Error::ChipNotFound("Missing".to_string())),
+            library.chip("Missing")
+        );
+    }
+}
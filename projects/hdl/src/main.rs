@@ -0,0 +1,215 @@
+use hdl::circuit::Circuit;
+use hdl::test_script::HardwareSimulator;
+use script::runner::{self, Outcome};
+use script::script::{parse_script, Command};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+struct Options {
+    input: String,
+    search_path: Vec<PathBuf>,
+    keys: Vec<u16>,
+    sets: Vec<(String, u64)>,
+}
+
+// Parses "PIN=VALUE", where the value may be negative
+fn parse_set(set: &str) -> Option<(String, u64)> {
+    let (pin, value) = set.split_once('=')?;
+    Some((pin.to_string(), value.parse::<i64>().ok()? as u64))
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        input: String::new(),
+        search_path: Vec::new(),
+        keys: Vec::new(),
+        sets: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--path" => options.search_path.push(PathBuf::from(args.next()?)),
+            "--keys" => {
+                options.keys = args
+                    .next()?
+                    .split(',')
+                    .map(|key| key.parse().ok())
+                    .collect::<Option<Vec<u16>>>()?
+            }
+            input if !input.starts_with("--") && options.input.is_empty() => {
+                options.input = input.to_string()
+            }
+            set if options.input.ends_with(".hdl") => options.sets.push(parse_set(set)?),
+            _ => return None,
+        }
+    }
+    Some(options).filter(|options| !options.input.is_empty())
+}
+
+// Elaborates a chip, evaluates it with the given inputs and prints its outputs
+fn run_chip(options: &Options) -> Result<(), String> {
+    let search_path = options
+        .search_path
+        .iter()
+        .map(PathBuf::as_path)
+        .collect::<Vec<&Path>>();
+    let mut circuit = Circuit::load(Path::new(&options.input), &search_path).map_err(|errors| {
+        errors
+            .iter()
+            .map(|error| format!("error: {}\n", error))
+            .collect::<String>()
+            + &format!(
+                "error: could not elaborate `{}` due to {} previous error{}",
+                options.input,
+                errors.len(),
+                if errors.len() == 1 { "" } else { "s" }
+            )
+    })?;
+    let (nands, dffs, memories) = circuit.size();
+    println!(
+        "{}: {} Nand gates, {} flip-flops, {} memories",
+        circuit.name, nands, dffs, memories
+    );
+    for (pin, value) in &options.sets {
+        circuit.set(pin, *value)?;
+    }
+    circuit.eval();
+    for pin in circuit.outputs() {
+        let (value, width) = circuit.get(&pin)?;
+        println!("{} = {:0width$b}", pin, value, width = width);
+    }
+    Ok(())
+}
+
+// Runs a .tst script and writes its output file.
+// Returns None for scripts of other simulators, which load no .hdl file.
+fn run_test(input: &Path, options: &Options) -> Result<Option<Outcome>, String> {
+    let content =
+        fs::read_to_string(input).map_err(|error| format!("{}: {}", input.display(), error))?;
+    let script =
+        parse_script(&content).map_err(|error| format!("{}: {}", input.display(), error))?;
+    let loads_chip = script.iter().any(|command| match command {
+        Command::Load(Some(file)) => file.ends_with(".hdl"),
+        _ => false,
+    });
+    if !loads_chip {
+        return Ok(None);
+    }
+    let dir = input.parent().unwrap_or(Path::new("."));
+    let mut simulator = HardwareSimulator::new(options.search_path.clone(), options.keys.clone());
+    let outcome = runner::run(&script, dir, &mut simulator)
+        .map_err(|error| format!("{}: {}", input.display(), error))?;
+    if let Some(output) = &outcome.output_file {
+        fs::write(output, outcome.output_text())
+            .map_err(|error| format!("{}: {}", output.display(), error))?;
+    }
+    Ok(Some(outcome))
+}
+
+fn failure(input: &Path, outcome: &Outcome) -> Option<String> {
+    outcome.mismatch.as_ref().map(|mismatch| {
+        format!(
+            "{}: Comparison failure at line {}\nexpected: {}\nactual:   {}",
+            input.display(),
+            mismatch.line,
+            mismatch.expected,
+            mismatch.actual
+        )
+    })
+}
+
+fn run_script(options: &Options) -> Result<(), String> {
+    let input = Path::new(&options.input);
+    match run_test(input, options)? {
+        Some(outcome) => match failure(input, &outcome) {
+            None => {
+                println!(
+                    "{}: End of script - Comparison ended successfully",
+                    input.display()
+                );
+                Ok(())
+            }
+            Some(failure) => Err(failure),
+        },
+        None => Err(format!(
+            "{}: not a hardware simulator script",
+            input.display()
+        )),
+    }
+}
+
+// Returns the .tst files under `dir`, sorted
+fn test_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(dir).map_err(|error| format!("{}: {}", dir.display(), error))?;
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    paths.into_iter().try_fold(Vec::new(), |mut files, path| {
+        if path.is_dir() {
+            files.extend(test_files(&path)?);
+        } else if path.extension().is_some_and(|extension| extension == "tst") {
+            files.push(path);
+        }
+        Ok(files)
+    })
+}
+
+// Runs every hardware script under a directory and prints a summary
+fn run_batch(options: &Options) -> Result<(), String> {
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for file in test_files(Path::new(&options.input))? {
+        match run_test(&file, options) {
+            Ok(Some(outcome)) => match failure(&file, &outcome) {
+                None => {
+                    println!("PASS {}", file.display());
+                    passed += 1;
+                }
+                Some(failure) => {
+                    println!("FAIL {}", failure);
+                    failed += 1;
+                }
+            },
+            Ok(None) => skipped += 1,
+            Err(error) => {
+                println!("FAIL {}", error);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed, {} skipped", passed, failed, skipped);
+    if failed > 0 {
+        Err(format!("{} of {} scripts failed", failed, passed + failed))
+    } else {
+        Ok(())
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    match parse_options(&args[1..]) {
+        Some(options) => {
+            let result = if options.input.ends_with(".hdl") {
+                run_chip(&options)
+            } else if options.input.ends_with(".tst") {
+                run_script(&options)
+            } else {
+                run_batch(&options)
+            };
+            result.unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(1);
+            })
+        }
+        None => {
+            eprintln!(
+                "Usage: {0} [--path DIR]... <hdl file name> [PIN=VALUE]...\n       {0} [--path DIR]... [--keys CODE,...] <tst file name>\n       {0} [--path DIR]... [--keys CODE,...] <directory>",
+                &args[0]
+            );
+            process::exit(1);
+        }
+    }
+}
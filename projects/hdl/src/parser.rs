@@ -0,0 +1,276 @@
+use crate::ast::*;
+use crate::error::Error;
+use parser::parser::*;
+
+fn optional<'a, P, A>(parser: P) -> impl Parser<'a, Option<A>>
+where
+    P: Parser<'a, A>,
+{
+    move |input: &'a str| match parser.parse(input) {
+        Ok((rest, value)) => Ok((rest, Some(value))),
+        Err(_) => Ok((input, None)),
+    }
+}
+
+// Pin and chip names; unlike `identifier` they stop at '.' so "a[0..7]" splits correctly
+fn name(input: &str) -> ParseResult<'_, String> {
+    pred(
+        one_or_more(pred(any_char, |c| c.is_ascii_alphanumeric() || *c == '_')),
+        |chars| chars[0].is_ascii_alphabetic(),
+    )
+    .map(|chars| chars.into_iter().collect())
+    .parse(input)
+}
+
+fn word(input: &str) -> ParseResult<'_, String> {
+    token(name).parse(input)
+}
+
+fn index(input: &str) -> ParseResult<'_, usize> {
+    one_or_more(pred(any_char, |c| c.is_ascii_digit()))
+        .and_then(|digits| {
+            let digits = digits.into_iter().collect::<String>();
+            move |input| {
+                digits
+                    .parse()
+                    .map(|index| (input, index))
+                    .map_err(|_| input)
+            }
+        })
+        .parse(input)
+}
+
+fn symbol<'a>(text: &'static str) -> impl Parser<'a, ()> {
+    token(match_literal(text))
+}
+
+// "[3]" or "[0..7]"
+fn range(input: &str) -> ParseResult<'_, (usize, usize)> {
+    right(
+        symbol("["),
+        left(
+            pair(token(index), optional(right(symbol(".."), token(index)))),
+            symbol("]"),
+        ),
+    )
+    .map(|(first, last)| (first, last.unwrap_or(first)))
+    .parse(input)
+}
+
+fn bus(input: &str) -> ParseResult<'_, Bus> {
+    pair(token(name), optional(range))
+        .map(|(name, range)| Bus { name, range })
+        .parse(input)
+}
+
+fn signal(input: &str) -> ParseResult<'_, Signal> {
+    bus.map(|bus| match (bus.name.as_str(), bus.range) {
+        ("true", None) => Signal::Constant(true),
+        ("false", None) => Signal::Constant(false),
+        _ => Signal::Bus(bus),
+    })
+    .parse(input)
+}
+
+fn connection(input: &str) -> ParseResult<'_, Connection> {
+    pair(bus, right(symbol("="), signal))
+        .map(|(pin, signal)| Connection { pin, signal })
+        .parse(input)
+}
+
+fn separated<'a, P, A>(parser: P) -> impl Parser<'a, Vec<A>>
+where
+    P: Parser<'a, A> + Clone + 'a,
+    A: 'a,
+{
+    pair(parser.clone(), zero_or_more(right(symbol(","), parser))).map(|(first, rest)| {
+        let mut items = vec![first];
+        items.extend(rest);
+        items
+    })
+}
+
+fn part(input: &str) -> ParseResult<'_, Part> {
+    pair(
+        token(name),
+        right(
+            symbol("("),
+            left(separated(connection), pair(symbol(")"), symbol(";"))),
+        ),
+    )
+    .map(|(name, connections)| Part { name, connections })
+    .parse(input)
+}
+
+// "a" or "a[16]"
+fn pin(input: &str) -> ParseResult<'_, Pin> {
+    pair(
+        token(name),
+        optional(right(symbol("["), left(token(index), symbol("]")))),
+    )
+    .map(|(name, width)| Pin {
+        name,
+        width: width.unwrap_or(1),
+    })
+    .parse(input)
+}
+
+fn pins<'a>(keyword: &'static str) -> impl Parser<'a, Vec<Pin>> {
+    optional(right(symbol(keyword), left(separated(pin), symbol(";"))))
+        .map(|pins| pins.unwrap_or_default())
+}
+
+fn body(input: &str) -> ParseResult<'_, Body> {
+    either(
+        right(pair(symbol("PARTS"), symbol(":")), zero_or_more(part)).map(Body::Parts),
+        pair(
+            right(symbol("BUILTIN"), left(token(name), symbol(";"))),
+            optional(right(symbol("CLOCKED"), left(separated(word), symbol(";")))),
+        )
+        .map(|(name, clocked)| Body::Builtin {
+            name,
+            clocked: clocked.unwrap_or_default(),
+        }),
+    )
+    .parse(input)
+}
+
+fn chip(input: &str) -> ParseResult<'_, Chip> {
+    right(
+        symbol("CHIP"),
+        pair(
+            token(name),
+            right(
+                symbol("{"),
+                left(pair(pins("IN"), pair(pins("OUT"), body)), symbol("}")),
+            ),
+        ),
+    )
+    .map(|(name, (inputs, (outputs, body)))| Chip {
+        name,
+        inputs,
+        outputs,
+        body,
+    })
+    .parse(input)
+}
+
+// Parses the text of a .hdl file
+pub fn parse_chip(file: &str, input: &str) -> Result<Chip, Error> {
+    match left(chip, blank()).parse(input) {
+        Ok(("", chip)) => Ok(chip),
+        Ok((rest, _)) | Err(rest) => Err(Error::Syntax {
+            file: file.to_string(),
+            line: input[..input.len() - rest.len()].matches('\n').count() + 1,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus(name: &str, range: Option<(usize, usize)>) -> Bus {
+        Bus {
+            name: name.to_string(),
+            range,
+        }
+    }
+
+    #[test]
+    fn parse_parts() {
+        let chip = parse_chip(
+            "Test.hdl",
+            "/** Doc */ CHIP Test {
+                IN a[16], sel; // comment
+                OUT out[16], first;
+                PARTS:
+                Mux16(a=a, b[0..7]=false, b[8]=true, sel=sel, out=out, out[15]=first);
+            }",
+        )
+        .unwrap();
+        assert_eq!("Test", chip.name);
+        assert_eq!(
+            vec![
+                Pin {
+                    name: "a".to_string(),
+                    width: 16
+                },
+                Pin {
+                    name: "sel".to_string(),
+                    width: 1
+                }
+            ],
+            chip.inputs
+        );
+        assert_eq!(2, chip.outputs.len());
+        let connections = match chip.body {
+            Body::Parts(parts) => parts[0].connections.clone(),
+            _ => panic!("expected parts"),
+        };
+        assert_eq!(
+            vec![
+                Connection {
+                    pin: bus("a", None),
+                    signal: Signal::Bus(bus("a", None))
+                },
+                Connection {
+                    pin: bus("b", Some((0, 7))),
+                    signal: Signal::Constant(false)
+                },
+                Connection {
+                    pin: bus("b", Some((8, 8))),
+                    signal: Signal::Constant(true)
+                },
+                Connection {
+                    pin: bus("sel", None),
+                    signal: Signal::Bus(bus("sel", None))
+                },
+                Connection {
+                    pin: bus("out", None),
+                    signal: Signal::Bus(bus("out", None))
+                },
+                Connection {
+                    pin: bus("out", Some((15, 15))),
+                    signal: Signal::Bus(bus("first", None))
+                },
+            ],
+            connections
+        );
+    }
+
+    #[test]
+    fn parse_builtin() {
+        let chip = parse_chip(
+            "DFF.hdl",
+            "CHIP DFF { IN in; OUT out; BUILTIN DFF; CLOCKED in; }",
+        )
+        .unwrap();
+        assert_eq!(
+            Body::Builtin {
+                name: "DFF".to_string(),
+                clocked: vec!["in".to_string()]
+            },
+            chip.body
+        );
+        let chip = parse_chip(
+            "Keyboard.hdl",
+            "CHIP Keyboard { OUT out[16]; BUILTIN Keyboard; }",
+        );
+        assert!(chip.unwrap().inputs.is_empty());
+    }
+
+    #[test]
+    fn report_syntax_errors() {
+        assert_eq!(
+            Err(Error::Syntax {
+                file: "Bad.hdl".to_string(),
+                line: 3
+            }),
+            parse_chip(
+                "Bad.hdl",
+                "CHIP Bad {\n IN a; OUT out;\n PARTS: Not(in=a out=out);\n}"
+            )
+        );
+    }
+}
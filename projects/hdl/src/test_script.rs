@@ -0,0 +1,175 @@
+use crate::circuit::Circuit;
+use asm::format::Format;
+use script::runner::Simulator;
+use script::script::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Runs hardware simulator scripts such as projects/03/a/PC.tst.
+// "time" counts clock cycles, shown as "N+" between a tick and the following tock.
+pub struct HardwareSimulator {
+    search_path: Vec<PathBuf>,
+    // Keys to hold down during the successive `while` loops of a script, which wait for them
+    keys: Vec<u16>,
+    waits: usize,
+    dir: PathBuf,
+    circuit: Option<Circuit>,
+    time: u64,
+    ticked: bool,
+}
+
+impl HardwareSimulator {
+    // Parts without an .hdl file next to the loaded chip are looked for in `search_path`
+    pub fn new(search_path: Vec<PathBuf>, keys: Vec<u16>) -> Self {
+        HardwareSimulator {
+            search_path,
+            keys,
+            waits: 0,
+            dir: PathBuf::from("."),
+            circuit: None,
+            time: 0,
+            ticked: false,
+        }
+    }
+
+    fn circuit(&mut self) -> Result<&mut Circuit, String> {
+        self.circuit
+            .as_mut()
+            .ok_or_else(|| "no chip loaded".to_string())
+    }
+}
+
+impl Simulator for HardwareSimulator {
+    fn load(&mut self, dir: &Path, path: Option<&str>) -> Result<(), String> {
+        let path = path.ok_or("`load` needs a chip")?;
+        let search_path = self
+            .search_path
+            .iter()
+            .map(PathBuf::as_path)
+            .collect::<Vec<&Path>>();
+        let circuit = Circuit::load(&dir.join(path), &search_path).map_err(|errors| {
+            errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<String>>()
+                .join("\n")
+        })?;
+        self.dir = dir.to_path_buf();
+        self.circuit = Some(circuit);
+        self.time = 0;
+        self.ticked = false;
+        self.waits = 0;
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: i64) -> Result<(), String> {
+        self.circuit()?.set(name, value as u64)
+    }
+
+    fn get(&self, name: &str) -> Result<Value, String> {
+        if name == "time" {
+            let suffix = if self.ticked { "+" } else { "" };
+            return Ok(Value::Text(format!("{}{}", self.time, suffix)));
+        }
+        let circuit = self.circuit.as_ref().ok_or("no chip loaded")?;
+        let (value, width) = circuit.get(name)?;
+        // 16-bit values read as two's complement, as in the Java simulator
+        Ok(Value::Number(if width == 16 {
+            value as u16 as i16 as i64
+        } else {
+            value as i64
+        }))
+    }
+
+    fn action(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        match (name, args) {
+            ("eval", []) => self.circuit()?.eval(),
+            ("tick", []) => {
+                self.circuit()?.tick();
+                self.ticked = true;
+            }
+            ("tock", []) => {
+                self.circuit()?.tock();
+                self.ticked = false;
+                self.time += 1;
+            }
+            ("ticktock", []) => {
+                self.action("tick", args)?;
+                self.action("tock", args)?;
+            }
+            (memory, [command, file]) if command == "load" => {
+                let path = self.dir.join(file);
+                let content =
+                    fs::read(&path).map_err(|error| format!("{}: {}", path.display(), error))?;
+                let words = Format::Hack
+                    .decode(&content)
+                    .map_err(|error| format!("{}: {}", file, error))?;
+                self.circuit()?.load_memory(memory, &words)?;
+            }
+            _ => return Err(format!("unknown command `{}`", name)),
+        }
+        Ok(())
+    }
+
+    fn waiting(&mut self, waiting: bool) {
+        let key = match self.keys.get(self.waits) {
+            Some(&key) if waiting => key,
+            Some(_) => {
+                // Released keys show up at the next evaluation
+                self.waits += 1;
+                0
+            }
+            None => return,
+        };
+        if let Some(circuit) = self.circuit.as_mut() {
+            // Chips without a keyboard ignore the key
+            if circuit.set("Keyboard[0]", key as u64).is_ok() && waiting {
+                circuit.eval();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use script::runner::run;
+    use script::script::parse_script;
+
+    fn run_test(dir: &str, test: &str) -> script::runner::Outcome {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(dir);
+        let script = parse_script(&fs::read_to_string(dir.join(test)).unwrap()).unwrap();
+        run(
+            &script,
+            &dir,
+            &mut HardwareSimulator::new(Vec::new(), vec![75, 89]),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn run_combinational_tests() {
+        let outcome = run_test("01", "Mux.tst");
+        assert_eq!(None, outcome.mismatch);
+        assert_eq!("|   a   |   b   |  sel  |  out  |", outcome.output[0]);
+        assert_eq!(None, run_test("02", "ALU.tst").mismatch);
+    }
+
+    #[test]
+    fn run_sequential_tests() {
+        assert_eq!(None, run_test("03/a", "PC.tst").mismatch);
+        assert_eq!(None, run_test("05", "CPU.tst").mismatch);
+        // Holds down 'K' and 'Y' as the script asks
+        assert_eq!(None, run_test("05", "Memory.tst").mismatch);
+    }
+
+    #[test]
+    fn run_computer_test() {
+        let outcome = run_test("05", "ComputerMax.tst");
+        assert_eq!(None, outcome.mismatch);
+        assert_eq!(
+            "| 0    |  0  |       0 |       0 |   0|       3 |       5 |       0 |",
+            outcome.output[1]
+        );
+    }
+}
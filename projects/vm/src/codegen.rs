@@ -0,0 +1,149 @@
+use crate::command::{command, parse_line, ArithOp, Command, Segment};
+use crate::error::{Cause, Error};
+
+// What a code generator needs to know besides the command, shared by all the files of
+// a program so that the labels it generates are unique
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TranslatorState {
+    // Stem of the file being translated, which scopes its static variables
+    pub file: String,
+    // Function being translated, which scopes its labels, or None at the top level
+    pub function: Option<String>,
+    // Functions declared so far, in order
+    pub functions: Vec<String>,
+    // Numbers of the comparisons of each kind, calls and if-gotos generated so far
+    pub eq: usize,
+    pub gt: usize,
+    pub lt: usize,
+    pub calls: usize,
+    pub branches: usize,
+}
+
+impl TranslatorState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Scopes a label to the current function, as the interpreter does
+    pub fn scoped(&self, label: &str) -> String {
+        match &self.function {
+            Some(function) => format!("{}${}", function, label),
+            None => label.to_string(),
+        }
+    }
+}
+
+// A backend of the translator, with one method per command. The driver keeps
+// `state.file` and `state.function` up to date; the counters are the backend's.
+pub trait CodeGen {
+    // Sets up the stack and calls the entry function, usually Sys.init
+    fn bootstrap(&mut self, state: &mut TranslatorState, entry: &str);
+    fn push(&mut self, state: &mut TranslatorState, segment: Segment, index: u16);
+    // Never called with the constant segment, which the parser rejects
+    fn pop(&mut self, state: &mut TranslatorState, segment: Segment, index: u16);
+    fn arithmetic(&mut self, state: &mut TranslatorState, op: ArithOp);
+    fn label(&mut self, state: &mut TranslatorState, label: &str);
+    fn goto(&mut self, state: &mut TranslatorState, label: &str);
+    fn if_goto(&mut self, state: &mut TranslatorState, label: &str);
+    fn function(&mut self, state: &mut TranslatorState, name: &str, locals: u16);
+    fn call(&mut self, state: &mut TranslatorState, function: &str, arguments: u16);
+    fn r#return(&mut self, state: &mut TranslatorState);
+    // Jumps to `label` if `op` ("eq", "gt" or "lt") holds for the two values on top of the
+    // stack, or does not hold if `negated`, popping them
+    fn if_compare(&mut self, state: &mut TranslatorState, op: ArithOp, negated: bool, label: &str);
+    // Jumps to `label` unless the value on top of the stack is true (-1), popping it
+    fn if_not(&mut self, state: &mut TranslatorState, label: &str);
+    // Calls a function that returns to the caller of the current one, in its frame
+    fn tail_call(&mut self, state: &mut TranslatorState, function: &str, arguments: u16);
+    // Called once after the last file, e.g. to write code shared by the whole program
+    fn finish(&mut self, _: &mut TranslatorState) {}
+}
+
+// Passes a command to the code generator, entering the function it declares if any
+pub fn generate<G: CodeGen + ?Sized>(
+    codegen: &mut G,
+    state: &mut TranslatorState,
+    command: &Command,
+) {
+    match command {
+        Command::Push(segment, index) => codegen.push(state, *segment, *index),
+        Command::Pop(segment, index) => codegen.pop(state, *segment, *index),
+        Command::Arithmetic(op) => codegen.arithmetic(state, *op),
+        Command::Label(label) => codegen.label(state, label),
+        Command::Goto(label) => codegen.goto(state, label),
+        Command::IfGoto(label) => codegen.if_goto(state, label),
+        Command::Function(name, locals) => {
+            state.function = Some(name.clone());
+            state.functions.push(name.clone());
+            codegen.function(state, name, *locals)
+        }
+        Command::Call(function, arguments) => codegen.call(state, function, *arguments),
+        Command::Return => codegen.r#return(state),
+        Command::IfCompare { op, negated, label } => {
+            codegen.if_compare(state, *op, *negated, label)
+        }
+        Command::IfNot(label) => codegen.if_not(state, label),
+        Command::TailCall(function, arguments) => codegen.tail_call(state, function, *arguments),
+    }
+}
+
+// Parses the lines of the file with stem `file`. Lines that are not commands, and
+// returns and calls outside a function, are reported.
+pub fn parse(file: &str, lines: &[&str]) -> Result<Vec<Command>, Vec<Error>> {
+    let parser = command();
+    let mut commands = Vec::new();
+    let mut errors = Vec::new();
+    // Whether a function was declared, as the file starts at the top level
+    let mut function = false;
+    for (index, line) in lines.iter().enumerate() {
+        let command = parse_line(&parser, line).and_then(|command| match command {
+            Some(Command::Return) if !function => Err(Cause::ReturnOutsideFunction),
+            Some(Command::Call(_, _)) if !function => Err(Cause::CallOutsideFunction),
+            command => Ok(command),
+        });
+        match command {
+            Ok(Some(command)) => {
+                function |= matches!(command, Command::Function(_, _));
+                commands.push(command);
+            }
+            Ok(None) => (),
+            Err(cause) => errors.push(Error {
+                file: file.to_string(),
+                line: index + 1,
+                source: line.trim().to_string(),
+                cause,
+            }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(commands)
+    } else {
+        Err(errors)
+    }
+}
+
+// Generates the commands of the file with stem `file`, which starts at the top level
+pub fn generate_file<G: CodeGen + ?Sized>(
+    codegen: &mut G,
+    state: &mut TranslatorState,
+    file: &str,
+    commands: &[Command],
+) {
+    state.file = file.to_string();
+    state.function = None;
+    for command in commands {
+        generate(codegen, state, command);
+    }
+}
+
+// Translates the lines of the file with stem `file`, if they have no errors
+pub fn translate<G: CodeGen + ?Sized>(
+    codegen: &mut G,
+    state: &mut TranslatorState,
+    file: &str,
+    lines: &[&str],
+) -> Result<(), Vec<Error>> {
+    let commands = parse(file, lines)?;
+    generate_file(codegen, state, file, &commands);
+    Ok(())
+}
@@ -1,21 +1,205 @@
+use crate::error::Cause;
 use parser::parser::*;
+use std::fmt;
 
-#[derive(PartialEq, Debug)]
+// The largest index of a segment, and the largest constant
+pub const MAX_INDEX: u16 = 0x7FFF;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Segment {
+    Local,
+    Argument,
+    This,
+    That,
+    Pointer,
+    Temp,
+    Static,
+    Constant,
+}
+
+pub const SEGMENTS: [Segment; 8] = [
+    Segment::Local,
+    Segment::Argument,
+    Segment::This,
+    Segment::That,
+    Segment::Pointer,
+    Segment::Temp,
+    Segment::Static,
+    Segment::Constant,
+];
+
+impl Segment {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Segment::Local => "local",
+            Segment::Argument => "argument",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Pointer => "pointer",
+            Segment::Temp => "temp",
+            Segment::Static => "static",
+            Segment::Constant => "constant",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Segment> {
+        SEGMENTS.into_iter().find(|segment| segment.name() == name)
+    }
+
+    // Returns the largest index of the segment
+    pub fn max_index(&self) -> u16 {
+        match self {
+            Segment::Pointer => 1,
+            Segment::Temp => 7,
+            _ => MAX_INDEX,
+        }
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+pub const ARITH_OPS: [ArithOp; 9] = [
+    ArithOp::Add,
+    ArithOp::Sub,
+    ArithOp::Neg,
+    ArithOp::Eq,
+    ArithOp::Gt,
+    ArithOp::Lt,
+    ArithOp::And,
+    ArithOp::Or,
+    ArithOp::Not,
+];
+
+impl ArithOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArithOp::Add => "add",
+            ArithOp::Sub => "sub",
+            ArithOp::Neg => "neg",
+            ArithOp::Eq => "eq",
+            ArithOp::Gt => "gt",
+            ArithOp::Lt => "lt",
+            ArithOp::And => "and",
+            ArithOp::Or => "or",
+            ArithOp::Not => "not",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ArithOp> {
+        ARITH_OPS.into_iter().find(|op| op.name() == name)
+    }
+
+    // Returns true for "neg" and "not", which take one operand
+    pub fn is_unary(&self) -> bool {
+        matches!(self, ArithOp::Neg | ArithOp::Not)
+    }
+}
+
+impl fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum Command {
-    Push(String, String),
-    Pop(String, String),
-    Arithmetic(String),
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Arithmetic(ArithOp),
     Label(String),
     IfGoto(String),
     Goto(String),
-    Function(String, String),
-    Call(String, String),
+    Function(String, u16),
+    Call(String, u16),
     Return,
-    Error(String),
+    // The commands below are produced by the optimizer only, and displayed as the commands
+    // they replace. It may also push constants above MAX_INDEX, e.g. -1 for "push constant
+    // 0; not".
+    // "eq", "gt" or "lt", optionally followed by "not", followed by "if-goto"
+    IfCompare {
+        op: ArithOp,
+        negated: bool,
+        label: String,
+    },
+    // "not" followed by "if-goto", which jumps unless the value is true (-1)
+    IfNot(String),
+    // "call" followed by "return", which reuses the frame of the calling function
+    TailCall(String, u16),
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Push(segment, index) => write!(f, "push {} {}", segment, index),
+            Command::Pop(segment, index) => write!(f, "pop {} {}", segment, index),
+            Command::Arithmetic(op) => write!(f, "{}", op),
+            Command::Label(label) => write!(f, "label {}", label),
+            Command::IfGoto(label) => write!(f, "if-goto {}", label),
+            Command::Goto(label) => write!(f, "goto {}", label),
+            Command::Function(name, locals) => write!(f, "function {} {}", name, locals),
+            Command::Call(name, arguments) => write!(f, "call {} {}", name, arguments),
+            Command::Return => write!(f, "return"),
+            Command::IfCompare { op, negated, label } => write!(
+                f,
+                "{}; {}if-goto {}",
+                op,
+                if *negated { "not; " } else { "" },
+                label
+            ),
+            Command::IfNot(label) => write!(f, "not; if-goto {}", label),
+            Command::TailCall(name, arguments) => {
+                write!(f, "call {} {}; return", name, arguments)
+            }
+        }
+    }
+}
+
+// Parses an index, a number of locals or of arguments no greater than `max`
+fn parse_index(index: &str, max: u16) -> Result<u16, Cause> {
+    index
+        .parse::<u16>()
+        .ok()
+        .filter(|&index| index <= max)
+        .ok_or_else(|| Cause::InvalidIndex(index.to_string()))
 }
 
-// Returns a parser for a command
-pub fn command<'a>() -> impl Parser<'a, Command> {
+// Checks the segment and index of a push or pop
+fn access(segment: &str, index: &str, pop: bool) -> Result<(Segment, u16), Cause> {
+    let segment =
+        Segment::from_name(segment).ok_or_else(|| Cause::UnknownSegment(segment.to_string()))?;
+    if pop && segment == Segment::Constant {
+        return Err(Cause::PopConstant);
+    }
+    match parse_index(index, MAX_INDEX) {
+        Ok(value) if value <= segment.max_index() => Ok((segment, value)),
+        Ok(_) => Err(Cause::IndexOutOfRange {
+            segment,
+            index: index.to_string(),
+        }),
+        Err(cause) => Err(cause),
+    }
+}
+
+// Returns a parser for a command. Lines that look like a command but are not one, such
+// as "pop constant 1", give the cause.
+pub fn command<'a>() -> impl Parser<'a, Result<Command, Cause>> {
     whitespace_wrap(right(
         simple_comment(),
         either(
@@ -25,39 +209,63 @@ pub fn command<'a>() -> impl Parser<'a, Command> {
     ))
 }
 
+// Parses a line of a .vm file, which is None if it is blank or a comment
+pub fn parse_line<'a>(
+    parser: &impl Parser<'a, Result<Command, Cause>>,
+    line: &'a str,
+) -> Result<Option<Command>, Cause> {
+    match parser.parse(line) {
+        Ok(("", command)) => command.map(Some),
+        Ok(_) => Err(Cause::Syntax),
+        Err(_) if line.split("//").next().unwrap_or("").trim().is_empty() => Ok(None),
+        Err(_) => Err(Cause::Syntax),
+    }
+}
+
 // Returns a parser for a command with its target and variable
-fn command_and_target_variable<'a>() -> impl Parser<'a, Command> {
+fn command_and_target_variable<'a>() -> impl Parser<'a, Result<Command, Cause>> {
     pair(
         identifier,
-        pair(right(space1(), identifier), right(space1(), number)),
+        // An identifier in place of the number is an invalid index rather than a syntax error
+        pair(
+            right(space1(), identifier),
+            right(space1(), either(number, identifier)),
+        ),
     )
     .map(|(command, (target, variable))| match command.as_str() {
-        "push" => Command::Push(target, variable),
-        "pop" => Command::Pop(target, variable),
-        "function" => Command::Function(target, variable),
-        "call" => Command::Call(target, variable),
-        _ => Command::Error(command),
+        "push" => {
+            access(&target, &variable, false).map(|(segment, index)| Command::Push(segment, index))
+        }
+        "pop" => {
+            access(&target, &variable, true).map(|(segment, index)| Command::Pop(segment, index))
+        }
+        "function" => {
+            parse_index(&variable, MAX_INDEX).map(|locals| Command::Function(target, locals))
+        }
+        "call" => {
+            parse_index(&variable, MAX_INDEX).map(|arguments| Command::Call(target, arguments))
+        }
+        _ => Err(Cause::Syntax),
     })
 }
 
 // Returns a parser for a command with its target
-fn command_and_target<'a>() -> impl Parser<'a, Command> {
+fn command_and_target<'a>() -> impl Parser<'a, Result<Command, Cause>> {
     pair(identifier, right(space1(), identifier)).map(|(command, target)| match command.as_str() {
-        "label" => Command::Label(target),
-        "if-goto" => Command::IfGoto(target),
-        "goto" => Command::Goto(target),
-        _ => Command::Error(command),
+        "label" => Ok(Command::Label(target)),
+        "if-goto" => Ok(Command::IfGoto(target)),
+        "goto" => Ok(Command::Goto(target)),
+        _ => Err(Cause::Syntax),
     })
 }
 
 // Returns a parser for a command as itself
-fn command_only<'a>() -> impl Parser<'a, Command> {
+fn command_only<'a>() -> impl Parser<'a, Result<Command, Cause>> {
     identifier.map(|command| match command.as_str() {
-        "add" | "sub" | "neg" | "eq" | "gt" | "lt" | "and" | "or" | "not" => {
-            Command::Arithmetic(command)
-        }
-        "return" => Command::Return,
-        _ => Command::Error(command),
+        "return" => Ok(Command::Return),
+        _ => ArithOp::from_name(&command)
+            .map(Command::Arithmetic)
+            .ok_or(Cause::Syntax),
     })
 }
 
@@ -65,100 +273,146 @@ fn command_only<'a>() -> impl Parser<'a, Command> {
 mod tests {
     use super::*;
 
+    fn parsed<'a>(result: ParseResult<'a, Result<Command, Cause>>) -> Option<Command> {
+        match result {
+            Ok(("", Ok(command))) => Some(command),
+            _ => None,
+        }
+    }
+
     #[test]
     fn parse_push_pop_command() {
         assert_eq!(
-            Ok(("", Command::Push("constant".to_string(), "10".to_string()))),
-            command_and_target_variable().parse("push constant 10")
+            Some(Command::Push(Segment::Constant, 10)),
+            parsed(command_and_target_variable().parse("push constant 10"))
         );
         assert_eq!(
-            Ok(("", Command::Pop("local".to_string(), "0".to_string()))),
-            command_and_target_variable().parse("pop local 0")
+            Some(Command::Pop(Segment::Local, 0)),
+            parsed(command_and_target_variable().parse("pop local 0"))
         );
     }
 
     #[test]
     fn parse_arithmetic_command() {
         assert_eq!(
-            Ok(("", Command::Arithmetic("add".to_string()))),
-            command_only().parse("add")
+            Some(Command::Arithmetic(ArithOp::Add)),
+            parsed(command_only().parse("add"))
         );
     }
 
     #[test]
     fn parse_branching_command() {
         assert_eq!(
-            Ok(("", Command::Label("LOOP_START".to_string()))),
-            command_and_target().parse("label LOOP_START")
+            Some(Command::Label("LOOP_START".to_string())),
+            parsed(command_and_target().parse("label LOOP_START"))
         );
         assert_eq!(
-            Ok(("", Command::IfGoto("LOOP_START".to_string()))),
-            command_and_target().parse("if-goto LOOP_START")
+            Some(Command::IfGoto("LOOP_START".to_string())),
+            parsed(command_and_target().parse("if-goto LOOP_START"))
         );
         assert_eq!(
-            Ok(("", Command::Goto("LOOP_START".to_string()))),
-            command_and_target().parse("goto LOOP_START")
+            Some(Command::Goto("LOOP_START".to_string())),
+            parsed(command_and_target().parse("goto LOOP_START"))
         );
     }
 
     #[test]
     fn parse_function_call_command() {
         assert_eq!(
-            Ok((
-                "",
-                Command::Function("Class.test".to_string(), "2".to_string())
-            )),
-            command_and_target_variable().parse("function Class.test 2")
+            Some(Command::Function("Class.test".to_string(), 2)),
+            parsed(command_and_target_variable().parse("function Class.test 2"))
         );
         assert_eq!(
-            Ok(("", Command::Call("Class.test".to_string(), "2".to_string()))),
-            command_and_target_variable().parse("call Class.test 2")
+            Some(Command::Call("Class.test".to_string(), 2)),
+            parsed(command_and_target_variable().parse("call Class.test 2"))
         );
     }
 
     #[test]
     fn parse_return_command() {
-        assert_eq!(Ok(("", Command::Return)), command_only().parse("return"));
+        assert_eq!(
+            Some(Command::Return),
+            parsed(command_only().parse("return"))
+        );
     }
 
     #[test]
     fn parse_command() {
-        assert_eq!(Err(""), command().parse("   // Comment"));
+        assert!(command().parse("   // Comment").is_err());
         assert_eq!(
-            Ok(("", Command::Push("constant".to_string(), "21".to_string()))),
-            command().parse("   push constant 21")
+            Some(Command::Push(Segment::Constant, 21)),
+            parsed(command().parse("   push constant 21"))
         );
         assert_eq!(
-            Ok(("", Command::Push("constant".to_string(), "21".to_string()))),
-            command().parse("   push constant 21 // Comment")
+            Some(Command::Push(Segment::Constant, 21)),
+            parsed(command().parse("   push constant 21 // Comment"))
+        );
+        assert_eq!(
+            Some(Command::Arithmetic(ArithOp::Add)),
+            parsed(command().parse("  add"))
+        );
+        assert_eq!(
+            Some(Command::Label("LOOP_START".to_string())),
+            parsed(command().parse("  label LOOP_START"))
+        );
+        assert_eq!(
+            Some(Command::IfGoto("LOOP_START".to_string())),
+            parsed(command().parse("  if-goto LOOP_START"))
+        );
+        assert_eq!(
+            Some(Command::Goto("LOOP_START".to_string())),
+            parsed(command().parse("  goto LOOP_START"))
+        );
+        assert_eq!(
+            Some(Command::Function("Class.test".to_string(), 2)),
+            parsed(command().parse("  function Class.test 2"))
+        );
+        assert_eq!(
+            Some(Command::Call("Class.test".to_string(), 2)),
+            parsed(command().parse("  call Class.test 2"))
+        );
+        assert_eq!(Some(Command::Return), parsed(command().parse("  return")));
+    }
+
+    #[test]
+    fn parse_lines() {
+        let parser = command();
+        assert_eq!(Ok(None), parse_line(&parser, "  // Comment"));
+        assert_eq!(Ok(None), parse_line(&parser, ""));
+        assert_eq!(
+            Ok(Some(Command::Pop(Segment::Temp, 7))),
+            parse_line(&parser, "pop temp 7")
         );
         assert_eq!(
-            Ok(("", Command::Arithmetic("add".to_string()))),
-            command().parse("  add")
+            Err(Cause::PopConstant),
+            parse_line(&parser, "pop constant 1")
         );
         assert_eq!(
-            Ok(("", Command::Label("LOOP_START".to_string()))),
-            command().parse("  label LOOP_START")
+            Err(Cause::IndexOutOfRange {
+                segment: Segment::Pointer,
+                index: "2".to_string()
+            }),
+            parse_line(&parser, "push pointer 2")
         );
         assert_eq!(
-            Ok(("", Command::IfGoto("LOOP_START".to_string()))),
-            command().parse("  if-goto LOOP_START")
+            Err(Cause::IndexOutOfRange {
+                segment: Segment::Temp,
+                index: "8".to_string()
+            }),
+            parse_line(&parser, "pop temp 8")
         );
         assert_eq!(
-            Ok(("", Command::Goto("LOOP_START".to_string()))),
-            command().parse("  goto LOOP_START")
+            Err(Cause::InvalidIndex("32768".to_string())),
+            parse_line(&parser, "push constant 32768")
         );
         assert_eq!(
-            Ok((
-                "",
-                Command::Function("Class.test".to_string(), "2".to_string())
-            )),
-            command().parse("  function Class.test 2")
+            Err(Cause::UnknownSegment("heap".to_string())),
+            parse_line(&parser, "push heap 0")
         );
         assert_eq!(
-            Ok(("", Command::Call("Class.test".to_string(), "2".to_string()))),
-            command().parse("  call Class.test 2")
+            Err(Cause::InvalidIndex("x".to_string())),
+            parse_line(&parser, "push that x")
         );
-        assert_eq!(Ok(("", Command::Return)), command().parse("  return"));
+        assert_eq!(Err(Cause::Syntax), parse_line(&parser, "jump"));
     }
 }
@@ -0,0 +1,306 @@
+use crate::codegen::{CodeGen, TranslatorState};
+use crate::command::{ArithOp, Segment};
+use crate::translation::{Hack, BOOTSTRAP, RETURN};
+
+// Routines shared by the whole program. Call sites pass the function and the number of
+// arguments of a call in R13 and R14, and jump to the routine with the return address in
+// D rather than in R15: "@RET D=A" is two instructions fewer at every call site than also
+// storing it, and the routine saves it once, to R15 (comparisons) or on the stack (call),
+// before using D.
+
+// For "eq", "gt" and "lt", from the routine's `name` and the jump taken when the
+// comparison is true
+fn comparison(name: &str, jump: &str) -> String {
+    format!(
+        r#"({name})
+@R15
+M=D
+@SP
+AM=M-1
+D=M
+A=A-1
+D=D-M
+M=-1
+@{name}.TRUE
+D;{jump}
+@SP
+A=M-1
+M=0
+({name}.TRUE)
+@R15
+A=M
+0;JMP"#
+    )
+}
+
+// For "call", with the function in R13 and the number of arguments in R14
+const CALL: &str = r#"($CALL)
+// save return address to stack
+@SP
+A=M
+M=D
+// save LCL
+@LCL
+D=M
+@SP
+AM=M+1
+M=D
+// save ARG
+@ARG
+D=M
+@SP
+AM=M+1
+M=D
+// save THIS
+@THIS
+D=M
+@SP
+AM=M+1
+M=D
+// save THAT
+@THAT
+D=M
+@SP
+AM=M+1
+M=D
+@SP
+M=M+1
+// ARG = SP - 5 - nArgs
+D=M
+@5
+D=D-A
+@R14
+D=D-M
+@ARG
+M=D
+// set LCL to SP
+@SP
+D=M
+@LCL
+M=D
+// jump to the function
+@R13
+A=M
+0;JMP"#;
+
+// Stops the program before the routines, where it would otherwise run into them
+const END: &str = r#"($END)
+@$END
+0;JMP"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Routine {
+    Call,
+    Return,
+    Eq,
+    Gt,
+    Lt,
+}
+
+impl Routine {
+    fn label(self) -> &'static str {
+        match self {
+            Routine::Call => "$CALL",
+            Routine::Return => "$RETURN",
+            Routine::Eq => "$EQ",
+            Routine::Gt => "$GT",
+            Routine::Lt => "$LT",
+        }
+    }
+
+    fn code(self) -> String {
+        match self {
+            Routine::Call => CALL.to_string(),
+            Routine::Return => format!("({})\n{}", self.label(), RETURN),
+            Routine::Eq => comparison(self.label(), "JEQ"),
+            Routine::Gt => comparison(self.label(), "JLT"),
+            Routine::Lt => comparison(self.label(), "JGT"),
+        }
+    }
+}
+
+// Generates the same code as `Hack`, except that calls, returns and comparisons jump to
+// routines written once after the program by `finish`, which keeps large programs in ROM
+#[derive(Debug, Clone, Default)]
+pub struct Compact {
+    pub hack: Hack,
+    // Routines jumped to so far, sorted
+    routines: Vec<Routine>,
+}
+
+impl Compact {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the assembly generated so far, one instruction per line
+    pub fn text(&self) -> String {
+        self.hack.text()
+    }
+
+    // Returns the number of instructions generated so far, i.e. of words of ROM
+    pub fn instructions(&self) -> usize {
+        self.hack.instructions()
+    }
+
+    // Jumps to `routine` with `return_label` in D and returns there
+    fn jump(&mut self, comment: String, setup: &[&str], routine: Routine, return_label: &str) {
+        let jump = format!(
+            "@{return_label}\nD=A\n@{}\n0;JMP\n({return_label})",
+            routine.label()
+        );
+        self.hack.emit(comment, &[setup, &[jump.as_str()]].concat());
+        self.used(routine);
+    }
+
+    fn used(&mut self, routine: Routine) {
+        if let Err(index) = self.routines.binary_search(&routine) {
+            self.routines.insert(index, routine);
+        }
+    }
+}
+
+impl CodeGen for Compact {
+    fn bootstrap(&mut self, state: &mut TranslatorState, entry: &str) {
+        self.hack.emit("bootstrap".to_string(), &[BOOTSTRAP]);
+        self.call(state, entry, 0);
+    }
+
+    fn push(&mut self, state: &mut TranslatorState, segment: Segment, index: u16) {
+        self.hack.push(state, segment, index);
+    }
+
+    fn pop(&mut self, state: &mut TranslatorState, segment: Segment, index: u16) {
+        self.hack.pop(state, segment, index);
+    }
+
+    fn arithmetic(&mut self, state: &mut TranslatorState, op: ArithOp) {
+        let (routine, return_label) = match op {
+            ArithOp::Eq => {
+                state.eq += 1;
+                (Routine::Eq, format!("EQUAL.{}", state.eq - 1))
+            }
+            ArithOp::Gt => {
+                state.gt += 1;
+                (Routine::Gt, format!("GREATERTHAN.{}", state.gt - 1))
+            }
+            ArithOp::Lt => {
+                state.lt += 1;
+                (Routine::Lt, format!("LESSTHAN.{}", state.lt - 1))
+            }
+            _ => return self.hack.arithmetic(state, op),
+        };
+        self.jump(op.to_string(), &[], routine, &return_label);
+    }
+
+    fn label(&mut self, state: &mut TranslatorState, label: &str) {
+        self.hack.label(state, label);
+    }
+
+    fn goto(&mut self, state: &mut TranslatorState, label: &str) {
+        self.hack.goto(state, label);
+    }
+
+    fn if_goto(&mut self, state: &mut TranslatorState, label: &str) {
+        self.hack.if_goto(state, label);
+    }
+
+    fn function(&mut self, state: &mut TranslatorState, name: &str, locals: u16) {
+        self.hack.function(state, name, locals);
+    }
+
+    fn call(&mut self, state: &mut TranslatorState, function: &str, arguments: u16) {
+        let return_label = state.scoped(&format!("ret.{}", state.calls));
+        state.calls += 1;
+        // 0 and 1 are constants of the ALU, which saves loading them in A
+        let count = match arguments {
+            0 | 1 => format!("@R14\nM={}", arguments),
+            _ => format!("@{}\nD=A\n@R14\nM=D", arguments),
+        };
+        self.jump(
+            format!("call {} {}", function, arguments),
+            &[&format!("@{}\nD=A\n@R13\nM=D", function), &count],
+            Routine::Call,
+            &return_label,
+        );
+    }
+
+    fn r#return(&mut self, _: &mut TranslatorState) {
+        self.hack.emit(
+            "return".to_string(),
+            &[&format!("@{}\n0;JMP", Routine::Return.label())],
+        );
+        self.used(Routine::Return);
+    }
+
+    fn if_compare(&mut self, state: &mut TranslatorState, op: ArithOp, negated: bool, label: &str) {
+        self.hack.if_compare(state, op, negated, label);
+    }
+
+    fn if_not(&mut self, state: &mut TranslatorState, label: &str) {
+        self.hack.if_not(state, label);
+    }
+
+    fn tail_call(&mut self, state: &mut TranslatorState, function: &str, arguments: u16) {
+        self.hack.tail_call(state, function, arguments);
+    }
+
+    fn finish(&mut self, _: &mut TranslatorState) {
+        if self.routines.is_empty() {
+            return;
+        }
+        self.hack.emit("end".to_string(), &[END]);
+        for routine in self.routines.clone() {
+            self.hack
+                .emit(format!("routine {}", routine.label()), &[&routine.code()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::translate;
+
+    #[test]
+    fn share_routines() {
+        let main = [
+            "function Main.main 0",
+            "push constant 1",
+            "push constant 2",
+            "lt",
+            "push constant 3",
+            "eq",
+            "call Main.double 1",
+            "call Main.double 1",
+            "return",
+            "function Main.double 0",
+            "push argument 0",
+            "push argument 0",
+            "add",
+            "return",
+        ];
+        let mut hack = Hack::new();
+        let mut state = TranslatorState::new();
+        translate(&mut hack, &mut state, "Main", &main).unwrap();
+        hack.finish(&mut state);
+        let mut compact = Compact::new();
+        let mut state = TranslatorState::new();
+        translate(&mut compact, &mut state, "Main", &main).unwrap();
+        compact.finish(&mut state);
+
+        let text = compact.text();
+        let labels = text
+            .lines()
+            .filter(|line| line.starts_with("($") && !line.ends_with(".TRUE)"))
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            vec!["($END)", "($CALL)", "($RETURN)", "($EQ)", "($LT)"],
+            labels
+        );
+        assert_eq!(2, text.matches("@$CALL\n").count());
+        assert_eq!(2, text.matches("@$RETURN\n").count());
+        assert!(text.contains("(Main.main$ret.1)"));
+        assert!(compact.instructions() < hack.instructions());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::command::Segment;
+use std::fmt;
+
+// Why a line could not be loaded or translated, or a program stopped with an error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cause {
+    Syntax,
+    UnknownSegment(String),
+    // Not a number, or too large for a number of locals or arguments
+    InvalidIndex(String),
+    IndexOutOfRange { segment: Segment, index: String },
+    PopConstant,
+    ReturnOutsideFunction,
+    CallOutsideFunction,
+    UnknownLabel(String),
+    UnknownFunction(String),
+    StackUnderflow,
+    StackOverflow,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub file: String,
+    pub line: usize,
+    pub source: String,
+    pub cause: Cause,
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cause::Syntax => write!(f, "syntax error"),
+            Cause::UnknownSegment(segment) => write!(f, "unknown segment `{}`", segment),
+            Cause::InvalidIndex(index) => write!(f, "invalid index `{}`", index),
+            Cause::IndexOutOfRange { segment, index } => write!(
+                f,
+                "index `{}` out of range for segment `{}` (0..={})",
+                index,
+                segment,
+                segment.max_index()
+            ),
+            Cause::PopConstant => write!(f, "cannot pop to the constant segment"),
+            Cause::ReturnOutsideFunction => write!(f, "`return` outside a function"),
+            Cause::CallOutsideFunction => write!(f, "`call` outside a function"),
+            Cause::UnknownLabel(label) => write!(f, "unknown label `{}`", label),
+            Cause::UnknownFunction(function) => write!(f, "unknown function `{}`", function),
+            Cause::StackUnderflow => write!(f, "stack underflow"),
+            Cause::StackOverflow => write!(f, "stack overflow"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.vm:{}: error: {}\n  {}",
+            self.file, self.line, self.cause, self.source
+        )
+    }
+}
+
+// Formats all errors followed by a summary line. `action` is what failed, e.g. "translate".
+pub fn report(action: &str, errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("{}\n", error))
+        .collect::<String>()
+        + &format!(
+            "error: could not {} the program due to {} previous error{}",
+            action,
+            errors.len(),
+            if errors.len() == 1 { "" } else { "s" }
+        )
+}
@@ -0,0 +1,628 @@
+use crate::command::{command, parse_line, ArithOp, Command, Segment};
+use crate::error::{Cause, Error};
+use collections::hashmap::HashMap;
+
+pub const RAM_SIZE: usize = 32768;
+pub const SP: usize = 0;
+pub const LCL: usize = 1;
+pub const ARG: usize = 2;
+pub const THIS: usize = 3;
+pub const THAT: usize = 4;
+pub const TEMP: usize = 5;
+pub const STATIC: usize = 16;
+pub const STACK: usize = 256;
+pub const HEAP: usize = 2048;
+
+// Why a run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // The program entered an infinite loop on itself, e.g. "label END goto END"
+    Halted,
+    // Execution returned past the last command, e.g. from Sys.init
+    EndOfProgram,
+    // The step limit was reached
+    StepLimit,
+}
+
+// A command with where it came from. `target` is the resolved command index of a goto,
+// if-goto or call, or the RAM address of a static variable.
+#[derive(Debug, Clone)]
+struct Instruction {
+    command: Command,
+    file: String,
+    line: usize,
+    source: String,
+    target: Option<usize>,
+}
+
+// A function activation. Pops below `base`, the stack pointer after the locals were
+// pushed, would read the caller's frame.
+#[derive(Debug, Clone)]
+struct Frame {
+    function: String,
+    base: usize,
+}
+
+// VM emulator executing commands directly on a Hack RAM, with the same memory layout
+// and call frames as the translated code
+#[derive(Clone)]
+pub struct Vm {
+    pub ram: Vec<u16>,
+    pub pc: usize,
+    pub steps: u64,
+    program: Vec<Instruction>,
+    functions: HashMap<String, usize>,
+    frames: Vec<Frame>,
+}
+
+// Scopes a label to its function, as the translator does
+fn scoped(function: &Option<String>, label: &str) -> String {
+    match function {
+        Some(function) => format!("{}${}", function, label),
+        None => label.to_string(),
+    }
+}
+
+impl Vm {
+    // Loads the files given as (file stem, content). If a Sys.init function is defined,
+    // it is called as the bootstrap code does, otherwise execution starts at the first command.
+    // Either way the stack starts at 256.
+    pub fn new(files: &[(&str, &str)]) -> Result<Vm, Vec<Error>> {
+        let parser = command();
+        let mut errors = Vec::new();
+        let mut program = Vec::new();
+        // Scoped label of each goto and if-goto, to be resolved once all labels are known
+        let mut jumps = Vec::new();
+        let mut labels = HashMap::new();
+        let mut functions = HashMap::new();
+        let mut statics = HashMap::new();
+        let mut next_static = STATIC;
+
+        for (file, content) in files {
+            let mut function = None;
+            for (number, source) in content.lines().enumerate() {
+                let error = |cause| Error {
+                    file: file.to_string(),
+                    line: number + 1,
+                    source: source.trim().to_string(),
+                    cause,
+                };
+                let command = match parse_line(&parser, source) {
+                    Ok(Some(command)) => command,
+                    Ok(None) => continue,
+                    Err(cause) => {
+                        errors.push(error(cause));
+                        continue;
+                    }
+                };
+                let mut target = None;
+                match &command {
+                    Command::Push(Segment::Static, index)
+                    | Command::Pop(Segment::Static, index) => {
+                        let key = format!("{}.{}", file, index);
+                        target = Some(match statics.get(&key) {
+                            Some(&address) => address,
+                            None => {
+                                statics = statics.insert(key, next_static);
+                                next_static += 1;
+                                next_static - 1
+                            }
+                        });
+                    }
+                    Command::Label(label) => {
+                        labels = labels.insert(scoped(&function, label), program.len());
+                    }
+                    Command::Goto(label)
+                    | Command::IfGoto(label)
+                    | Command::IfNot(label)
+                    | Command::IfCompare { label, .. } => {
+                        jumps.push((program.len(), scoped(&function, label)));
+                    }
+                    Command::Function(name, _) => {
+                        function = Some(name.clone());
+                        functions = functions.insert(name.clone(), program.len());
+                    }
+                    _ => (),
+                }
+                program.push(Instruction {
+                    command,
+                    file: file.to_string(),
+                    line: number + 1,
+                    source: source.trim().to_string(),
+                    target,
+                });
+            }
+        }
+
+        for (index, label) in jumps {
+            match labels.get(&label) {
+                Some(&target) => program[index].target = Some(target),
+                None => {
+                    let instruction = &program[index];
+                    errors.push(Error {
+                        file: instruction.file.clone(),
+                        line: instruction.line,
+                        source: instruction.source.clone(),
+                        cause: Cause::UnknownLabel(label),
+                    });
+                }
+            }
+        }
+        for instruction in program.iter_mut() {
+            if let Command::Call(callee, _) | Command::TailCall(callee, _) = &instruction.command {
+                instruction.target = functions.get(callee).copied();
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut vm = Vm {
+            ram: vec![0; RAM_SIZE],
+            pc: 0,
+            steps: 0,
+            program,
+            functions,
+            frames: vec![Frame {
+                function: String::new(),
+                base: STACK,
+            }],
+        };
+        vm.ram[SP] = STACK as u16;
+        if let Some(&init) = vm.functions.get(&"Sys.init".to_string()) {
+            let end = vm.program.len();
+            vm.call("Sys.init", init, 0, end)
+                .expect("an empty stack holds the frame of Sys.init");
+        }
+        vm.skip_labels();
+        Ok(vm)
+    }
+
+    pub fn peek(&self, address: usize) -> u16 {
+        self.ram[address % RAM_SIZE]
+    }
+
+    pub fn poke(&mut self, address: usize, value: u16) {
+        self.ram[address % RAM_SIZE] = value
+    }
+
+    // Returns the function being executed, or None at the top level
+    pub fn function(&self) -> Option<&str> {
+        self.frames
+            .last()
+            .map(|frame| frame.function.as_str())
+            .filter(|function| !function.is_empty())
+    }
+
+    // Returns the command at the program counter, with its file stem and line
+    pub fn current(&self) -> Option<(&Command, &str, usize)> {
+        self.program.get(self.pc).map(|instruction| {
+            (
+                &instruction.command,
+                instruction.file.as_str(),
+                instruction.line,
+            )
+        })
+    }
+
+    fn sp(&self) -> usize {
+        self.ram[SP] as usize
+    }
+
+    // Returns the stack pointer, unless the program or the user moved it past the stack
+    fn top(&self) -> Result<usize, Cause> {
+        match self.sp() {
+            sp if sp > HEAP => Err(Cause::StackOverflow),
+            sp => Ok(sp),
+        }
+    }
+
+    fn push(&mut self, value: u16) -> Result<(), Cause> {
+        let sp = self.sp();
+        if sp >= HEAP {
+            return Err(Cause::StackOverflow);
+        }
+        self.ram[sp] = value;
+        self.ram[SP] += 1;
+        Ok(())
+    }
+
+    // Returns the number of values the current function has pushed
+    fn depth(&self) -> usize {
+        let base = self.frames.last().map_or(0, |frame| frame.base);
+        self.sp().saturating_sub(base)
+    }
+
+    fn pop(&mut self) -> Result<u16, Cause> {
+        let sp = self.top()?;
+        if self.depth() == 0 {
+            return Err(Cause::StackUnderflow);
+        }
+        self.ram[SP] -= 1;
+        Ok(self.ram[sp - 1])
+    }
+
+    // Returns the RAM address of a segment entry. `static_address` is resolved at load time.
+    fn address(&self, segment: Segment, index: u16, static_address: Option<usize>) -> usize {
+        let index = index as usize;
+        let base = |pointer: usize| self.ram[pointer] as usize;
+        let address = match segment {
+            Segment::Local => base(LCL) + index,
+            Segment::Argument => base(ARG) + index,
+            Segment::This => base(THIS) + index,
+            Segment::That => base(THAT) + index,
+            Segment::Pointer => THIS + index,
+            Segment::Temp => TEMP + index,
+            Segment::Static | Segment::Constant => static_address.unwrap_or(STATIC),
+        };
+        address % RAM_SIZE
+    }
+
+    // Saves the caller's frame and jumps to a function, as the CALL template does
+    fn call(
+        &mut self,
+        name: &str,
+        function: usize,
+        arguments: usize,
+        return_address: usize,
+    ) -> Result<(), Cause> {
+        let sp = self.top()?;
+        if self.depth() < arguments {
+            return Err(Cause::StackUnderflow);
+        }
+        if sp + 5 > HEAP {
+            return Err(Cause::StackOverflow);
+        }
+        for value in [
+            return_address as u16,
+            self.ram[LCL],
+            self.ram[ARG],
+            self.ram[THIS],
+            self.ram[THAT],
+        ] {
+            self.push(value)?;
+        }
+        self.ram[ARG] = (sp - arguments) as u16;
+        self.ram[LCL] = self.ram[SP];
+        self.frames.push(Frame {
+            function: name.to_string(),
+            base: self.sp(),
+        });
+        self.pc = function;
+        Ok(())
+    }
+
+    // Restores the caller's frame, as the RETURN template does
+    fn r#return(&mut self) -> Result<(), Cause> {
+        let end_frame = self.ram[LCL] as usize + RAM_SIZE;
+        // Saved first, as a function without arguments returns its value over it
+        let return_address = self.peek(end_frame - 5);
+        let value = self.pop()?;
+        let arg = self.ram[ARG] as usize;
+        self.poke(arg, value);
+        self.ram[SP] = (arg + 1) as u16;
+        self.ram[THAT] = self.peek(end_frame - 1);
+        self.ram[THIS] = self.peek(end_frame - 2);
+        self.ram[ARG] = self.peek(end_frame - 3);
+        self.ram[LCL] = self.peek(end_frame - 4);
+        if self.frames.len() > 1 {
+            self.frames.pop();
+        }
+        self.pc = return_address as usize;
+        Ok(())
+    }
+
+    // Moves the arguments over those of the current function and jumps to a function in
+    // its frame, as the optimizer's tail calls do
+    fn tail_call(&mut self, function: usize, arguments: usize) -> Result<(), Cause> {
+        if self.depth() < arguments {
+            return Err(Cause::StackUnderflow);
+        }
+        let (sp, arg) = (self.top()?, self.ram[ARG] as usize);
+        for index in 0..arguments {
+            let value = self.ram[sp - arguments + index];
+            self.poke(arg + index, value);
+        }
+        self.ram[SP] = self.ram[LCL];
+        self.pc = function;
+        Ok(())
+    }
+
+    fn arithmetic(&mut self, op: ArithOp) -> Result<(), Cause> {
+        let operands = if op.is_unary() { 1 } else { 2 };
+        if self.depth() < operands {
+            return Err(Cause::StackUnderflow);
+        }
+        let y = self.pop()?;
+        let value = match op {
+            ArithOp::Neg => y.wrapping_neg(),
+            ArithOp::Not => !y,
+            _ => {
+                let x = self.pop()?;
+                let boolean = |condition: bool| if condition { 0xFFFF } else { 0 };
+                // The translated code compares the sign of y - x, which wraps around
+                let difference = y.wrapping_sub(x) as i16;
+                match op {
+                    ArithOp::Add => x.wrapping_add(y),
+                    ArithOp::Sub => x.wrapping_sub(y),
+                    ArithOp::And => x & y,
+                    ArithOp::Or => x | y,
+                    ArithOp::Eq => boolean(x == y),
+                    ArithOp::Gt => boolean(difference < 0),
+                    _ => boolean(difference > 0),
+                }
+            }
+        };
+        self.push(value)
+    }
+
+    fn execute(&mut self, instruction: &Instruction) -> Result<(), Cause> {
+        let next = self.pc + 1;
+        match &instruction.command {
+            Command::Push(segment, index) => {
+                let value = if *segment == Segment::Constant {
+                    *index
+                } else {
+                    self.ram[self.address(*segment, *index, instruction.target)]
+                };
+                self.push(value)?;
+                self.pc = next;
+            }
+            Command::Pop(segment, index) => {
+                let address = self.address(*segment, *index, instruction.target);
+                self.ram[address] = self.pop()?;
+                self.pc = next;
+            }
+            Command::Arithmetic(op) => {
+                self.arithmetic(*op)?;
+                self.pc = next;
+            }
+            Command::Goto(_) => self.pc = instruction.target.unwrap_or(next),
+            Command::IfGoto(_) => {
+                self.pc = if self.pop()? != 0 {
+                    instruction.target.unwrap_or(next)
+                } else {
+                    next
+                }
+            }
+            Command::Function(name, locals) => {
+                for _ in 0..*locals {
+                    self.push(0)?;
+                }
+                let base = self.sp();
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.function = name.clone();
+                    frame.base = base;
+                }
+                self.pc = next;
+            }
+            Command::Call(callee, arguments) => {
+                let function = instruction
+                    .target
+                    .ok_or_else(|| Cause::UnknownFunction(callee.clone()))?;
+                self.call(callee, function, *arguments as usize, next)?;
+            }
+            Command::Return => self.r#return()?,
+            Command::IfCompare { op, negated, .. } => {
+                self.arithmetic(*op)?;
+                self.pc = if (self.pop()? != 0) != *negated {
+                    instruction.target.unwrap_or(next)
+                } else {
+                    next
+                }
+            }
+            Command::IfNot(_) => {
+                self.pc = if self.pop()? != 0xFFFF {
+                    instruction.target.unwrap_or(next)
+                } else {
+                    next
+                }
+            }
+            Command::TailCall(callee, arguments) => {
+                let function = instruction
+                    .target
+                    .ok_or_else(|| Cause::UnknownFunction(callee.clone()))?;
+                self.tail_call(function, *arguments as usize)?;
+            }
+            Command::Label(_) => self.pc = next,
+        }
+        Ok(())
+    }
+
+    // Moves the program counter past labels, which take no step, as in the VM emulator
+    fn skip_labels(&mut self) {
+        while let Some(Instruction {
+            command: Command::Label(_),
+            ..
+        }) = self.program.get(self.pc)
+        {
+            self.pc += 1;
+        }
+    }
+
+    // Executes one command. Returns Ok(false) if the program counter is outside the program.
+    pub fn step(&mut self) -> Result<bool, Error> {
+        let instruction = match self.program.get(self.pc) {
+            Some(instruction) => instruction.clone(),
+            None => return Ok(false),
+        };
+        self.execute(&instruction).map_err(|cause| Error {
+            file: instruction.file,
+            line: instruction.line,
+            source: instruction.source,
+            cause,
+        })?;
+        self.skip_labels();
+        self.steps += 1;
+        Ok(true)
+    }
+
+    // Returns true if the command at the program counter is a goto back to itself,
+    // with only labels in between
+    pub fn is_halted(&self) -> bool {
+        match self.program.get(self.pc) {
+            Some(Instruction {
+                command: Command::Goto(_),
+                target: Some(target),
+                ..
+            }) => {
+                *target <= self.pc
+                    && self.program[*target..self.pc]
+                        .iter()
+                        .all(|instruction| matches!(instruction.command, Command::Label(_)))
+            }
+            _ => false,
+        }
+    }
+
+    // Runs until the program halts, returns past its end or executes `max_steps` commands
+    pub fn run(&mut self, max_steps: u64) -> Result<Stop, Error> {
+        for _ in 0..max_steps {
+            if self.is_halted() {
+                return Ok(Stop::Halted);
+            }
+            if !self.step()? {
+                return Ok(Stop::EndOfProgram);
+            }
+        }
+        Ok(Stop::StepLimit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn read(dir: &str, stems: &[&str]) -> Vec<(String, String)> {
+        stems
+            .iter()
+            .map(|stem| {
+                let path = format!("{}/../{}/{}.vm", env!("CARGO_MANIFEST_DIR"), dir, stem);
+                (stem.to_string(), fs::read_to_string(path).unwrap())
+            })
+            .collect()
+    }
+
+    fn load(files: &[(String, String)]) -> Result<Vm, Vec<Error>> {
+        let files = files
+            .iter()
+            .map(|(stem, content)| (stem.as_str(), content.as_str()))
+            .collect::<Vec<(&str, &str)>>();
+        Vm::new(&files)
+    }
+
+    #[test]
+    fn run_from_sys_init() {
+        let files = read("08/FunctionCalls/FibonacciElement", &["Main", "Sys"]);
+        let mut vm = load(&files).unwrap();
+        assert_eq!(Ok(Stop::Halted), vm.run(10_000));
+        assert_eq!(262, vm.peek(SP));
+        assert_eq!(3, vm.peek(261));
+        assert_eq!(Some("Sys.init"), vm.function());
+    }
+
+    #[test]
+    fn run_without_sys_init() {
+        let files = read("07/StackArithmetic/SimpleAdd", &["SimpleAdd"]);
+        let mut vm = load(&files).unwrap();
+        assert_eq!(256, vm.peek(SP));
+        assert_eq!(Ok(Stop::EndOfProgram), vm.run(100));
+        assert_eq!((257, 15), (vm.peek(SP), vm.peek(256)));
+    }
+
+    #[test]
+    fn allocate_statics_per_file() {
+        let files = read("08/FunctionCalls/StaticsTest", &["Class1", "Class2", "Sys"]);
+        let mut vm = load(&files).unwrap();
+        assert_eq!(Ok(Stop::Halted), vm.run(10_000));
+        assert_eq!((6, 8), (vm.peek(16), vm.peek(17)));
+        assert_eq!((23, 15), (vm.peek(18), vm.peek(19)));
+        assert_eq!((-2i16 as u16, 8), (vm.peek(261), vm.peek(262)));
+    }
+
+    #[test]
+    fn report_load_errors() {
+        let errors = Vm::new(&[(
+            "Main",
+            "function Main.main 0\n  pop constant 1\n  goto NOWHERE // loop\n  push temp 8\n  push that x\n  jump\n",
+        )])
+        .err()
+        .unwrap();
+        let causes = errors
+            .iter()
+            .map(|error| (error.line, error.cause.clone()))
+            .collect::<Vec<(usize, Cause)>>();
+        assert_eq!(
+            vec![
+                (2, Cause::PopConstant),
+                (
+                    4,
+                    Cause::IndexOutOfRange {
+                        segment: Segment::Temp,
+                        index: "8".to_string()
+                    }
+                ),
+                (5, Cause::InvalidIndex("x".to_string())),
+                (6, Cause::Syntax),
+                (3, Cause::UnknownLabel("Main.main$NOWHERE".to_string())),
+            ],
+            causes
+        );
+        assert_eq!(
+            "Main.vm:2: error: cannot pop to the constant segment\n  pop constant 1",
+            errors[0].to_string()
+        );
+    }
+
+    #[test]
+    fn stop_on_runtime_errors() {
+        let mut vm = Vm::new(&[(
+            "Sys",
+            "function Sys.init 0\npush constant 1\ncall Main.main 0\n",
+        )])
+        .unwrap();
+        let error = vm.run(10).unwrap_err();
+        assert_eq!(Cause::UnknownFunction("Main.main".to_string()), error.cause);
+        assert_eq!(3, error.line);
+
+        let mut vm = Vm::new(&[("Sys", "function Sys.init 0\npush constant 1\nadd\n")]).unwrap();
+        let error = vm.run(10).unwrap_err();
+        assert_eq!(Cause::StackUnderflow, error.cause);
+        assert_eq!("add", error.source);
+        // The failed command is not executed
+        assert_eq!((2, 262), (vm.pc, vm.peek(SP)));
+
+        // A stack pointer moved past the stack, by the user or through LCL = 0
+        let mut vm = Vm::new(&[("Main", "pop temp 0")]).unwrap();
+        vm.poke(SP, -25536i16 as u16);
+        assert_eq!(Cause::StackOverflow, vm.run(10).unwrap_err().cause);
+        let mut vm = Vm::new(&[("Main", "push constant 30000\npop local 0\npop temp 0")]).unwrap();
+        assert_eq!(Cause::StackOverflow, vm.run(10).unwrap_err().cause);
+        assert_eq!(30000, vm.peek(SP));
+        let mut vm = Vm::new(&[
+            ("Main", "function Main.f 0\ncall Main.f 0\nreturn"),
+            ("Sys", "function Sys.init 0\ncall Main.f 0"),
+        ])
+        .unwrap();
+        vm.run(3).unwrap();
+        vm.poke(SP, 40000);
+        assert_eq!(Cause::StackOverflow, vm.run(10).unwrap_err().cause);
+    }
+
+    #[test]
+    fn compare_as_the_translated_code() {
+        // x = -32768 and y = 1, where y - x wraps around to -32767
+        let minimum = "push constant 32767\nneg\npush constant 1\nsub\n";
+        let mut vm = Vm::new(&[(
+            "Main",
+            &format!("{minimum}push constant 1\ngt\n{minimum}push constant 1\nlt\npush constant 1\n{minimum}lt"),
+        )])
+        .unwrap();
+        assert_eq!(Ok(Stop::EndOfProgram), vm.run(100));
+        assert_eq!(
+            (0xFFFF, 0, 0xFFFF),
+            (vm.peek(256), vm.peek(257), vm.peek(258))
+        );
+    }
+}
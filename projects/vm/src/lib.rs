@@ -0,0 +1,9 @@
+pub mod codegen;
+pub mod command;
+pub mod compact;
+pub mod error;
+pub mod interpreter;
+pub mod optimizer;
+pub mod options;
+pub mod test_script;
+pub mod translation;
@@ -1,541 +1,242 @@
-use collections::catdeque::CatenableDeque;
 use collections::deque::*;
 use collections::Empty;
-use command::*;
 use functional::functor::*;
 use functional::io::*;
-use parser::parser::Parser;
+use script::runner;
+use script::script::parse_script;
 use std::env;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process;
-use translation::*;
-
-mod command;
-mod translation;
-
-fn translate<'a>(
-    lines: &'a [&'a str],
-    file_stem: &'a str,
-    file_index: usize,
-) -> Result<CatenableDeque<String>, &'a str> {
-    const SENTINEL: &str = "\0";
-
-    let command = command();
-
-    let assembly = if file_index == 0 {
-        CatenableDeque::<String>::empty()
-            .push_back("// bootstrap".to_string())
-            .push_back(BOOTSTRAP.to_string())
-    } else {
-        CatenableDeque::<String>::empty()
-    };
-
-    let eq_index = 0;
-    let gt_index = 0;
-    let lt_index = 0;
-
-    let callee_index = 0;
-    let caller_stack = if file_index == 0 {
-        BankersDeque::<String>::empty().push_back("CALLER".to_string())
-    } else {
-        BankersDeque::<String>::empty()
-    };
-
-    let prefix = if file_index == 0 {
-        Some("call Sys.init 0")
+use vm::codegen::{self, CodeGen, TranslatorState};
+use vm::command::Command;
+use vm::compact::Compact;
+use vm::error::report;
+use vm::interpreter::Vm;
+use vm::optimizer::{optimize, PASSES};
+use vm::options::{get_source, parse_options, Options};
+use vm::test_script::VmEmulator;
+use vm::translation::Hack;
+
+// Words of the Hack ROM
+const ROM_SIZE: usize = 32 * 1024;
+
+// Parses the files given as (file stem, content) into (file stem, commands). The errors
+// of all files are reported together.
+fn parse(files: &[(String, String)]) -> Result<Vec<(String, Vec<Command>)>, String> {
+    let mut program = Vec::new();
+    let mut errors = Vec::new();
+    for (stem, content) in files {
+        let lines = content.lines().collect::<Vec<&str>>();
+        match codegen::parse(stem, &lines) {
+            Ok(commands) => program.push((stem.clone(), commands)),
+            Err(file_errors) => errors.extend(file_errors),
+        }
+    }
+    if errors.is_empty() {
+        Ok(program)
     } else {
-        None
-    };
-
-    prefix
-        .into_iter()
-        .chain(lines.iter().copied())
-        .chain(std::iter::once(SENTINEL))
-        .try_fold(
-            (
-                assembly,
-                eq_index,
-                gt_index,
-                lt_index,
-                callee_index,
-                caller_stack,
-                None,
-            ),
-            |(assembly, eq_index, gt_index, lt_index, callee_index, caller_stack, pending),
-             incoming| {
-                match pending {
-                    Some(current) if current != SENTINEL => {
-                        match command.parse(current) {
-                            // Push
-                            Ok(("", Command::Push(segment, index))) => {
-                                index
-                                    .parse::<u32>()
-                                    .map_or(Err("Failed to translate"), |_| {
-                                        let assembly_code = match segment.as_str() {
-                                            "local" => Some(
-                                                SEGMENT
-                                                    .replace("{segment}", "LCL")
-                                                    .replace("{index}", &index),
-                                            ),
-                                            "argument" => Some(
-                                                SEGMENT
-                                                    .replace("{segment}", "ARG")
-                                                    .replace("{index}", &index),
-                                            ),
-                                            "this" => Some(
-                                                SEGMENT
-                                                    .replace("{segment}", "THIS")
-                                                    .replace("{index}", &index),
-                                            ),
-                                            "that" => Some(
-                                                SEGMENT
-                                                    .replace("{segment}", "THAT")
-                                                    .replace("{index}", &index),
-                                            ),
-                                            "temp" => Some(TEMP.replace("{index}", &index)),
-                                            "pointer" => match index.as_str() {
-                                                "0" => Some(
-                                                    POINTER
-                                                        .replace("{segment}", "THIS")
-                                                        .replace("{index}", "0"),
-                                                ),
-                                                "1" => Some(
-                                                    POINTER
-                                                        .replace("{segment}", "THAT")
-                                                        .replace("{index}", "1"),
-                                                ),
-                                                _ => None,
-                                            },
-                                            "static" => Some(
-                                                STATIC
-                                                    .replace("{file}", file_stem)
-                                                    .replace("{index}", &index),
-                                            ),
-                                            "constant" => Some(CONSTANT.replace("{index}", &index)),
-                                            _ => None,
-                                        };
-                                        assembly_code.map_or(Err("Failed to translate"), |code| {
-                                            Ok((
-                                                assembly
-                                                    .push_back(format!("// push {segment} {index}"))
-                                                    .push_back(code)
-                                                    .push_back(POST_PUSH.to_string()),
-                                                eq_index,
-                                                gt_index,
-                                                lt_index,
-                                                callee_index,
-                                                caller_stack,
-                                                Some(incoming),
-                                            ))
-                                        })
-                                    })
-                            }
-                            // Pop
-                            Ok(("", Command::Pop(segment, index))) => {
-                                index
-                                    .parse::<u32>()
-                                    .map_or(Err("Failed to translante"), |_| {
-                                        let assembly_code = match segment.as_str() {
-                                            "local" => Some(
-                                                SEGMENT_ADDRESS
-                                                    .replace("{segment}", "LCL")
-                                                    .replace("{index}", &index),
-                                            ),
-                                            "argument" => Some(
-                                                SEGMENT_ADDRESS
-                                                    .replace("{segment}", "ARG")
-                                                    .replace("{index}", &index),
-                                            ),
-                                            "this" => Some(
-                                                SEGMENT_ADDRESS
-                                                    .replace("{segment}", "THIS")
-                                                    .replace("{index}", &index),
-                                            ),
-                                            "that" => Some(
-                                                SEGMENT_ADDRESS
-                                                    .replace("{segment}", "THAT")
-                                                    .replace("{index}", &index),
-                                            ),
-                                            "temp" => Some(TEMP_ADDRESS.replace("{index}", &index)),
-                                            "pointer" => match index.as_str() {
-                                                "0" => Some(
-                                                    POINTER_ADDRESS.replace("{segment}", "THIS"),
-                                                ),
-                                                "1" => Some(
-                                                    POINTER_ADDRESS.replace("{segment}", "THAT"),
-                                                ),
-                                                _ => None,
-                                            },
-                                            "static" => Some(
-                                                STAIC_ADDRESS
-                                                    .replace("{file}", file_stem)
-                                                    .replace("{index}", &index),
-                                            ),
-                                            _ => None,
-                                        };
-                                        assembly_code.map_or(Err("Failed to translate"), |code| {
-                                            Ok((
-                                                assembly
-                                                    .push_back(format!("// pop {segment} {index}"))
-                                                    .push_back(PRE_POP.to_string())
-                                                    .push_back(code)
-                                                    .push_back(POST_POP.to_string()),
-                                                eq_index,
-                                                gt_index,
-                                                lt_index,
-                                                callee_index,
-                                                caller_stack,
-                                                Some(incoming),
-                                            ))
-                                        })
-                                    })
-                            }
-                            // Operator
-                            Ok(("", Command::Arithmetic(operator))) => {
-                                let assembly_code = match operator.as_str() {
-                                    "add" => Some(BINARY_COMP.replace("{comp}", "D=D+M")),
-                                    "sub" => Some(BINARY_COMP.replace("{comp}", "D=M-D")),
-                                    "and" => Some(BINARY_COMP.replace("{comp}", "D=D&M")),
-                                    "or" => Some(BINARY_COMP.replace("{comp}", "D=D|M")),
-                                    "neg" => Some(UNARY_COMP.replace("{comp}", "D=-D")),
-                                    "not" => Some(UNARY_COMP.replace("{comp}", "D=!D")),
-                                    "eq" => Some(
-                                        COMPARISON
-                                            .replace("{label}", &format!("EQUAL.{eq_index}"))
-                                            .replace("{jump}", "JEQ"),
-                                    ),
-                                    "gt" => Some(
-                                        COMPARISON
-                                            .replace("{label}", &format!("GREATERTHAN.{gt_index}"))
-                                            .replace("{jump}", "JLT"),
-                                    ),
-                                    "lt" => Some(
-                                        COMPARISON
-                                            .replace("{label}", &format!("LESSTHAN.{lt_index}"))
-                                            .replace("{jump}", "JGT"),
-                                    ),
-                                    _ => None,
-                                };
-                                assembly_code.map_or(Err("Failed to translate"), |code| {
-                                    Ok((
-                                        assembly
-                                            .push_back(format!("// {operator}"))
-                                            .push_back(code),
-                                        eq_index + if operator.eq("eq") { 1 } else { 0 },
-                                        gt_index + if operator.eq("gt") { 1 } else { 0 },
-                                        lt_index + if operator.eq("lt") { 1 } else { 0 },
-                                        callee_index,
-                                        caller_stack,
-                                        Some(incoming),
-                                    ))
-                                })
-                            }
-                            // Label
-                            Ok(("", Command::Label(label))) => {
-                                let assembly_code = if caller_stack.is_empty() {
-                                    Some(format!("({label})"))
-                                } else {
-                                    caller_stack
-                                        .back()
-                                        .map(|function| format!("({function}${label})"))
-                                };
-                                assembly_code.map_or(Err("Failed to translate"), |code| {
-                                    Ok((
-                                        assembly.push_back(format!("// {label}")).push_back(code),
-                                        eq_index,
-                                        gt_index,
-                                        lt_index,
-                                        callee_index,
-                                        caller_stack,
-                                        Some(incoming),
-                                    ))
-                                })
-                            }
-                            // Goto
-                            Ok(("", Command::Goto(label))) => {
-                                let assembly_code = if caller_stack.is_empty() {
-                                    Some(format!("@{label}"))
-                                } else {
-                                    caller_stack
-                                        .back()
-                                        .map(|function| format!("@{function}${label}"))
-                                };
-                                assembly_code.map_or(Err("Failed to translate"), |code| {
-                                    Ok((
-                                        assembly
-                                            .push_back(format!("// goto {label}"))
-                                            .push_back(code)
-                                            .push_back("0;JMP".to_string()),
-                                        eq_index,
-                                        gt_index,
-                                        lt_index,
-                                        callee_index,
-                                        caller_stack,
-                                        Some(incoming),
-                                    ))
-                                })
-                            }
-                            // If-Goto
-                            Ok(("", Command::IfGoto(label))) => {
-                                let assembly_code = if caller_stack.is_empty() {
-                                    Some(
-                                        IF_GOTO
-                                            .replace("{dontgoto}", "DONTGOTO")
-                                            .replace("{label}", &label),
-                                    )
-                                } else {
-                                    caller_stack.back().map(|function| {
-                                        IF_GOTO
-                                            .replace("{dontgoto}", &format!("{function}.DONTGOTO"))
-                                            .replace("{label}", &format!("{function}${label}"))
-                                    })
-                                };
-                                assembly_code.map_or(Err("Failed to translate"), |code| {
-                                    Ok((
-                                        assembly
-                                            .push_back(format!("// if-goto {label}"))
-                                            .push_back(code),
-                                        eq_index,
-                                        gt_index,
-                                        lt_index,
-                                        callee_index,
-                                        caller_stack,
-                                        Some(incoming),
-                                    ))
-                                })
-                            }
-                            // Function
-                            Ok(("", Command::Function(caller, nvers))) => nvers
-                                .parse::<usize>()
-                                .map_or(Err("Failed to translate"), |vers| {
-                                    Ok((
-                                        assembly
-                                            .push_back(format!("// function {caller} {nvers}"))
-                                            .push_back(format!("({caller})"))
-                                            .push_back(
-                                                format!("{FUNCTION}\n")
-                                                    .repeat(vers)
-                                                    .trim_end_matches("\n")
-                                                    .to_owned(),
-                                            ),
-                                        eq_index,
-                                        gt_index,
-                                        lt_index,
-                                        callee_index,
-                                        caller_stack.push_back(caller.clone()),
-                                        Some(incoming),
-                                    ))
-                                }),
-                            // Call
-                            Ok(("", Command::Call(callee, nargs))) => {
-                                match (nargs.parse::<usize>(), caller_stack.back()) {
-                                    (Ok(_), Some(caller)) => Ok((
-                                        assembly
-                                            .push_back(format!("// call {callee} {nargs}"))
-                                            .push_back(
-                                                CALL.replace("{caller}", &caller)
-                                                    .replace(
-                                                        "{callee_index}",
-                                                        &callee_index.to_string(),
-                                                    )
-                                                    .replace("{nargs}", &nargs)
-                                                    .replace("{callee}", &callee),
-                                            ),
-                                        eq_index,
-                                        gt_index,
-                                        lt_index,
-                                        callee_index + 1,
-                                        caller_stack,
-                                        Some(incoming),
-                                    )),
-                                    _ => Err("Failed to translate"),
-                                }
-                            }
-                            // Return
-                            Ok(("", Command::Return)) => {
-                                // if there is a label for a jump after return, keep the caller stack as is, since the process is still in a function
-                                if matches!(command.parse(incoming), Ok(("", Command::Label(_)))) {
-                                    Ok(caller_stack)
-                                // otherwise, pop the caller stack and continue
-                                } else {
-                                    caller_stack
-                                        .pop_back()
-                                        .map_or(Err(""), |(_, next_caller_stack)| {
-                                            Ok(next_caller_stack)
-                                        })
-                                }
-                                .and_then(|next_caller_stack| {
-                                    Ok((
-                                        assembly
-                                            .push_back("// return".to_string())
-                                            .push_back(RETURN.to_string()),
-                                        eq_index,
-                                        gt_index,
-                                        lt_index,
-                                        callee_index,
-                                        next_caller_stack,
-                                        Some(incoming),
-                                    ))
-                                })
-                            }
-                            Err(_) => Ok((
-                                assembly,
-                                eq_index,
-                                gt_index,
-                                lt_index,
-                                callee_index,
-                                caller_stack,
-                                Some(incoming),
-                            )),
-                            _ => Err("Failed to translate"),
-                        }
-                    }
-                    _ => Ok((
-                        assembly,
-                        eq_index,
-                        gt_index,
-                        lt_index,
-                        callee_index,
-                        caller_stack,
-                        Some(incoming),
-                    )),
-                }
-            },
-        )
-        .map(|(assembly, _, _, _, _, _, _)| assembly.push_back("\n".to_string()))
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum SourceType {
-    File,
-    Directory,
-}
-
-struct Source<D: Deque<String>> {
-    source_type: SourceType,
-    dir: String,
-    file_paths: D,
+        Err(report("translate", &errors))
+    }
 }
 
-fn get_file_paths<D: Deque<String>>(dir: &str, ext: &str, paths: D) -> D {
-    fs::read_dir(dir).map_or(D::empty(), |entries| {
-        entries.flatten().fold(paths, |paths, entry| {
-            let path = entry.path();
-            if path.is_file() && path.extension().and_then(|e| e.to_str()) == Some(ext) {
-                return paths.push_back(path.to_string_lossy().into_owned());
-            }
-            paths
-        })
-    })
+// Translates a program given as (file stem, commands) into Hack assembly with `codegen`,
+// after the bootstrap code calling `entry` if any
+fn translate<G: CodeGen>(
+    codegen: &mut G,
+    program: &[(String, Vec<Command>)],
+    entry: Option<&str>,
+) -> Result<(), String> {
+    let mut state = TranslatorState::new();
+    if let Some(entry) = entry {
+        codegen.bootstrap(&mut state, entry);
+    }
+    for (stem, commands) in program {
+        codegen::generate_file(codegen, &mut state, stem, commands);
+    }
+    codegen.finish(&mut state);
+    match entry {
+        Some(entry) if !state.functions.iter().any(|function| function == entry) => Err(format!(
+            "error: entry function `{}` is not defined; use --entry or --no-bootstrap",
+            entry
+        )),
+        _ => Ok(()),
+    }
 }
 
-fn get_file_path<D: Deque<String>>(path: &Path, ext: &str, paths: D) -> D {
-    if path.extension().and_then(|s| s.to_str()) == Some(ext) {
-        return paths.push_back(path.to_string_lossy().into_owned());
+// Returns the assembly of the program, with calls, returns and comparisons inline or in
+// shared routines, and its number of instructions
+fn assemble(
+    program: &[(String, Vec<Command>)],
+    entry: Option<&str>,
+    compact: bool,
+) -> Result<(String, usize), String> {
+    if compact {
+        let mut compact = Compact::new();
+        translate(&mut compact, program, entry)?;
+        Ok((compact.text(), compact.instructions()))
+    } else {
+        let mut hack = Hack::new();
+        translate(&mut hack, program, entry)?;
+        Ok((hack.text(), hack.instructions()))
     }
-    paths
 }
 
-fn get_source<D: Deque<String>>(input: Option<&str>, ext: &str, paths: D) -> Source<D> {
-    match input {
-        None => Source {
-            source_type: SourceType::Directory,
-            dir: "./".to_string(),
-            file_paths: get_file_paths(".", ext, paths),
-        },
-        Some(path_str) => {
-            let path = PathBuf::from(path_str);
-            if path.is_dir() {
-                Source {
-                    source_type: SourceType::Directory,
-                    dir: path_str.to_string(),
-                    file_paths: get_file_paths(path_str, ext, paths),
-                }
+// Compares the numbers of instructions of the inline and compact translations
+fn size_report(inline: usize, compact: usize) -> String {
+    let line = |mode: &str, instructions: usize| {
+        format!(
+            "{:<8}{:>7} instructions{}",
+            mode,
+            instructions,
+            if instructions > ROM_SIZE {
+                format!(" (exceeds the ROM by {})", instructions - ROM_SIZE)
             } else {
-                Source {
-                    source_type: SourceType::File,
-                    dir: path.parent().unwrap().to_string_lossy().into_owned(),
-                    file_paths: get_file_path(&path, ext, paths),
-                }
+                String::new()
             }
-        }
-    }
+        )
+    };
+    format!(
+        "{}\n{}\ncompact mode saves {} instructions ({:.1}%)",
+        line("inline", inline),
+        line("compact", compact),
+        inline as i64 - compact as i64,
+        100.0 * (inline as f64 - compact as f64) / inline.max(1) as f64
+    )
 }
 
-fn run(input: Option<&str>) {
-    let paths = BankersDeque::<String>::empty();
-    let source = get_source(input, "vm", paths);
-    let offset = if source.source_type == SourceType::Directory { 0 } else { 1 };
-
-    if source.file_paths.is_empty() {
-        eprintln!("Usage: vm <vm file name|dir name where vm files reside>");
-        process::exit(1);
-    }
+fn run(options: &Options) -> Result<(), String> {
+    let source = get_source(&options.inputs, "vm", BankersDeque::<String>::empty())?;
+    let entry = options.entry(source.directory);
 
-    let output = if source.dir == "./" {
-        let stem = if source.file_paths.len() == 1 {
-            PathBuf::from(source.file_paths.front().unwrap().as_str())
+    // Fold over the file paths, accumulating their stems and contents
+    source
+        .file_paths
+        .iter()
+        .fold(IO::Return(Vec::new()), |acc, path| {
+            let file_stem = PathBuf::from(path.as_str())
                 .file_stem()
                 .unwrap()
                 .to_string_lossy()
-                .into_owned()
-        } else {
-            "Main".to_string()
-        };
-        format!("{}.asm", stem)
-    } else {
-        let stem = PathBuf::from(source.dir.clone())
-            .file_name()
-            .unwrap()
-            .to_string_lossy()
-            .into_owned();
-        format!("{}/{}.asm", source.dir, stem)
-    };
+                .into_owned();
+            IO::<String>::read_file(path.to_string()).flat_map(move |content| {
+                acc.map(move |mut files: Vec<(String, String)>| {
+                    files.push((file_stem, content));
+                    files
+                })
+            })
+        })
+        // After reading all files, write a single combined result
+        .flat_map(|files| {
+            let program = parse(&files).map(|mut program| {
+                optimize(&mut program, entry, options.passes);
+                program
+            });
+            let assembly = program.and_then(|program| {
+                let (assembly, size) = assemble(&program, entry, options.compact)?;
+                if options.report {
+                    // Translates again in the other mode to compare
+                    let (_, other) = assemble(&program, entry, !options.compact)?;
+                    let (inline, compact) = if options.compact {
+                        (other, size)
+                    } else {
+                        (size, other)
+                    };
+                    println!("{}", size_report(inline, compact));
+                }
+                Ok(assembly)
+            });
+            match assembly {
+                Ok(assembly) => IO::<String>::write_file(source.output, assembly),
+                Err(error) => IO::Error(error),
+            }
+        })
+        .unsafe_run()
+}
 
-    // Fold over the file paths, accumulating the results into an IO<CatenableDeque>
-    source
+// Executes the VM files with the VM interpreter and prints the requested RAM
+fn interpret(options: Options) -> Result<(), String> {
+    let source = get_source(&options.inputs, "vm", BankersDeque::<String>::empty())?;
+    let files = source
         .file_paths
         .iter()
-        .enumerate()
-        .fold(
-            IO::Return(CatenableDeque::<String>::empty()),
-            |acc, (file_index, path)| {
-                let file_stem = PathBuf::from(path.as_str())
-                    .file_stem()
-                    .unwrap()
-                    .to_string_lossy()
-                    .into_owned();
-                IO::<String>::read_file(path.to_string()).flat_map(move |content| {
-                    let commands = content.lines().collect::<Vec<&str>>();
-                    translate(&commands, &file_stem, file_index + offset).map_or_else(
-                        |error| IO::Error(error.to_string()),
-                        |assembly| acc.map(move |deque| deque.append(&assembly)),
-                    )
-                })
-            },
-        )
-        // After processing all files, write a single combined result
-        .flat_map(|assembly| {
-            let lines = assembly
-                .iter()
-                .map(|s| s.as_ref().clone())
-                .collect::<Vec<String>>()
-                .join("\n");
-            IO::<String>::write_file(output, lines)
+        .map(|path| {
+            let stem = PathBuf::from(path.as_str())
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            fs::read_to_string(path.as_str())
+                .map(|content| (stem, content))
+                .map_err(|error| format!("{}: {}", path, error))
         })
-        .unsafe_run()
-        .unwrap_or_else(|e| panic!("Failed to process files: {}", e));
+        .collect::<Result<Vec<(String, String)>, String>>()?;
+    let files = files
+        .iter()
+        .map(|(stem, content)| (stem.as_str(), content.as_str()))
+        .collect::<Vec<(&str, &str)>>();
+    let mut vm = Vm::new(&files).map_err(|errors| report("load", &errors))?;
+    options
+        .sets
+        .iter()
+        .for_each(|&(address, value)| vm.poke(address, value));
+    let stop = vm.run(options.steps).map_err(|error| error.to_string())?;
+    println!("{:?} after {} steps", stop, vm.steps);
+    options.dumps.iter().for_each(|&(start, end)| {
+        (start..end).for_each(|address| println!("RAM[{}] = {}", address, vm.peek(address) as i16))
+    });
+    Ok(())
+}
+
+// Runs a VM emulator .tst script, writes its output file and compares it with the compare file
+fn run_script(input: &str) -> Result<(), String> {
+    let dir = Path::new(input).parent().unwrap_or(Path::new("."));
+    let content = fs::read_to_string(input).map_err(|error| format!("{}: {}", input, error))?;
+    let script = parse_script(&content).map_err(|error| format!("{}: {}", input, error))?;
+    let outcome = runner::run(&script, dir, &mut VmEmulator::new())
+        .map_err(|error| format!("{}: {}", input, error))?;
+    if let Some(output) = &outcome.output_file {
+        fs::write(output, outcome.output_text())
+            .map_err(|error| format!("{}: {}", output.display(), error))?;
+    }
+    match outcome.mismatch {
+        None => {
+            println!("{}: End of script - Comparison ended successfully", input);
+            Ok(())
+        }
+        Some(mismatch) => Err(format!(
+            "{}: Comparison failure at line {}\nexpected: {}\nactual:   {}",
+            input, mismatch.line, mismatch.expected, mismatch.actual
+        )),
+    }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    const STACK_SIZE: usize = 16 * 1024 * 1024;
-    std::thread::Builder::new()
-        .stack_size(STACK_SIZE)
-        .spawn(move || run(if 1 < args.len() { Some(&args[1]) } else { None }))
-        .unwrap()
-        .join()
-        .unwrap();
+
+    match parse_options(&args[1..]) {
+        Some(options) if options.interpret => interpret(options).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(1);
+        }),
+        Some(Options { inputs, .. }) if inputs.len() == 1 && inputs[0].ends_with(".tst") => {
+            run_script(&inputs[0]).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(1);
+            })
+        }
+        Some(options) => run(&options).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(1);
+        }),
+        None => {
+            eprintln!(
+                "Usage: {0} [--bootstrap|--no-bootstrap] [--entry CLASS.FUNCTION] [--compact] [--report] [-O|--passes PASS,...] [vm file name|dir name where vm files reside]...\n       {0} --run [--steps N] [--set ADDRESS=VALUE]... [--dump START[..END]]... [vm file name|dir name]...\n       {0} <tst file name>\nDirectories are bootstrapped to call Sys.init by default, single files are not.\n--compact jumps to routines shared by all calls, returns and comparisons; --report\ncompares the numbers of instructions with and without it.\n-O runs all the passes of the optimizer before translating, --passes those listed among\n{1}.",
+                &args[0],
+                PASSES.join(", ")
+            );
+            process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,542 @@
+use crate::command::{ArithOp, Command, Segment};
+use collections::hashmap::HashMap;
+
+// The passes to run, each of which can be switched on by name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Passes {
+    pub fold: bool,
+    pub push_pop: bool,
+    pub branches: bool,
+    pub tail_calls: bool,
+    pub dead_functions: bool,
+}
+
+pub const PASSES: [&str; 5] = [
+    "fold",
+    "push-pop",
+    "branches",
+    "tail-calls",
+    "dead-functions",
+];
+
+impl Passes {
+    pub fn all() -> Passes {
+        Passes {
+            fold: true,
+            push_pop: true,
+            branches: true,
+            tail_calls: true,
+            dead_functions: true,
+        }
+    }
+
+    // Parses a comma-separated list of the names in PASSES
+    pub fn parse(names: &str) -> Option<Passes> {
+        names
+            .split(',')
+            .try_fold(Passes::default(), |passes, name| {
+                Some(match name {
+                    "fold" => Passes {
+                        fold: true,
+                        ..passes
+                    },
+                    "push-pop" => Passes {
+                        push_pop: true,
+                        ..passes
+                    },
+                    "branches" => Passes {
+                        branches: true,
+                        ..passes
+                    },
+                    "tail-calls" => Passes {
+                        tail_calls: true,
+                        ..passes
+                    },
+                    "dead-functions" => Passes {
+                        dead_functions: true,
+                        ..passes
+                    },
+                    _ => return None,
+                })
+            })
+    }
+
+    pub fn any(&self) -> bool {
+        *self != Passes::default()
+    }
+}
+
+// Replaces each run of `width` consecutive commands for which `replacement` returns
+// commands. Jumps go to labels only, so a run with no label in it is entered at its start.
+fn rewrite(
+    commands: &mut Vec<Command>,
+    width: usize,
+    replacement: impl Fn(&[Command]) -> Option<Vec<Command>>,
+) -> bool {
+    let mut rewritten = Vec::with_capacity(commands.len());
+    let mut changed = false;
+    let mut index = 0;
+    while index < commands.len() {
+        match commands.get(index..index + width).and_then(&replacement) {
+            Some(replaced) => {
+                rewritten.extend(replaced);
+                index += width;
+                changed = true;
+            }
+            None => {
+                rewritten.push(commands[index].clone());
+                index += 1;
+            }
+        }
+    }
+    *commands = rewritten;
+    changed
+}
+
+// Returns the value of a binary operation on constants, as the translated code computes
+// it. Comparisons whose y - x overflows are left to it.
+fn binary(op: ArithOp, x: u16, y: u16) -> Option<u16> {
+    let boolean = |condition: bool| if condition { 0xFFFF } else { 0 };
+    let difference = (y as i16).checked_sub(x as i16);
+    match op {
+        ArithOp::Add => Some(x.wrapping_add(y)),
+        ArithOp::Sub => Some(x.wrapping_sub(y)),
+        ArithOp::And => Some(x & y),
+        ArithOp::Or => Some(x | y),
+        ArithOp::Eq => Some(boolean(x == y)),
+        ArithOp::Gt => difference.map(|difference| boolean(difference < 0)),
+        ArithOp::Lt => difference.map(|difference| boolean(difference > 0)),
+        _ => None,
+    }
+}
+
+// Operations on constants push their value, and a constant condition jumps always or never.
+// "not" and "neg" twice cancel out.
+fn fold(commands: &mut Vec<Command>) -> bool {
+    let unary = rewrite(commands, 2, |window| match window {
+        [Command::Push(Segment::Constant, value), Command::Arithmetic(op)] => {
+            let value = match op {
+                ArithOp::Neg => value.wrapping_neg(),
+                ArithOp::Not => !value,
+                _ => return None,
+            };
+            Some(vec![Command::Push(Segment::Constant, value)])
+        }
+        [Command::Arithmetic(ArithOp::Not), Command::Arithmetic(ArithOp::Not)]
+        | [Command::Arithmetic(ArithOp::Neg), Command::Arithmetic(ArithOp::Neg)] => {
+            Some(Vec::new())
+        }
+        [Command::Push(Segment::Constant, 0), Command::IfGoto(_)] => Some(Vec::new()),
+        [Command::Push(Segment::Constant, _), Command::IfGoto(label)] => {
+            Some(vec![Command::Goto(label.clone())])
+        }
+        _ => None,
+    });
+    let constant = |command: &Command| match command {
+        Command::Push(Segment::Constant, value) => Some(*value),
+        _ => None,
+    };
+    let binary = rewrite(commands, 3, |window| {
+        match (constant(&window[0]), constant(&window[1]), &window[2]) {
+            (Some(x), Some(y), Command::Arithmetic(op)) => {
+                binary(*op, x, y).map(|value| vec![Command::Push(Segment::Constant, value)])
+            }
+            _ => None,
+        }
+    });
+    unary || binary
+}
+
+// Popping a value just pushed from the same place leaves memory as it was
+fn push_pop(commands: &mut Vec<Command>) -> bool {
+    rewrite(commands, 2, |window| match window {
+        [Command::Push(pushed, i), Command::Pop(popped, j)] if pushed == popped && i == j => {
+            Some(Vec::new())
+        }
+        _ => None,
+    })
+}
+
+// A comparison, or "not", followed by "if-goto" jumps on the operands directly
+fn branches(commands: &mut Vec<Command>) -> bool {
+    let compare = |op: &ArithOp| matches!(op, ArithOp::Eq | ArithOp::Gt | ArithOp::Lt);
+    let negated = rewrite(commands, 3, |window| match window {
+        [Command::Arithmetic(op), Command::Arithmetic(ArithOp::Not), Command::IfGoto(label)]
+            if compare(op) =>
+        {
+            Some(vec![Command::IfCompare {
+                op: *op,
+                negated: true,
+                label: label.clone(),
+            }])
+        }
+        _ => None,
+    });
+    let direct = rewrite(commands, 2, |window| match window {
+        [Command::Arithmetic(op), Command::IfGoto(label)] if compare(op) => {
+            Some(vec![Command::IfCompare {
+                op: *op,
+                negated: false,
+                label: label.clone(),
+            }])
+        }
+        [Command::Arithmetic(ArithOp::Not), Command::IfGoto(label)] => {
+            Some(vec![Command::IfNot(label.clone())])
+        }
+        _ => None,
+    });
+    negated || direct
+}
+
+// The code of a program split at function declarations: the top-level code at the start
+// of each file, possibly empty, then each function with its declaration
+struct Block {
+    file: usize,
+    start: usize,
+    end: usize,
+    function: Option<String>,
+}
+
+fn blocks(program: &[(String, Vec<Command>)]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    for (file, (_, commands)) in program.iter().enumerate() {
+        let mut block = Block {
+            file,
+            start: 0,
+            end: 0,
+            function: None,
+        };
+        for (index, command) in commands.iter().enumerate() {
+            if let Command::Function(name, _) = command {
+                blocks.push(Block {
+                    end: index,
+                    ..block
+                });
+                block = Block {
+                    file,
+                    start: index,
+                    end: 0,
+                    function: Some(name.clone()),
+                };
+            }
+        }
+        blocks.push(Block {
+            end: commands.len(),
+            ..block
+        });
+    }
+    blocks
+}
+
+fn commands<'a>(program: &'a [(String, Vec<Command>)], block: &Block) -> &'a [Command] {
+    &program[block.file].1[block.start..block.end]
+}
+
+// Returns true if the code after a block is executed when the block runs to its end.
+// Empty blocks are skipped by whatever runs past them.
+fn falls_through(commands: &[Command]) -> bool {
+    !matches!(
+        commands.last(),
+        Some(Command::Return | Command::Goto(_) | Command::TailCall(_, _))
+    )
+}
+
+// Returns the index of each block that execution may run into from the block before it,
+// or from the start of the program if it has no bootstrap code
+fn fallen_into(program: &[(String, Vec<Command>)], blocks: &[Block], bootstrap: bool) -> Vec<bool> {
+    let mut falls = !bootstrap;
+    blocks
+        .iter()
+        .map(|block| {
+            let commands = commands(program, block);
+            let fallen = falls && !commands.is_empty();
+            if !commands.is_empty() {
+                falls = falls_through(commands);
+            }
+            fallen
+        })
+        .collect()
+}
+
+// Returns the number of arguments each function is always called with, by the calls of
+// the program and the bootstrap code calling `entry`, or None if it varies
+fn arguments(
+    program: &[(String, Vec<Command>)],
+    entry: Option<&str>,
+) -> HashMap<String, Option<u16>> {
+    let calls = program
+        .iter()
+        .flat_map(|(_, commands)| commands)
+        .filter_map(|command| match command {
+            Command::Call(function, arguments) | Command::TailCall(function, arguments) => {
+                Some((function.as_str(), *arguments))
+            }
+            _ => None,
+        });
+    entry.map(|entry| (entry, 0)).into_iter().chain(calls).fold(
+        HashMap::new(),
+        |counts, (function, arguments)| {
+            let count = match counts.get(&function.to_string()) {
+                Some(&count) if count != Some(arguments) => None,
+                _ => Some(arguments),
+            };
+            counts.insert(function.to_string(), count)
+        },
+    )
+}
+
+// A call followed by a return, in a function always called with at least as many arguments,
+// moves its arguments over those of the function and jumps in its frame. Functions the
+// code before them may run into are left alone, as their frame is unknown.
+fn tail_calls(program: &mut [(String, Vec<Command>)], entry: Option<&str>) {
+    let blocks = blocks(program);
+    let fallen_into = fallen_into(program, &blocks, entry.is_some());
+    let arguments = arguments(program, entry);
+    for (block, fallen_into) in blocks.iter().zip(fallen_into) {
+        let Some(function) = &block.function else {
+            continue;
+        };
+        let Some(Some(available)) = arguments.get(function).copied().filter(|_| !fallen_into)
+        else {
+            continue;
+        };
+        let commands = &mut program[block.file].1;
+        for index in block.start..block.end.saturating_sub(1) {
+            if let (Command::Call(callee, count), Command::Return) =
+                (&commands[index], &commands[index + 1])
+            {
+                if *count <= available {
+                    commands[index] = Command::TailCall(callee.clone(), *count);
+                }
+            }
+        }
+    }
+    // The returns are dropped once all blocks are rewritten, as they delimit them
+    for (_, commands) in program.iter_mut() {
+        rewrite(commands, 2, |window| match window {
+            [tail_call @ Command::TailCall(_, _), Command::Return] => Some(vec![tail_call.clone()]),
+            _ => None,
+        });
+    }
+}
+
+// Removes the functions that are not called from live code, nor run into from it. The
+// top-level code is live, as is the start of the program without bootstrap code, and
+// `entry` with it.
+fn dead_functions(program: &mut [(String, Vec<Command>)], entry: Option<&str>) {
+    let blocks = blocks(program);
+    let indices = blocks
+        .iter()
+        .enumerate()
+        .fold(HashMap::new(), |indices, (index, block)| {
+            match &block.function {
+                Some(function) => indices.insert(function.clone(), index),
+                None => indices,
+            }
+        });
+    let non_empty = |index: usize| !commands(program, &blocks[index]).is_empty();
+
+    let mut work = (0..blocks.len())
+        .filter(|&index| blocks[index].function.is_none() && non_empty(index))
+        .collect::<Vec<usize>>();
+    work.extend(entry.and_then(|entry| indices.get(&entry.to_string()).copied()));
+    if entry.is_none() {
+        work.extend((0..blocks.len()).find(|&index| non_empty(index)));
+    }
+    let mut live = vec![false; blocks.len()];
+    while let Some(index) = work.pop() {
+        if live[index] {
+            continue;
+        }
+        live[index] = true;
+        let code = commands(program, &blocks[index]);
+        for command in code {
+            if let Command::Call(callee, _) | Command::TailCall(callee, _) = command {
+                work.extend(indices.get(callee).copied());
+            }
+        }
+        if falls_through(code) {
+            work.extend((index + 1..blocks.len()).find(|&next| non_empty(next)));
+        }
+    }
+
+    let mut kept = vec![Vec::new(); program.len()];
+    for (block, live) in blocks.iter().zip(live) {
+        if live || block.function.is_none() {
+            kept[block.file].extend_from_slice(commands(program, block));
+        }
+    }
+    for ((_, commands), kept) in program.iter_mut().zip(kept) {
+        *commands = kept;
+    }
+}
+
+// Runs the passes on a program given as (file stem, commands), which is bootstrapped to
+// call `entry` if any. The local passes run until none of them changes the program.
+pub fn optimize(program: &mut [(String, Vec<Command>)], entry: Option<&str>, passes: Passes) {
+    let local = [
+        (passes.fold, fold as fn(&mut Vec<Command>) -> bool),
+        (passes.push_pop, push_pop),
+        (passes.branches, branches),
+    ];
+    for (_, commands) in program.iter_mut() {
+        loop {
+            let mut changed = false;
+            for (_, pass) in local.iter().filter(|(enabled, _)| *enabled) {
+                changed |= pass(commands);
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+    if passes.tail_calls {
+        tail_calls(program, entry);
+    }
+    if passes.dead_functions {
+        dead_functions(program, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::parse;
+
+    // Optimizes the files given as (file stem, content) with the passes listed in `passes`
+    fn optimized(files: &[(&str, &str)], entry: Option<&str>, passes: &str) -> Vec<String> {
+        let mut program = files
+            .iter()
+            .map(|(stem, content)| {
+                let lines = content.lines().collect::<Vec<&str>>();
+                (stem.to_string(), parse(stem, &lines).unwrap())
+            })
+            .collect::<Vec<(String, Vec<Command>)>>();
+        optimize(&mut program, entry, Passes::parse(passes).unwrap());
+        program
+            .iter()
+            .flat_map(|(_, commands)| commands.iter().map(Command::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn parse_passes() {
+        assert_eq!(
+            Some(Passes {
+                fold: true,
+                tail_calls: true,
+                ..Passes::default()
+            }),
+            Passes::parse("tail-calls,fold")
+        );
+        assert_eq!(Some(Passes::all()), Passes::parse(&PASSES.join(",")));
+        assert_eq!(None, Passes::parse("fold,inline"));
+    }
+
+    #[test]
+    fn fold_constants() {
+        let main = "push constant 0\nnot\npush constant 1\nneg\nadd\npush local 0\nnot\nnot\n\
+                    push constant 2\npush constant 3\nlt\nif-goto END\n\
+                    push constant 32767\npush constant 2\nneg\ngt\nlabel END";
+        assert_eq!(
+            vec![
+                "push constant 65534",
+                "push local 0",
+                "goto END",
+                // 65534 - 32767 overflows, which the translated code does not check
+                "push constant 32767",
+                "push constant 65534",
+                "gt",
+                "label END",
+            ],
+            optimized(&[("Main", main)], None, "fold")
+        );
+    }
+
+    #[test]
+    fn remove_push_pop_pairs() {
+        let main =
+            "push local 1\npop local 1\npush local 1\npop local 2\npush static 0\npop static 0";
+        assert_eq!(
+            vec!["push local 1", "pop local 2"],
+            optimized(&[("Main", main)], None, "push-pop")
+        );
+    }
+
+    #[test]
+    fn fuse_branches() {
+        let main = "lt\nnot\nif-goto A\neq\nif-goto B\nnot\nif-goto C\ngt\nlabel C\nif-goto C\n\
+                    label A\nlabel B";
+        assert_eq!(
+            vec![
+                "lt; not; if-goto A",
+                "eq; if-goto B",
+                "not; if-goto C",
+                "gt",
+                "label C",
+                "if-goto C",
+                "label A",
+                "label B",
+            ],
+            optimized(&[("Main", main)], None, "branches")
+        );
+    }
+
+    #[test]
+    fn call_in_tail_position_when_arguments_fit() {
+        let sys = "function Sys.init 0\npush constant 5\ncall Main.f 1\nlabel LOOP\ngoto LOOP";
+        let main = "function Main.f 0\npush argument 0\npush constant 1\ncall Main.g 2\nreturn\n\
+                    function Main.g 0\npush argument 1\ncall Main.f 1\nreturn";
+        let files = [("Sys", sys), ("Main", main)];
+        let program = optimized(&files, Some("Sys.init"), "tail-calls");
+        // Main.f is called with 1 argument, fewer than Main.g needs
+        assert_eq!(
+            vec!["call Main.g 2", "return", "function Main.g 0"],
+            program[8..11]
+        );
+        assert_eq!(
+            vec!["push argument 1", "call Main.f 1; return"],
+            program[11..]
+        );
+        // Without bootstrap code, Sys.init is run into and its frame is unknown
+        let sys = "function Sys.init 0\npush constant 1\ncall Sys.init 1\nreturn";
+        assert_eq!(
+            vec![
+                "function Sys.init 0",
+                "push constant 1",
+                "call Sys.init 1",
+                "return"
+            ],
+            optimized(&[("Sys", sys)], None, "tail-calls")
+        );
+    }
+
+    #[test]
+    fn remove_dead_functions() {
+        let sys = "function Sys.init 0\ncall Main.used 0\nlabel LOOP\ngoto LOOP";
+        // Main.used runs into Main.after, and Main.unused only calls itself
+        let main = "function Main.used 0\npush constant 1\npop temp 0\n\
+                    function Main.after 0\npush constant 0\nreturn\n\
+                    function Main.unused 0\ncall Main.unused 0\nreturn";
+        let functions = |optimized: Vec<String>| {
+            optimized
+                .into_iter()
+                .filter(|command| command.starts_with("function"))
+                .collect::<Vec<String>>()
+        };
+        let files = [("Sys", sys), ("Main", main)];
+        assert_eq!(
+            vec![
+                "function Sys.init 0",
+                "function Main.used 0",
+                "function Main.after 0"
+            ],
+            functions(optimized(&files, Some("Sys.init"), "dead-functions"))
+        );
+        // Without bootstrap code, the program starts with the first function
+        assert_eq!(
+            vec!["function Main.used 0", "function Main.after 0"],
+            functions(optimized(&files[1..], None, "dead-functions"))
+        );
+    }
+}
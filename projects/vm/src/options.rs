@@ -0,0 +1,289 @@
+use crate::interpreter::RAM_SIZE;
+use crate::optimizer::Passes;
+use collections::deque::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_STEPS: u64 = 1_000_000;
+// The function the bootstrap code calls by default
+pub const ENTRY: &str = "Sys.init";
+
+// The .vm files of the inputs, those of each directory sorted by name, and where to
+// write the program translated from them
+pub struct Source<D: Deque<String>> {
+    pub file_paths: D,
+    // Whether a directory was given, whose program is bootstrapped by default
+    pub directory: bool,
+    pub output: String,
+}
+
+fn get_file_paths<D: Deque<String>>(dir: &str, ext: &str, paths: D) -> D {
+    let mut files = fs::read_dir(dir).map_or(Vec::new(), |entries| {
+        entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().and_then(|e| e.to_str()) == Some(ext))
+            .collect::<Vec<PathBuf>>()
+    });
+    files.sort();
+    files.iter().fold(paths, |paths, path| {
+        paths.push_back(path.to_string_lossy().into_owned())
+    })
+}
+
+// Returns the name of a file or directory, resolving "." and ".."
+fn file_name(path: &Path) -> Option<String> {
+    path.canonicalize()
+        .ok()?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+// Returns the output for the first input: Xxx/Xxx.asm for a directory Xxx and Xxx.asm for
+// a file Xxx.vm. Without inputs, the files of the current directory are written to the
+// file's stem for a single file and to Main.asm otherwise.
+fn output_path<D: Deque<String>>(input: Option<&String>, file_paths: &D) -> String {
+    match input.map(Path::new) {
+        Some(path) if path.is_dir() => {
+            let stem = file_name(path).unwrap_or("Main".to_string());
+            path.join(format!("{}.asm", stem))
+                .to_string_lossy()
+                .into_owned()
+        }
+        Some(path) => path.with_extension("asm").to_string_lossy().into_owned(),
+        None if file_paths.len() == 1 => Path::new(file_paths.front().unwrap().as_str())
+            .with_extension("asm")
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned(),
+        None => "Main.asm".to_string(),
+    }
+}
+
+// Collects the .vm files of the inputs, which are files or directories, in order. Without
+// inputs, those of the current directory.
+pub fn get_source<D: Deque<String>>(
+    inputs: &[String],
+    ext: &str,
+    paths: D,
+) -> Result<Source<D>, String> {
+    let file_paths = if inputs.is_empty() {
+        get_file_paths(".", ext, paths)
+    } else {
+        inputs.iter().try_fold(paths, |paths, input| {
+            let path = Path::new(input);
+            if path.is_dir() {
+                Ok(get_file_paths(input, ext, paths))
+            } else if path.extension().and_then(|e| e.to_str()) == Some(ext) {
+                Ok(paths.push_back(input.clone()))
+            } else {
+                Err(format!("{}: not a .{} file or a directory", input, ext))
+            }
+        })?
+    };
+    if file_paths.is_empty() {
+        return Err(format!("no .{} files found", ext));
+    }
+    Ok(Source {
+        output: output_path(inputs.first(), &file_paths),
+        directory: inputs.is_empty() || inputs.iter().any(|input| Path::new(input).is_dir()),
+        file_paths,
+    })
+}
+
+pub struct Options {
+    pub inputs: Vec<String>,
+    // Whether to write the bootstrap code, or None to decide from the inputs
+    pub bootstrap: Option<bool>,
+    pub entry: Option<String>,
+    // Whether to jump to shared routines for calls, returns and comparisons
+    pub compact: bool,
+    // Whether to print the numbers of instructions of both translations
+    pub report: bool,
+    // The passes of the optimizer to run before translating
+    pub passes: Passes,
+    pub interpret: bool,
+    pub steps: u64,
+    pub sets: Vec<(usize, u16)>,
+    pub dumps: Vec<(usize, usize)>,
+}
+
+impl Options {
+    // Returns the function the bootstrap code calls, if any. Programs in a directory start
+    // with the entry function, single files at their first command, unless asked otherwise.
+    pub fn entry(&self, directory: bool) -> Option<&str> {
+        self.bootstrap
+            .unwrap_or(directory || self.entry.is_some())
+            .then(|| self.entry.as_deref().unwrap_or(ENTRY))
+    }
+}
+
+// Parses "N" or "N..M" (exclusive) into an address range
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once("..") {
+        Some((start, end)) => Some((start.parse().ok()?, end.parse().ok()?)),
+        None => range
+            .parse()
+            .ok()
+            .map(|address: usize| (address, address + 1)),
+    }
+    .filter(|&(_, end)| end <= RAM_SIZE)
+}
+
+// Parses "ADDRESS=VALUE", where the value may be negative
+fn parse_set(set: &str) -> Option<(usize, u16)> {
+    let (address, value) = set.split_once('=')?;
+    Some((address.parse().ok()?, value.parse::<i16>().ok()? as u16))
+}
+
+pub fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        inputs: Vec::new(),
+        bootstrap: None,
+        entry: None,
+        compact: false,
+        report: false,
+        passes: Passes::default(),
+        interpret: false,
+        steps: DEFAULT_STEPS,
+        sets: Vec::new(),
+        dumps: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--run" => options.interpret = true,
+            "--bootstrap" => options.bootstrap = Some(true),
+            "--no-bootstrap" => options.bootstrap = Some(false),
+            "--entry" => options.entry = Some(args.next()?.clone()),
+            "--compact" => options.compact = true,
+            "--report" => options.report = true,
+            "-O" => options.passes = Passes::all(),
+            "--passes" => options.passes = Passes::parse(args.next()?)?,
+            "--steps" => options.steps = args.next()?.parse().ok()?,
+            "--set" => options.sets.push(parse_set(args.next()?)?),
+            "--dump" => options.dumps.push(parse_range(args.next()?)?),
+            input if !input.starts_with("--") => options.inputs.push(input.to_string()),
+            _ => return None,
+        }
+    }
+    // The entry function is that of the bootstrap code, which the interpreter does not run,
+    // and it does not generate code either
+    let translates = options.bootstrap.is_some()
+        || options.entry.is_some()
+        || options.compact
+        || options.report
+        || options.passes.any();
+    if (options.entry.is_some() && options.bootstrap == Some(false))
+        || (options.interpret && translates)
+    {
+        return None;
+    }
+    Some(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collections::Empty;
+
+    fn options(args: &str) -> Option<Options> {
+        parse_options(
+            &args
+                .split_whitespace()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<String>>(),
+        )
+    }
+
+    fn source(inputs: &[String]) -> Result<Source<BankersDeque<String>>, String> {
+        get_source(inputs, "vm", BankersDeque::empty())
+    }
+
+    fn paths(source: &Source<BankersDeque<String>>) -> Vec<String> {
+        source
+            .file_paths
+            .iter()
+            .map(|path| path.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sort_the_files_of_directories() {
+        let dir = std::env::temp_dir().join("vm-source").join("Prog");
+        fs::create_dir_all(&dir).unwrap();
+        for file in ["Sys.vm", "Main.vm", "Array.vm", "Notes.txt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+
+        let directory = source(&[dir.to_string_lossy().into_owned()]).unwrap();
+        assert_eq!(
+            vec![path("Array.vm"), path("Main.vm"), path("Sys.vm")],
+            paths(&directory)
+        );
+        assert!(directory.directory);
+        assert_eq!(path("Prog.asm"), directory.output);
+
+        // Explicit files keep the order they were given in, and write beside the first
+        let files = source(&[path("Sys.vm"), path("Main.vm")]).unwrap();
+        assert_eq!(vec![path("Sys.vm"), path("Main.vm")], paths(&files));
+        assert!(!files.directory);
+        assert_eq!(path("Sys.asm"), files.output);
+
+        assert_eq!(
+            Err(format!(
+                "{}: not a .vm file or a directory",
+                path("Notes.txt")
+            )),
+            source(&[path("Main.vm"), path("Notes.txt")]).map(|source| source.output)
+        );
+    }
+
+    #[test]
+    fn choose_the_entry_function() {
+        let entry = |args: &str, directory: bool| {
+            options(args)
+                .unwrap()
+                .entry(directory)
+                .map(|entry| entry.to_string())
+        };
+        assert_eq!(Some("Sys.init".to_string()), entry("Prog", true));
+        assert_eq!(None, entry("Main.vm", false));
+        assert_eq!(None, entry("--no-bootstrap Prog", true));
+        assert_eq!(
+            Some("Sys.init".to_string()),
+            entry("--bootstrap Main.vm", false)
+        );
+        assert_eq!(
+            Some("Main.main".to_string()),
+            entry("--entry Main.main Main.vm", false)
+        );
+        assert_eq!(
+            Some("Main.main".to_string()),
+            entry("--entry Main.main --bootstrap Prog", true)
+        );
+    }
+
+    #[test]
+    fn reject_conflicting_options() {
+        assert!(options("--entry Main.main --no-bootstrap Prog").is_none());
+        assert!(options("--entry").is_none());
+        for translation in [
+            "--bootstrap",
+            "--entry Main.main",
+            "--compact",
+            "--report",
+            "-O",
+        ] {
+            assert!(options(&format!("--run {} Prog", translation)).is_none());
+        }
+        assert!(options("--passes fold,loops Prog").is_none());
+        let options = options("--run --steps 10 --set 0=-1 --dump 256..258 Main.vm").unwrap();
+        assert_eq!(
+            (10, vec![(0, 0xFFFF)], vec![(256, 258)]),
+            (options.steps, options.sets, options.dumps)
+        );
+    }
+}
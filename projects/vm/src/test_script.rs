@@ -0,0 +1,142 @@
+use crate::interpreter::*;
+use script::runner::Simulator;
+use script::script::Value;
+use std::fs;
+use std::path::Path;
+
+// Runs VM emulator scripts such as projects/08/FunctionCalls/NestedCall/NestedCallVME.tst.
+// "vmstep" executes one command.
+pub struct VmEmulator {
+    vm: Option<Vm>,
+}
+
+impl VmEmulator {
+    pub fn new() -> Self {
+        VmEmulator { vm: None }
+    }
+
+    fn vm(&self) -> Result<&Vm, String> {
+        self.vm.as_ref().ok_or("no program is loaded".to_string())
+    }
+}
+
+impl Default for VmEmulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Returns the RAM address of "RAM[n]", "sp", "local", ..., or of "local[n]", "argument[n]", ...
+fn address(vm: &Vm, name: &str) -> Option<usize> {
+    let (name, index) = match name.split_once('[') {
+        Some((name, index)) => (name, Some(index.strip_suffix(']')?.parse::<usize>().ok()?)),
+        None => (name, None),
+    };
+    let pointer = match name {
+        "sp" => SP,
+        "local" => LCL,
+        "argument" => ARG,
+        "this" => THIS,
+        "that" => THAT,
+        "RAM" => return index.filter(|&index| index < RAM_SIZE),
+        "temp" => return index.filter(|&index| index < 8).map(|index| TEMP + index),
+        _ => return None,
+    };
+    match index {
+        Some(index) => Some((vm.peek(pointer) as usize + index) % RAM_SIZE),
+        None => Some(pointer),
+    }
+}
+
+impl Simulator for VmEmulator {
+    fn load(&mut self, dir: &Path, path: Option<&str>) -> Result<(), String> {
+        let files = match path {
+            Some(path) => vec![dir.join(path)],
+            None => {
+                let entries =
+                    fs::read_dir(dir).map_err(|error| format!("{}: {}", dir.display(), error))?;
+                let mut files = entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "vm"))
+                    .collect::<Vec<_>>();
+                files.sort();
+                files
+            }
+        };
+        let sources = files
+            .iter()
+            .map(|file| {
+                let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+                fs::read_to_string(file)
+                    .map(|content| (stem.into_owned(), content))
+                    .map_err(|error| format!("{}: {}", file.display(), error))
+            })
+            .collect::<Result<Vec<(String, String)>, String>>()?;
+        let sources = sources
+            .iter()
+            .map(|(stem, content)| (stem.as_str(), content.as_str()))
+            .collect::<Vec<(&str, &str)>>();
+        let vm = Vm::new(&sources).map_err(|errors| {
+            errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<String>>()
+                .join("\n")
+        })?;
+        self.vm = Some(vm);
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: i64) -> Result<(), String> {
+        let vm = self.vm.as_mut().ok_or("no program is loaded")?;
+        let address = address(vm, name).ok_or(format!("unknown variable `{}`", name))?;
+        vm.poke(address, value as u16);
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Value, String> {
+        let vm = self.vm()?;
+        let address = address(vm, name).ok_or(format!("unknown variable `{}`", name))?;
+        Ok(Value::Number(vm.peek(address) as i16 as i64))
+    }
+
+    fn action(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        match (name, args) {
+            ("vmstep", []) => {
+                let vm = self.vm.as_mut().ok_or("no program is loaded")?;
+                vm.step().map(|_| ()).map_err(|error| error.to_string())
+            }
+            _ => Err(format!("unknown command `{}`", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use script::runner::run;
+    use script::script::parse_script;
+
+    #[test]
+    fn run_vm_emulator_scripts() {
+        let projects = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        for test in [
+            "07/StackArithmetic/SimpleAdd/SimpleAddVME.tst",
+            "07/StackArithmetic/StackTest/StackTestVME.tst",
+            "07/MemoryAccess/BasicTest/BasicTestVME.tst",
+            "07/MemoryAccess/PointerTest/PointerTestVME.tst",
+            "07/MemoryAccess/StaticTest/StaticTestVME.tst",
+            "08/ProgramFlow/BasicLoop/BasicLoopVME.tst",
+            "08/ProgramFlow/FibonacciSeries/FibonacciSeriesVME.tst",
+            "08/FunctionCalls/SimpleFunction/SimpleFunctionVME.tst",
+            "08/FunctionCalls/NestedCall/NestedCallVME.tst",
+            "08/FunctionCalls/FibonacciElement/FibonacciElementVME.tst",
+            "08/FunctionCalls/StaticsTest/StaticsTestVME.tst",
+        ] {
+            let file = projects.join(test);
+            let script = parse_script(&fs::read_to_string(&file).unwrap()).unwrap();
+            let outcome = run(&script, file.parent().unwrap(), &mut VmEmulator::new()).unwrap();
+            assert_eq!(None, outcome.mismatch, "{}", test);
+        }
+    }
+}
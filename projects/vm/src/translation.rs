@@ -1,32 +1,64 @@
+use crate::codegen::{CodeGen, TranslatorState};
+use crate::command::{ArithOp, Segment, MAX_INDEX};
+
 // Push a segment
 
-// For "local", "argument", "this" and "that"
-pub const SEGMENT: &str = r#"@{segment}
+// For "local", "argument", "this" and "that", whose base address is in `pointer`
+fn segment(pointer: &str, index: u16) -> String {
+    format!(
+        r#"@{pointer}
 D=M
 @{index}
 A=D+A
-D=M"#;
+D=M"#
+    )
+}
 
 // For "temp"
-pub const TEMP: &str = r#"@5
+fn temp(index: u16) -> String {
+    format!(
+        r#"@5
 D=A
 @{index}
 A=D+A
-D=M"#;
+D=M"#
+    )
+}
 
-// For "pointer"
-pub const POINTER: &str = r#"@{segment}
-A=M
-D=A"#;
+// For "pointer", which is `pointer` itself
+fn pointer(pointer: &str) -> String {
+    format!(
+        r#"@{pointer}
+A=M
+D=A"#
+    )
+}
 
 // For "static"
-pub const STATIC: &str = r#"@{file}.{index}
+fn r#static(file: &str, index: u16) -> String {
+    format!(
+        r#"@{file}.{index}
 A=M
-D=A"#;
+D=A"#
+    )
+}
 
 // For "constant"
-pub const CONSTANT: &str = r#"@{index}
-D=A"#;
+fn constant(index: u16) -> String {
+    format!(
+        r#"@{index}
+D=A"#
+    )
+}
+
+// For the constants above MAX_INDEX the optimizer pushes, from their complement
+fn negative_constant(value: u16) -> String {
+    format!(
+        r#"@{}
+D=!A"#,
+        !value
+    )
+}
 
 // Push to the stack
 pub const POST_PUSH: &str = r#"@SP
@@ -37,25 +69,41 @@ M=M+1"#;
 
 // Pop a segment
 
-// Calculate adress of "segment" "index"
-pub const SEGMENT_ADDRESS: &str = r#"@{segment}
+// Calculate address of "segment" "index"
+fn segment_address(pointer: &str, index: u16) -> String {
+    format!(
+        r#"@{pointer}
 D=M
 @{index}
-D=D+A"#;
+D=D+A"#
+    )
+}
 
 // Calculate address of temp "index"
-pub const TEMP_ADDRESS: &str = r#"@5
+fn temp_address(index: u16) -> String {
+    format!(
+        r#"@5
 D=A
 @{index}
-D=D+A"#;
+D=D+A"#
+    )
+}
 
 // Calculate address of pointer "index"
-pub const POINTER_ADDRESS: &str = r#"@{segment}
-D=A"#;
+fn pointer_address(pointer: &str) -> String {
+    format!(
+        r#"@{pointer}
+D=A"#
+    )
+}
 
 // Calculate address of static "index"
-pub const STAIC_ADDRESS: &str = r#"@{file}.{index}
-D=A"#;
+fn static_address(file: &str, index: u16) -> String {
+    format!(
+        r#"@{file}.{index}
+D=A"#
+    )
+}
 
 // Calculate address of "index" of "segment"
 pub const PRE_POP: &str = r#"@SP
@@ -87,7 +135,9 @@ M=D"#;
 // Arithmetic and logic instructions
 
 // For "add", "sub", "and" and "or"
-pub const BINARY_COMP: &str = r#"@SP
+fn binary_comp(comp: &str) -> String {
+    format!(
+        r#"@SP
 M=M-1
 A=M
 D=M
@@ -99,10 +149,14 @@ A=M
 A=M
 M=D
 @SP
-M=M+1"#;
+M=M+1"#
+    )
+}
 
 // For "not", "neg"
-pub const UNARY_COMP: &str = r#"@SP
+fn unary_comp(comp: &str) -> String {
+    format!(
+        r#"@SP
 M=M-1
 A=M
 D=M
@@ -111,10 +165,14 @@ D=M
 A=M
 M=D
 @SP
-M=M+1"#;
+M=M+1"#
+    )
+}
 
 // For "eq", "lt" and "gt"
-pub const COMPARISON: &str = r#"@SP
+fn comparison(label: &str, jump: &str) -> String {
+    format!(
+        r#"@SP
 M=M-1
 A=M
 D=M
@@ -132,20 +190,51 @@ A=M
 M=!M
 ({label})
 @SP
-M=M+1"#;
+M=M+1"#
+    )
+}
 
 // For "if-goto"
-pub const IF_GOTO: &str = r#"@SP
+fn if_goto(dont_goto: &str, label: &str) -> String {
+    format!(
+        r#"@SP
 M=M-1
 A=M
 D=M
-@{dontgoto}
+@{dont_goto}
 D;JEQ
 @{label}
 0;JMP
-({dontgoto})"#;
+({dont_goto})"#
+    )
+}
+
+// For "eq", "gt" or "lt" followed by "if-goto", jumping on y - x as "comparison" does
+fn if_compare(label: &str, jump: &str) -> String {
+    format!(
+        r#"@SP
+AM=M-1
+D=M
+@SP
+AM=M-1
+D=D-M
+@{label}
+D;{jump}"#
+    )
+}
+
+// For "not" followed by "if-goto", jumping unless the value plus one is 0
+fn if_not(label: &str) -> String {
+    format!(
+        r#"@SP
+AM=M-1
+D=M+1
+@{label}
+D;JNE"#
+    )
+}
 
-// For "function"
+// For "function", once per local
 pub const FUNCTION: &str = r#"@0
 D=A
 @SP
@@ -155,8 +244,10 @@ M=D
 M=M+1"#;
 
 // For "call"
-pub const CALL: &str = r#"// save return address to stack
-@{caller}$ret.{callee_index}
+fn call(return_label: &str, arguments: u16, function: &str) -> String {
+    format!(
+        r#"// save return address to stack
+@{return_label}
 D=A
 @SP
 A=M
@@ -199,7 +290,7 @@ M=M+1
 D=M
 @5
 D=D-A
-@{nargs}
+@{arguments}
 D=D-A
 @ARG
 M=D
@@ -208,10 +299,59 @@ M=D
 D=M
 @LCL
 M=D
-// jump to {callee}
-@{callee}
+// jump to {function}
+@{function}
 0;JMP
-({caller}$ret.{callee_index})"#;
+({return_label})"#
+    )
+}
+
+// For a tail call, copying argument `index` of `arguments` from the top of the stack
+// over the arguments of the calling function. ARG + index is reached by incrementing A,
+// or through R13 for the last arguments of a long list.
+fn tail_argument(index: u16, arguments: u16) -> String {
+    let load = format!(
+        r#"@SP
+D=M
+@{}
+A=D-A
+D=M"#,
+        arguments - index
+    );
+    let store = if index < 8 {
+        format!("@ARG\nA=M{}\nM=D", "\nA=A+1".repeat(index as usize))
+    } else {
+        format!(
+            r#"@R13
+M=D
+@ARG
+D=M
+@{index}
+D=D+A
+@R14
+M=D
+@R13
+D=M
+@R14
+A=M
+M=D"#
+        )
+    };
+    format!("{}\n{}", load, store)
+}
+
+// For a tail call, after its arguments: drops the frame of the calling function, whose
+// saved return address and segments are those of the called one
+fn tail_jump(function: &str) -> String {
+    format!(
+        r#"@LCL
+D=M
+@SP
+M=D
+@{function}
+0;JMP"#
+    )
+}
 
 // For "return"
 pub const RETURN: &str = r#"// endFrame (R13) = LCL
@@ -275,3 +415,276 @@ pub const BOOTSTRAP: &str = r#"@256
 D=A
 @SP
 M=D"#;
+
+// Returns the symbol holding the base address of a segment, or the symbol of the
+// pointer itself for "pointer"
+fn base(segment: Segment, index: u16) -> &'static str {
+    match (segment, index) {
+        (Segment::Local, _) => "LCL",
+        (Segment::Argument, _) => "ARG",
+        (Segment::This, _) | (Segment::Pointer, 0) => "THIS",
+        _ => "THAT",
+    }
+}
+
+// Generates Hack assembly from the templates above, each command preceded by a comment
+#[derive(Debug, Clone, Default)]
+pub struct Hack {
+    pub assembly: Vec<String>,
+}
+
+impl Hack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn emit(&mut self, comment: String, code: &[&str]) {
+        self.assembly.push(format!("// {}", comment));
+        self.assembly
+            .extend(code.iter().map(|code| code.to_string()));
+    }
+
+    // Returns the assembly generated so far, one instruction per line
+    pub fn text(&self) -> String {
+        self.assembly.join("\n") + "\n"
+    }
+
+    // Returns the number of instructions generated so far, i.e. of words of ROM
+    pub fn instructions(&self) -> usize {
+        self.assembly
+            .iter()
+            .flat_map(|code| code.lines())
+            .filter(|line| !line.is_empty() && !line.starts_with("//") && !line.starts_with('('))
+            .count()
+    }
+}
+
+impl CodeGen for Hack {
+    fn bootstrap(&mut self, state: &mut TranslatorState, entry: &str) {
+        self.emit("bootstrap".to_string(), &[BOOTSTRAP]);
+        self.call(state, entry, 0);
+    }
+
+    fn push(&mut self, state: &mut TranslatorState, segment: Segment, index: u16) {
+        let code = match segment {
+            Segment::Local | Segment::Argument | Segment::This | Segment::That => {
+                self::segment(base(segment, index), index)
+            }
+            Segment::Temp => temp(index),
+            Segment::Pointer => pointer(base(segment, index)),
+            Segment::Static => r#static(&state.file, index),
+            Segment::Constant if index > MAX_INDEX => negative_constant(index),
+            Segment::Constant => constant(index),
+        };
+        self.emit(format!("push {} {}", segment, index), &[&code, POST_PUSH]);
+    }
+
+    fn pop(&mut self, state: &mut TranslatorState, segment: Segment, index: u16) {
+        let code = match segment {
+            Segment::Temp => temp_address(index),
+            Segment::Pointer => pointer_address(base(segment, index)),
+            Segment::Static => static_address(&state.file, index),
+            _ => segment_address(base(segment, index), index),
+        };
+        self.emit(
+            format!("pop {} {}", segment, index),
+            &[PRE_POP, &code, POST_POP],
+        );
+    }
+
+    fn arithmetic(&mut self, state: &mut TranslatorState, op: ArithOp) {
+        let code = match op {
+            ArithOp::Add => binary_comp("D=D+M"),
+            ArithOp::Sub => binary_comp("D=M-D"),
+            ArithOp::And => binary_comp("D=D&M"),
+            ArithOp::Or => binary_comp("D=D|M"),
+            ArithOp::Neg => unary_comp("D=-D"),
+            ArithOp::Not => unary_comp("D=!D"),
+            ArithOp::Eq => {
+                state.eq += 1;
+                comparison(&format!("EQUAL.{}", state.eq - 1), "JEQ")
+            }
+            ArithOp::Gt => {
+                state.gt += 1;
+                comparison(&format!("GREATERTHAN.{}", state.gt - 1), "JLT")
+            }
+            ArithOp::Lt => {
+                state.lt += 1;
+                comparison(&format!("LESSTHAN.{}", state.lt - 1), "JGT")
+            }
+        };
+        self.emit(op.to_string(), &[&code]);
+    }
+
+    fn label(&mut self, state: &mut TranslatorState, label: &str) {
+        self.emit(
+            format!("label {}", label),
+            &[&format!("({})", state.scoped(label))],
+        );
+    }
+
+    fn goto(&mut self, state: &mut TranslatorState, label: &str) {
+        self.emit(
+            format!("goto {}", label),
+            &[&format!("@{}", state.scoped(label)), "0;JMP"],
+        );
+    }
+
+    fn if_goto(&mut self, state: &mut TranslatorState, label: &str) {
+        let dont_goto = match &state.function {
+            Some(function) => format!("{}.DONTGOTO.{}", function, state.branches),
+            None => format!("DONTGOTO.{}", state.branches),
+        };
+        state.branches += 1;
+        self.emit(
+            format!("if-goto {}", label),
+            &[&if_goto(&dont_goto, &state.scoped(label))],
+        );
+    }
+
+    fn function(&mut self, _: &mut TranslatorState, name: &str, locals: u16) {
+        let code = [format!("({})", name)]
+            .into_iter()
+            .chain((0..locals).map(|_| FUNCTION.to_string()))
+            .collect::<Vec<String>>();
+        self.emit(
+            format!("function {} {}", name, locals),
+            &code.iter().map(String::as_str).collect::<Vec<&str>>(),
+        );
+    }
+
+    fn call(&mut self, state: &mut TranslatorState, function: &str, arguments: u16) {
+        let return_label = state.scoped(&format!("ret.{}", state.calls));
+        state.calls += 1;
+        self.emit(
+            format!("call {} {}", function, arguments),
+            &[&call(&return_label, arguments, function)],
+        );
+    }
+
+    fn r#return(&mut self, _: &mut TranslatorState) {
+        self.emit("return".to_string(), &[RETURN]);
+    }
+
+    fn if_compare(&mut self, state: &mut TranslatorState, op: ArithOp, negated: bool, label: &str) {
+        let jump = match (op, negated) {
+            (ArithOp::Eq, false) => "JEQ",
+            (ArithOp::Eq, true) => "JNE",
+            (ArithOp::Gt, false) => "JLT",
+            (ArithOp::Gt, true) => "JGE",
+            (ArithOp::Lt, false) => "JGT",
+            _ => "JLE",
+        };
+        self.emit(
+            format!(
+                "{}; {}if-goto {}",
+                op,
+                if negated { "not; " } else { "" },
+                label
+            ),
+            &[&if_compare(&state.scoped(label), jump)],
+        );
+    }
+
+    fn if_not(&mut self, state: &mut TranslatorState, label: &str) {
+        self.emit(
+            format!("not; if-goto {}", label),
+            &[&if_not(&state.scoped(label))],
+        );
+    }
+
+    fn tail_call(&mut self, _: &mut TranslatorState, function: &str, arguments: u16) {
+        let code = (0..arguments)
+            .map(|index| tail_argument(index, arguments))
+            .chain([tail_jump(function)])
+            .collect::<Vec<String>>();
+        self.emit(
+            format!("call {} {}; return", function, arguments),
+            &code.iter().map(String::as_str).collect::<Vec<&str>>(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::translate;
+    use crate::error::Cause;
+
+    #[test]
+    fn generate_unique_labels() {
+        let mut hack = Hack::new();
+        let mut state = TranslatorState::new();
+        let main = [
+            "function Main.main 0",
+            "eq",
+            "if-goto END",
+            "return",
+            "goto END",
+            "label END",
+        ];
+        translate(&mut hack, &mut state, "Main", &main).unwrap();
+        translate(
+            &mut hack,
+            &mut state,
+            "Sys",
+            &["eq", "if-goto END", "label END"],
+        )
+        .unwrap();
+        assert_eq!(vec!["Main.main".to_string()], state.functions);
+        let text = hack.text();
+        let labels = text
+            .lines()
+            .filter(|line| line.starts_with('('))
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            vec![
+                "(Main.main)",
+                "(EQUAL.0)",
+                "(Main.main.DONTGOTO.0)",
+                "(Main.main$END)",
+                "(EQUAL.1)",
+                "(DONTGOTO.1)",
+                "(END)",
+            ],
+            labels
+        );
+    }
+
+    #[test]
+    fn report_invalid_lines() {
+        let mut state = TranslatorState::new();
+        let lines = [
+            "push constant 1",
+            "call Main.main 0",
+            "pop constant 0",
+            "function Main.main 0",
+            "push pointer 2",
+            "return",
+        ];
+        let errors = translate(&mut Hack::new(), &mut state, "Main", &lines).unwrap_err();
+        assert_eq!(
+            vec![
+                (2, Cause::CallOutsideFunction),
+                (3, Cause::PopConstant),
+                (
+                    5,
+                    Cause::IndexOutOfRange {
+                        segment: Segment::Pointer,
+                        index: "2".to_string()
+                    }
+                ),
+            ],
+            errors
+                .iter()
+                .map(|error| (error.line, error.cause.clone()))
+                .collect::<Vec<(usize, Cause)>>()
+        );
+        assert_eq!(
+            "Main.vm:5: error: index `2` out of range for segment `pointer` (0..=1)\n  push pointer 2",
+            errors[2].to_string()
+        );
+        let errors = translate(&mut Hack::new(), &mut state, "Sys", &["return"]).unwrap_err();
+        assert_eq!(Cause::ReturnOutsideFunction, errors[0].cause);
+    }
+}
@@ -0,0 +1,193 @@
+use emulator::cpu::Cpu;
+use emulator::rom;
+use script::runner::{self, Simulator};
+use script::script::{parse_script, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use vm::codegen::{self, CodeGen, TranslatorState};
+use vm::command::Command;
+use vm::compact::Compact;
+use vm::optimizer::{optimize, Passes, PASSES};
+use vm::translation::Hack;
+
+// The directory of a project of the course, e.g. "07"
+fn project(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(name)
+}
+
+// The programs of projects/07 and 08 with a CPU emulator test script, sorted by path
+fn programs() -> Vec<PathBuf> {
+    let mut programs = ["07", "08"]
+        .iter()
+        .flat_map(|name| fs::read_dir(project(name)).unwrap())
+        .flat_map(|dir| fs::read_dir(dir.unwrap().path()).unwrap())
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.join(format!("{}.tst", stem(path))).is_file())
+        .collect::<Vec<PathBuf>>();
+    programs.sort();
+    assert_eq!(11, programs.len());
+    programs
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
+
+// Parses the files given as (file stem, content) into (file stem, commands)
+fn parse(files: &[(String, String)]) -> Vec<(String, Vec<Command>)> {
+    files
+        .iter()
+        .map(|(stem, content)| {
+            let lines = content.lines().collect::<Vec<&str>>();
+            let commands = codegen::parse(stem, &lines)
+                .unwrap_or_else(|errors| panic!("{}: {:?}", stem, errors));
+            (stem.clone(), commands)
+        })
+        .collect()
+}
+
+// Reads the .vm files of a program directory, sorted by name
+fn read(dir: &Path) -> Vec<(String, String)> {
+    let mut paths = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "vm"))
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| (stem(path), fs::read_to_string(path).unwrap()))
+        .collect()
+}
+
+// Optimizes and assembles a program, bootstrapped to call `entry` if any
+fn assemble(
+    files: &[(String, String)],
+    entry: Option<&str>,
+    passes: Passes,
+    compact: bool,
+) -> Vec<u16> {
+    let mut program = parse(files);
+    optimize(&mut program, entry, passes);
+    let generate = |codegen: &mut dyn CodeGen| {
+        let mut state = TranslatorState::new();
+        if let Some(entry) = entry {
+            codegen.bootstrap(&mut state, entry);
+        }
+        for (stem, commands) in &program {
+            codegen::generate_file(codegen, &mut state, stem, commands);
+        }
+        codegen.finish(&mut state);
+    };
+    let assembly = if compact {
+        let mut compact = Compact::new();
+        generate(&mut compact);
+        compact.text()
+    } else {
+        let mut hack = Hack::new();
+        generate(&mut hack);
+        hack.text()
+    };
+    rom::assemble("Test.asm", &assembly).unwrap()
+}
+
+// A CPU that runs the given code whatever program the script loads
+struct Translated {
+    cpu: Cpu,
+    code: Vec<u16>,
+}
+
+impl Simulator for Translated {
+    fn load(&mut self, _: &Path, _: Option<&str>) -> Result<(), String> {
+        self.cpu = Cpu::new(&self.code);
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: i64) -> Result<(), String> {
+        self.cpu.set(name, value)
+    }
+
+    fn get(&self, name: &str) -> Result<Value, String> {
+        self.cpu.get(name)
+    }
+
+    fn action(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        self.cpu.action(name, args)
+    }
+}
+
+#[test]
+fn optimized_programs_pass_their_tests() {
+    let singles = PASSES.map(|pass| Passes::parse(pass).unwrap());
+    let configurations = [(Passes::default(), false)]
+        .into_iter()
+        .chain(singles.into_iter().map(|passes| (passes, false)))
+        .chain([
+            (Passes::default(), true),
+            (Passes::all(), false),
+            (Passes::all(), true),
+        ])
+        .collect::<Vec<(Passes, bool)>>();
+    for dir in programs() {
+        let files = read(&dir);
+        // As the translator does for a directory with Sys.vm, and for a single file
+        let entry = files
+            .iter()
+            .any(|(stem, _)| stem == "Sys")
+            .then_some("Sys.init");
+        let script = fs::read_to_string(dir.join(format!("{}.tst", stem(&dir)))).unwrap();
+        let script = parse_script(&script).unwrap();
+        for &(passes, compact) in &configurations {
+            let code = assemble(&files, entry, passes, compact);
+            let mut translated = Translated {
+                cpu: Cpu::new(&code),
+                code,
+            };
+            let outcome = runner::run(&script, &dir, &mut translated).unwrap();
+            assert_eq!(
+                None,
+                outcome.mismatch,
+                "{} ({:?}, compact: {})",
+                stem(&dir),
+                passes,
+                compact
+            );
+        }
+    }
+}
+
+#[test]
+fn tail_calls_run_in_constant_stack() {
+    let main = "function Main.sum 0\n\
+                push argument 0\npush constant 0\neq\nif-goto DONE\n\
+                push argument 0\npush constant 1\nsub\n\
+                push argument 1\npush argument 0\nadd\n\
+                call Main.sum 2\nreturn\n\
+                label DONE\npush argument 1\nreturn";
+    let sys = "function Sys.init 0\npush constant 1000\npush constant 0\ncall Main.sum 2\n\
+               pop static 0\nlabel END\ngoto END";
+    let files = [
+        ("Main".to_string(), main.to_string()),
+        ("Sys".to_string(), sys.to_string()),
+    ];
+    // Without tail calls, each of the 1000 nested calls keeps its frame on the stack
+    for (passes, deep) in [("fold", true), ("fold,tail-calls", false)] {
+        let passes = Passes::parse(passes).unwrap();
+        let mut cpu = Cpu::new(&assemble(&files, Some("Sys.init"), passes, false));
+        let mut stack = 0;
+        while !cpu.is_halted() && cpu.cycles < 1_000_000 {
+            cpu.step();
+            stack = stack.max(cpu.peek(0));
+        }
+        assert!(cpu.is_halted());
+        // 1 + 2 + ... + 1000, modulo 2^16
+        assert_eq!(500500 % 65536, cpu.peek(16) as u32);
+        assert_eq!(
+            deep,
+            stack > 1000,
+            "{:?}: the stack went up to {}",
+            passes,
+            stack
+        );
+    }
+}